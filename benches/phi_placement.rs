@@ -0,0 +1,105 @@
+use std::time::{Duration, Instant};
+
+use bril_rs::Function;
+use ssa::cfg::{Cfg, CfgBuilder};
+use ssa::programs::nested_loops;
+use ssa::utils::read_program;
+
+// φ-placement from dominance frontiers against DJ-graph queries.
+//
+// Runs on every function of the programs (JSON) given after `--`, or on loop nests of a few depths:
+// the worst case for the size of the dominance frontiers. Each function gets its φ-functions placed
+// REPEAT times both ways, once from the materialized dominance frontiers (computing them included)
+// and once from DJ-graph queries (building the DJ-graph included). Dominators are shared, they are
+// needed either way.
+//
+//     cargo bench --bench phi_placement [-- FILE...]
+
+const DEPTHS: &[usize] = &[10, 50, 100, 200];
+const REPEAT: u32 = 10;
+
+fn main() {
+    // cargo bench passes --bench
+    let files: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+    let functions: Vec<Function> = if files.is_empty() {
+        DEPTHS
+            .iter()
+            .map(|&depth| {
+                let mut main = nested_loops(depth).functions.remove(0);
+                main.name = format!("nest{depth}");
+                main
+            })
+            .collect()
+    } else {
+        files
+            .iter()
+            .flat_map(|file| match read_program(Some(file)) {
+                Ok(program) => program.functions,
+                Err(err) => panic!("{err}"),
+            })
+            .collect()
+    };
+
+    println!(
+        "{:<24} {:>8} {:>8} {:>14} {:>14}",
+        "function", "blocks", "φs", "frontiers", "dj-graph"
+    );
+    let mut total_df = Duration::ZERO;
+    let mut total_dj = Duration::ZERO;
+    for func in &functions {
+        let mut cfg = CfgBuilder::new().build(func);
+        cfg.remove_unreachable();
+        let idoms = cfg.get_idoms();
+
+        let mut df_time = Duration::ZERO;
+        let mut dj_time = Duration::ZERO;
+        let (mut with_df, mut with_dj) = (cfg.clone(), cfg.clone());
+        for _ in 0..REPEAT {
+            with_df = cfg.clone();
+            let start = Instant::now();
+            let df = with_df.get_dominance_frontiers(&idoms);
+            with_df.insert_phi_nodes(&df);
+            df_time += start.elapsed();
+
+            with_dj = cfg.clone();
+            let start = Instant::now();
+            let dj = with_dj.dj_graph(&idoms);
+            with_dj.insert_phi_nodes_dj(&dj);
+            dj_time += start.elapsed();
+        }
+        df_time /= REPEAT;
+        dj_time /= REPEAT;
+
+        let phis = count_phis(&with_dj);
+        assert_eq!(
+            phis,
+            count_phis(&with_df),
+            "{}: the two placements differ",
+            func.name
+        );
+        println!(
+            "{:<24} {:>8} {:>8} {:>14?} {:>14?}",
+            func.name,
+            with_dj.layout.len(),
+            phis,
+            df_time,
+            dj_time
+        );
+        total_df += df_time;
+        total_dj += dj_time;
+    }
+    println!(
+        "{:<24} {:>8} {:>8} {:>14?} {:>14?}",
+        "total", "", "", total_df, total_dj
+    );
+}
+
+fn count_phis(cfg: &Cfg) -> usize {
+    cfg.insts
+        .iter()
+        .filter(|(_, data)| data.inst.is_phi())
+        .count()
+}
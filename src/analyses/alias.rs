@@ -0,0 +1,247 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use bril_rs::{EffectOps, Type, ValueOps};
+
+use crate::cfg::Cfg;
+use crate::inst::{InstId, Op};
+use crate::value::ValueId;
+
+// Inclusion based points-to analysis, flow and context insensitive
+// "Program Analysis and Specialization for the C Programming Language", Andersen, 1994
+//
+// The objects pointers point to are the allocation sites, with every element of an allocation and every time
+// it runs merged into one. Memory the function didn't allocate itself, that it reaches through its arguments
+// or gets from calls, is a single unknown object. Allocations whose address is passed to a call or returned,
+// or stored in memory that is reachable from outside, escape: they may be pointed to by anything pointing to the
+// unknown object.
+//
+// Must-aliasing comes from following `id` and `ptradd` with a constant offset back to a common base,
+// which only means anything in SSA form, where a name has a single value.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Location {
+    Alloc(InstId),
+    Unknown,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AliasAnalysis {
+    points_to: HashMap<ValueId, BTreeSet<Location>>,
+    escaped: HashSet<InstId>,
+    // Pointer -> the pointer it's derived from by constant offsets, and the sum of those offsets
+    base: HashMap<ValueId, (ValueId, i64)>,
+}
+
+impl AliasAnalysis {
+    pub fn points_to(&self, pointer: ValueId) -> Option<&BTreeSet<Location>> {
+        self.points_to.get(&pointer)
+    }
+
+    pub fn may_alias(&self, a: ValueId, b: ValueId) -> bool {
+        if a == b {
+            return true;
+        }
+        if let (Some(base_a), Some(base_b)) = (self.base.get(&a), self.base.get(&b))
+            && base_a.0 == base_b.0
+        {
+            return base_a.1 == base_b.1;
+        }
+        let (Some(a), Some(b)) = (self.points_to(a), self.points_to(b)) else {
+            // Not a pointer this analysis saw the definition of
+            return true;
+        };
+        a.iter()
+            .any(|&x| b.iter().any(|&y| self.locations_overlap(x, y)))
+    }
+
+    // Both always hold the same address
+    pub fn must_alias(&self, a: ValueId, b: ValueId) -> bool {
+        a == b
+            || matches!(
+                (self.base.get(&a), self.base.get(&b)),
+                (Some(base_a), Some(base_b)) if base_a == base_b
+            )
+    }
+
+    // Whether the memory behind the pointer can be reached from outside the function,
+    // by callees or by the caller once the function returns
+    pub fn may_escape(&self, pointer: ValueId) -> bool {
+        self.points_to(pointer).is_none_or(|locations| {
+            locations.iter().any(|location| match location {
+                Location::Unknown => true,
+                Location::Alloc(site) => self.escaped.contains(site),
+            })
+        })
+    }
+
+    fn locations_overlap(&self, a: Location, b: Location) -> bool {
+        match (a, b) {
+            (Location::Unknown, Location::Unknown) => true,
+            (Location::Alloc(site), Location::Unknown)
+            | (Location::Unknown, Location::Alloc(site)) => self.escaped.contains(&site),
+            (Location::Alloc(a), Location::Alloc(b)) => a == b,
+        }
+    }
+}
+
+fn is_pointer(ty: Option<&Type>) -> bool {
+    matches!(ty, Some(Type::Pointer(_)))
+}
+
+impl Cfg {
+    pub fn alias_analysis(&self) -> AliasAnalysis {
+        let mut analysis = AliasAnalysis::default();
+        let pointer = |value: ValueId| is_pointer(self.values.ty(value));
+
+        // What the objects hold, for the pointers stored in them
+        let mut contents: HashMap<Location, BTreeSet<Location>> = HashMap::new();
+        contents.insert(Location::Unknown, BTreeSet::from([Location::Unknown]));
+        for &arg in &self.args {
+            if pointer(arg) {
+                analysis
+                    .points_to
+                    .insert(arg, BTreeSet::from([Location::Unknown]));
+            }
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (id, data) in self.insts.iter() {
+                let inst = &data.inst;
+                // The locations flowing into the destination, and into the objects stored to
+                let mut into_dest = BTreeSet::new();
+                let mut stored: Vec<(Location, BTreeSet<Location>)> = Vec::new();
+                let pts =
+                    |value: &ValueId| analysis.points_to.get(value).cloned().unwrap_or_default();
+                match &inst.op {
+                    Op::Value(ValueOps::Alloc) => {
+                        into_dest.insert(Location::Alloc(id));
+                    }
+                    Op::Value(ValueOps::Id | ValueOps::Phi) => {
+                        for arg in &inst.args {
+                            into_dest.extend(pts(arg));
+                        }
+                    }
+                    Op::Value(ValueOps::PtrAdd) => into_dest.extend(pts(&inst.args[0])),
+                    Op::Value(ValueOps::Load) => {
+                        for location in pts(&inst.args[0]) {
+                            into_dest.extend(contents.get(&location).into_iter().flatten());
+                        }
+                    }
+                    Op::Effect(EffectOps::Store) if pointer(inst.args[1]) => {
+                        let value = pts(&inst.args[1]);
+                        for location in pts(&inst.args[0]) {
+                            stored.push((location, value.clone()));
+                        }
+                    }
+                    Op::Value(ValueOps::Call) | Op::Effect(EffectOps::Call) => {
+                        into_dest.insert(Location::Unknown);
+                        for arg in inst.args.iter().filter(|&&arg| pointer(arg)) {
+                            stored.push((Location::Unknown, pts(arg)));
+                        }
+                    }
+                    // The caller gets to see what is returned, as if it was stored in the unknown object
+                    Op::Effect(EffectOps::Return) => {
+                        for arg in inst.args.iter().filter(|&&arg| pointer(arg)) {
+                            stored.push((Location::Unknown, pts(arg)));
+                        }
+                    }
+                    _ => {}
+                }
+
+                if let Some(dest) = inst.dest
+                    && pointer(dest)
+                {
+                    let set = analysis.points_to.entry(dest).or_default();
+                    let before = set.len();
+                    set.extend(into_dest);
+                    changed |= set.len() != before;
+                }
+                for (location, value) in stored {
+                    let set = contents.entry(location).or_default();
+                    let before = set.len();
+                    set.extend(value);
+                    changed |= set.len() != before;
+                }
+            }
+
+            // Whatever is reachable from the unknown object escapes, and anything can be stored in it
+            let mut stack: Vec<Location> = vec![Location::Unknown];
+            let mut reachable = HashSet::new();
+            while let Some(location) = stack.pop() {
+                if reachable.insert(location) {
+                    stack.extend(contents.get(&location).into_iter().flatten());
+                }
+            }
+            for location in reachable {
+                if let Location::Alloc(site) = location
+                    && analysis.escaped.insert(site)
+                {
+                    contents
+                        .entry(location)
+                        .or_default()
+                        .insert(Location::Unknown);
+                    changed = true;
+                }
+            }
+        }
+
+        if self.in_ssa {
+            analysis.base = self.pointer_bases();
+        }
+        analysis
+    }
+
+    // Every pointer defined by `id` or `ptradd` with a constant offset, with the pointer at the root
+    // of such a chain and the total offset from it. Roots are their own base.
+    fn pointer_bases(&self) -> HashMap<ValueId, (ValueId, i64)> {
+        let mut bases: HashMap<ValueId, (ValueId, i64)> = HashMap::new();
+        let pointers: Vec<ValueId> = self
+            .insts
+            .iter()
+            .filter_map(|(_, data)| data.inst.dest)
+            .chain(self.args.iter().copied())
+            .filter(|&value| is_pointer(self.values.ty(value)))
+            .collect();
+
+        for pointer in pointers {
+            // Walk up the chain, then fill in everything on it. Cycles only go through φ-functions,
+            // which end the chain.
+            let mut chain = Vec::new();
+            let mut current = pointer;
+            let (root, mut offset) = loop {
+                if let Some(&known) = bases.get(&current) {
+                    break known;
+                }
+                let step = self.insts.def(current).and_then(|def| {
+                    let inst = &self.insts[def];
+                    match inst.op {
+                        Op::Value(ValueOps::Id) => Some((inst.args[0], 0)),
+                        Op::Value(ValueOps::PtrAdd) => {
+                            Some((inst.args[0], self.const_int(inst.args[1])?))
+                        }
+                        _ => None,
+                    }
+                });
+                match step {
+                    Some((from, by)) => {
+                        chain.push((current, by));
+                        current = from;
+                    }
+                    None => break (current, 0),
+                }
+            };
+            bases.entry(current).or_insert((root, offset));
+            for (value, by) in chain.into_iter().rev() {
+                // Past an offset that overflows, the pointers on the chain have no base
+                let Some(sum) = offset.checked_add(by) else {
+                    break;
+                };
+                offset = sum;
+                bases.insert(value, (root, offset));
+            }
+        }
+        bases
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashSet;
+
+use petgraph::{
+    graph::NodeIndex,
+    visit::{EdgeRef, NodeIndexable},
+    Direction,
+};
+
+use crate::cfg::{Cfg, IDoms};
+
+// The dominator tree (D-edges) together with the CFG edges that are not dominator tree edges (J-edges).
+// Iterated dominance frontiers are read off it in time linear in the size of the graph per query,
+// without ever building the dominance frontiers themselves, which can be quadratic in size.
+// "A Linear Time Algorithm for Placing φ-Nodes", Sreedhar and Gao, POPL 1995
+// https://dl.acm.org/doi/10.1145/199448.199464
+//
+// Everything is indexed by `NodeIndex::index`.
+#[derive(Debug, Clone, Default)]
+pub struct DjGraph {
+    // Depth in the dominator tree, None for blocks that are not in it (unreachable ones)
+    level: Vec<Option<usize>>,
+    dom_children: Vec<Vec<NodeIndex>>,
+    j_edges: Vec<Vec<NodeIndex>>,
+    max_level: usize,
+}
+
+impl DjGraph {
+    fn level(&self, node: NodeIndex) -> Option<usize> {
+        self.level[node.index()]
+    }
+
+    // The blocks that need a φ-function for a variable defined in `defs`
+    pub fn iterated_frontier(&self, defs: &HashSet<NodeIndex>) -> HashSet<NodeIndex> {
+        // The "piggy bank": nodes waiting to be visited, bucketed by level, deepest taken first
+        let mut bank: Vec<Vec<NodeIndex>> = vec![Vec::new(); self.max_level + 1];
+        let mut banked = vec![false; self.level.len()];
+        let mut visited = vec![false; self.level.len()];
+        let mut idf = HashSet::new();
+
+        for &def in defs {
+            if let Some(level) = self.level(def) {
+                bank[level].push(def);
+                banked[def.index()] = true;
+            }
+        }
+
+        // Nodes only ever go into the bank at or above the level of the current root,
+        // so the buckets can be emptied one after the other
+        for current in (0..=self.max_level).rev() {
+            while let Some(root) = bank[current].pop() {
+                visited[root.index()] = true;
+                let mut stack = vec![root];
+
+                // Walk the dominator subtree of `root`. A J-edge leaving it for a node no deeper than
+                // `root` crosses the dominance frontier of the subtree.
+                while let Some(node) = stack.pop() {
+                    for &target in &self.j_edges[node.index()] {
+                        let level = self.level(target).unwrap();
+                        if level <= current && idf.insert(target) && !banked[target.index()] {
+                            banked[target.index()] = true;
+                            bank[level].push(target);
+                        }
+                    }
+                    for &child in &self.dom_children[node.index()] {
+                        if !visited[child.index()] {
+                            visited[child.index()] = true;
+                            stack.push(child);
+                        }
+                    }
+                }
+            }
+        }
+
+        idf
+    }
+}
+
+impl Cfg {
+    pub fn dj_graph(&self, idoms: &IDoms) -> DjGraph {
+        let size = self.graph.node_bound();
+        let mut dj = DjGraph {
+            level: vec![None; size],
+            dom_children: vec![Vec::new(); size],
+            j_edges: vec![Vec::new(); size],
+            max_level: 0,
+        };
+
+        let mut stack = Vec::new();
+        for (&node, &idom) in idoms {
+            match idom {
+                Some(idom) => dj.dom_children[idom.index()].push(node),
+                None => stack.push((node, 0)),
+            }
+        }
+        while let Some((node, level)) = stack.pop() {
+            dj.level[node.index()] = Some(level);
+            dj.max_level = dj.max_level.max(level);
+            stack.extend(
+                dj.dom_children[node.index()]
+                    .iter()
+                    .map(|&child| (child, level + 1)),
+            );
+        }
+
+        for node in self.graph.node_indices() {
+            if dj.level[node.index()].is_none() {
+                continue;
+            }
+            for edge in self.graph.edges_directed(node, Direction::Outgoing) {
+                let target = edge.target();
+                if idoms.get(&target) != Some(&Some(node)) {
+                    dj.j_edges[node.index()].push(target);
+                }
+            }
+        }
+
+        dj
+    }
+}
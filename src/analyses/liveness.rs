@@ -0,0 +1,88 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::cfg::{BlockId, Cfg, Label};
+use crate::value::ValueId;
+
+#[derive(Debug, Clone, Default)]
+pub struct Liveness {
+    pub live_in: HashMap<BlockId, HashSet<ValueId>>,
+    pub live_out: HashMap<BlockId, HashSet<ValueId>>,
+}
+
+impl Liveness {
+    pub fn is_live_in(&self, block: BlockId, var: ValueId) -> bool {
+        self.live_in
+            .get(&block)
+            .is_some_and(|vars| vars.contains(&var))
+    }
+
+    pub fn is_live_out(&self, block: BlockId, var: ValueId) -> bool {
+        self.live_out
+            .get(&block)
+            .is_some_and(|vars| vars.contains(&var))
+    }
+}
+
+impl Cfg {
+    // Backward may-analysis over values, in or out of SSA form.
+    // An argument of a φ-function is used at the end of the predecessor it comes from,
+    // not at the start of the block the φ-function is in.
+    pub fn liveness(&self) -> Liveness {
+        // upward exposed uses, definitions, and the φ-function uses per predecessor label
+        let mut uses: HashMap<BlockId, HashSet<ValueId>> = HashMap::new();
+        let mut defs: HashMap<BlockId, HashSet<ValueId>> = HashMap::new();
+        let mut phi_uses: HashMap<BlockId, HashMap<&Label, HashSet<ValueId>>> = HashMap::new();
+
+        for &block in &self.layout {
+            let uses = uses.entry(block).or_default();
+            let defs = defs.entry(block).or_default();
+            let phi_uses = phi_uses.entry(block).or_default();
+
+            for inst in self.block(block).insts.iter().map(|&id| &self.insts[id]) {
+                if inst.is_phi() {
+                    for (&arg, label) in inst.args.iter().zip(&inst.labels) {
+                        phi_uses.entry(label).or_default().insert(arg);
+                    }
+                } else {
+                    uses.extend(inst.args.iter().filter(|arg| !defs.contains(*arg)));
+                }
+                if let Some(dest) = inst.dest {
+                    defs.insert(dest);
+                }
+            }
+        }
+
+        let mut liveness = Liveness {
+            live_in: uses.clone(),
+            live_out: self
+                .layout
+                .iter()
+                .map(|&block| (block, HashSet::new()))
+                .collect(),
+        };
+
+        // Blocks are visited last to first, so that most of them see their successors done already
+        let mut work_queue: VecDeque<BlockId> = self.layout.iter().rev().copied().collect();
+        while let Some(block) = work_queue.pop_front() {
+            let label = self.label(block);
+            let mut live_out = HashSet::new();
+            for succ in self.successors(block) {
+                live_out.extend(liveness.live_in[&succ].iter().copied());
+                if let Some(args) = phi_uses[&succ].get(label) {
+                    live_out.extend(args.iter().copied());
+                }
+            }
+
+            let mut live_in = uses[&block].clone();
+            live_in.extend(live_out.difference(&defs[&block]).copied());
+
+            liveness.live_out.insert(block, live_out);
+            if live_in != liveness.live_in[&block] {
+                liveness.live_in.insert(block, live_in);
+                work_queue.extend(self.predecessors(block));
+            }
+        }
+
+        liveness
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::{BTreeMap, HashSet};
+
+use petgraph::{graph::NodeIndex, visit::EdgeRef, Direction};
+
+use crate::cfg::{dominates, Cfg, IDoms};
+
+// A natural loop: everything that can reach one of the back edges into `header` without passing through it.
+// Back edges sharing a header make up a single loop.
+#[derive(Debug, Clone)]
+pub struct Loop {
+    pub header: NodeIndex,
+    pub latches: Vec<NodeIndex>,
+    pub body: HashSet<NodeIndex>,
+    // Edges leaving the loop, (inside, outside)
+    pub exits: Vec<(NodeIndex, NodeIndex)>,
+    // Index of the innermost loop that contains this one
+    pub parent: Option<usize>,
+    pub depth: usize,
+}
+
+impl Loop {
+    pub fn contains(&self, node: NodeIndex) -> bool {
+        self.body.contains(&node)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Loops {
+    // Outer loops come before the loops nested in them
+    pub loops: Vec<Loop>,
+}
+
+impl Cfg {
+    pub fn find_loops(&self, idoms: &IDoms) -> Loops {
+        let mut latches: BTreeMap<NodeIndex, Vec<NodeIndex>> = BTreeMap::new();
+        for node in self.graph.node_indices() {
+            // Unreachable blocks are not part of the dominator tree
+            if !idoms.contains_key(&node) {
+                continue;
+            }
+            for edge in self.graph.edges_directed(node, Direction::Outgoing) {
+                if dominates(idoms, edge.target(), node) {
+                    latches.entry(edge.target()).or_default().push(node);
+                }
+            }
+        }
+
+        let mut loops: Vec<Loop> = latches
+            .into_iter()
+            .map(|(header, latches)| {
+                let mut body = HashSet::from([header]);
+                let mut stack = latches.clone();
+                while let Some(node) = stack.pop() {
+                    if body.insert(node) {
+                        stack.extend(
+                            self.graph
+                                .edges_directed(node, Direction::Incoming)
+                                .map(|edge| edge.source()),
+                        );
+                    }
+                }
+
+                let mut exits: Vec<_> = body
+                    .iter()
+                    .flat_map(|&node| {
+                        self.graph
+                            .edges_directed(node, Direction::Outgoing)
+                            .map(move |edge| (node, edge.target()))
+                    })
+                    .filter(|(_, target)| !body.contains(target))
+                    .collect();
+                exits.sort();
+
+                Loop {
+                    header,
+                    latches,
+                    body,
+                    exits,
+                    parent: None,
+                    depth: 0,
+                }
+            })
+            .collect();
+
+        // Every loop contains all the loops nested in it, so sorting by size puts outer loops first
+        loops.sort_by_key(|l| (std::cmp::Reverse(l.body.len()), l.header));
+        for i in 0..loops.len() {
+            let parent = (0..i).rev().find(|&j| loops[j].contains(loops[i].header));
+            loops[i].parent = parent;
+            loops[i].depth = parent.map_or(1, |j| loops[j].depth + 1);
+        }
+
+        Loops { loops }
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+
+use bril_rs::{EffectOps, ValueOps};
+use petgraph::graph::NodeIndex;
+
+use crate::analyses::dj_graph::DjGraph;
+use crate::cfg::{dominator_tree, BlockId, Cfg, IDoms};
+use crate::inst::{Inst, InstId, Op};
+
+// Memory SSA: memory as a whole is treated as a single variable, that every instruction which may change it
+// defines (MemoryDef) and every load uses (MemoryUse). Where definitions meet, a MemoryPhi merges them,
+// at the same iterated dominance frontiers that φ-functions for variables go to.
+// Every access knows the access that last defined the memory it sees, so the store a load reads from,
+// if there is one, is found by walking up those links.
+// "Memory SSA - A Unified Approach for Sparsely Representing Memory Operations", Novillo, GCC Summit 2007
+//
+// Nothing is said about which locations are involved: a MemoryDef may well leave the memory seen by a later
+// load untouched. Telling that apart is up to an alias analysis.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MemoryAccessId(usize);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MemoryAccess {
+    // Memory as it is when the function is entered
+    LiveOnEntry,
+    Def {
+        inst: InstId,
+        defining: MemoryAccessId,
+    },
+    Use {
+        inst: InstId,
+        defining: MemoryAccessId,
+    },
+    // One incoming access per predecessor edge
+    Phi {
+        block: BlockId,
+        incoming: Vec<(BlockId, MemoryAccessId)>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryEffect {
+    None,
+    Read,
+    Write,
+}
+
+// What an instruction does to memory. Allocating counts as writing: the new memory has to be ordered
+// before anything reading it, and after anything still using what it may reuse.
+pub fn memory_effect(inst: &Inst) -> MemoryEffect {
+    match &inst.op {
+        Op::Value(ValueOps::Load) => MemoryEffect::Read,
+        Op::Value(ValueOps::Alloc | ValueOps::Call)
+        | Op::Effect(EffectOps::Store | EffectOps::Free | EffectOps::Call) => MemoryEffect::Write,
+        _ => MemoryEffect::None,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemorySsa {
+    accesses: Vec<MemoryAccess>,
+    by_inst: HashMap<InstId, MemoryAccessId>,
+    phis: HashMap<BlockId, MemoryAccessId>,
+    // The accesses of every reachable block in order, its MemoryPhi first
+    by_block: HashMap<BlockId, Vec<MemoryAccessId>>,
+}
+
+impl MemorySsa {
+    pub const LIVE_ON_ENTRY: MemoryAccessId = MemoryAccessId(0);
+
+    pub fn access(&self, id: MemoryAccessId) -> &MemoryAccess {
+        &self.accesses[id.0]
+    }
+
+    // The access made by a memory instruction
+    pub fn of_inst(&self, inst: InstId) -> Option<MemoryAccessId> {
+        self.by_inst.get(&inst).copied()
+    }
+
+    pub fn phi(&self, block: BlockId) -> Option<MemoryAccessId> {
+        self.phis.get(&block).copied()
+    }
+
+    pub fn block_accesses(&self, block: BlockId) -> &[MemoryAccessId] {
+        self.by_block.get(&block).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    // The definition the memory seen by a MemoryDef or MemoryUse comes from
+    pub fn defining(&self, id: MemoryAccessId) -> Option<MemoryAccessId> {
+        match self.access(id) {
+            MemoryAccess::Def { defining, .. } | MemoryAccess::Use { defining, .. } => {
+                Some(*defining)
+            }
+            _ => None,
+        }
+    }
+
+    // The access that defines memory at the end of a reachable block
+    pub fn live_at_end(&self, block: BlockId, idoms: &IDoms) -> MemoryAccessId {
+        let last = self
+            .block_accesses(block)
+            .iter()
+            .rev()
+            .find(|&&id| !matches!(self.access(id), MemoryAccess::Use { .. }));
+        match (last, idoms[&block]) {
+            (Some(&last), _) => last,
+            // Without a MemoryPhi, the only definition that reaches the block is the one above it
+            (None, Some(idom)) => self.live_at_end(idom, idoms),
+            (None, None) => Self::LIVE_ON_ENTRY,
+        }
+    }
+
+    // For every access, the accesses that refer to it, MemoryPhis included
+    pub fn users(&self) -> HashMap<MemoryAccessId, Vec<MemoryAccessId>> {
+        let mut users: HashMap<MemoryAccessId, Vec<MemoryAccessId>> = HashMap::new();
+        for (i, access) in self.accesses.iter().enumerate() {
+            let user = MemoryAccessId(i);
+            match access {
+                MemoryAccess::LiveOnEntry => {}
+                MemoryAccess::Def { defining, .. } | MemoryAccess::Use { defining, .. } => {
+                    users.entry(*defining).or_default().push(user);
+                }
+                MemoryAccess::Phi { incoming, .. } => {
+                    for &(_, from) in incoming {
+                        users.entry(from).or_default().push(user);
+                    }
+                }
+            }
+        }
+        users
+    }
+
+    fn add(&mut self, access: MemoryAccess) -> MemoryAccessId {
+        let id = MemoryAccessId(self.accesses.len());
+        self.accesses.push(access);
+        id
+    }
+
+    // `0 = LiveOnEntry`, `3 = MemoryPhi({b1, 1}, {b2, 2})`, `4 = MemoryDef(3)`, `MemoryUse(4)`
+    pub fn describe(&self, id: MemoryAccessId, cfg: &Cfg) -> String {
+        match self.access(id) {
+            MemoryAccess::LiveOnEntry => format!("{} = LiveOnEntry", id.0),
+            MemoryAccess::Def { defining, .. } => format!("{} = MemoryDef({})", id.0, defining.0),
+            MemoryAccess::Use { defining, .. } => format!("MemoryUse({})", defining.0),
+            MemoryAccess::Phi { incoming, .. } => {
+                let incoming: Vec<String> = incoming
+                    .iter()
+                    .map(|(pred, from)| format!("{{{}, {}}}", cfg.label(*pred), from.0))
+                    .collect();
+                format!("{} = MemoryPhi({})", id.0, incoming.join(", "))
+            }
+        }
+    }
+}
+
+impl Cfg {
+    pub fn memory_ssa(&self, idoms: &IDoms, dj: &DjGraph) -> MemorySsa {
+        let mut mssa = MemorySsa {
+            accesses: vec![MemoryAccess::LiveOnEntry],
+            by_inst: HashMap::new(),
+            phis: HashMap::new(),
+            by_block: HashMap::new(),
+        };
+
+        let def_blocks: HashSet<NodeIndex> = self
+            .layout
+            .iter()
+            .copied()
+            .filter(|&block| idoms.contains_key(&block))
+            .filter(|&block| {
+                self.block(block)
+                    .insts
+                    .iter()
+                    .any(|&id| memory_effect(&self.insts[id]) == MemoryEffect::Write)
+            })
+            .collect();
+        let mut phi_blocks: Vec<BlockId> = dj.iterated_frontier(&def_blocks).into_iter().collect();
+        phi_blocks.sort();
+        for block in phi_blocks {
+            let phi = mssa.add(MemoryAccess::Phi {
+                block,
+                incoming: Vec::new(),
+            });
+            mssa.phis.insert(block, phi);
+        }
+
+        let (root, dom_tree) = dominator_tree(idoms);
+        self.rename_memory(&dom_tree, root, MemorySsa::LIVE_ON_ENTRY, &mut mssa);
+        mssa
+    }
+
+    // Like rename_variables, with a single variable: `current` is the access that defines memory on entry
+    // to `block`, down this path of the dominator tree
+    fn rename_memory(
+        &self,
+        dom_tree: &HashMap<BlockId, BTreeSet<BlockId>>,
+        block: BlockId,
+        mut current: MemoryAccessId,
+        mssa: &mut MemorySsa,
+    ) {
+        let mut accesses = Vec::new();
+        if let Some(phi) = mssa.phi(block) {
+            current = phi;
+            accesses.push(phi);
+        }
+        for &id in &self.block(block).insts {
+            let access = match memory_effect(&self.insts[id]) {
+                MemoryEffect::None => continue,
+                MemoryEffect::Read => mssa.add(MemoryAccess::Use {
+                    inst: id,
+                    defining: current,
+                }),
+                MemoryEffect::Write => {
+                    let def = mssa.add(MemoryAccess::Def {
+                        inst: id,
+                        defining: current,
+                    });
+                    current = def;
+                    def
+                }
+            };
+            mssa.by_inst.insert(id, access);
+            accesses.push(access);
+        }
+        mssa.by_block.insert(block, accesses);
+
+        for succ in self.successors(block) {
+            if let Some(phi) = mssa.phi(succ)
+                && let MemoryAccess::Phi { incoming, .. } = &mut mssa.accesses[phi.0]
+            {
+                incoming.push((block, current));
+            }
+        }
+
+        if let Some(children) = dom_tree.get(&block) {
+            for &child in children {
+                self.rename_memory(dom_tree, child, current, mssa);
+            }
+        }
+    }
+
+    // The function with the memory access of every instruction, and the MemoryPhi of every block, next to it
+    pub fn memory_ssa_listing(&self, mssa: &MemorySsa) -> String {
+        let names = self.values.names();
+        let mut listing = String::new();
+        writeln!(
+            listing,
+            "; {}",
+            mssa.describe(MemorySsa::LIVE_ON_ENTRY, self)
+        )
+        .unwrap();
+        for block in self.blocks_in_order() {
+            writeln!(listing, ".{}:", block.label).unwrap();
+            if let Some(phi) = mssa.phi(block.node) {
+                writeln!(listing, "\t; {}", mssa.describe(phi, self)).unwrap();
+            }
+            for &id in &block.insts {
+                if let Some(access) = mssa.of_inst(id) {
+                    writeln!(listing, "\t; {}", mssa.describe(access, self)).unwrap();
+                }
+                writeln!(
+                    listing,
+                    "\t{}",
+                    self.insts[id].to_bril(&self.values, &names)
+                )
+                .unwrap();
+            }
+        }
+        listing
+    }
+}
@@ -0,0 +1,5 @@
+pub mod alias;
+pub mod dj_graph;
+pub mod liveness;
+pub mod loops;
+pub mod memory_ssa;
@@ -1,32 +1,50 @@
-use bril_rs::{Instruction, Type};
 use petgraph::graph::NodeIndex;
 
+use crate::inst::{InstArena, InstId};
+use crate::value::ValueId;
+
 #[derive(Debug, Clone, Default)]
 pub struct BasicBlock {
     pub node: NodeIndex,
     pub label: String,
-    pub insts: Vec<Instruction>,
-    pub definitions: Vec<(String, Type)>,
+    pub insts: Vec<InstId>,
+    pub definitions: Vec<ValueId>,
 }
 
 impl BasicBlock {
-    pub fn has_definition(&self, def: &(String, Type)) -> bool {
-        self.definitions.contains(def)
+    pub fn has_definition(&self, def: ValueId) -> bool {
+        self.definitions.contains(&def)
     }
-}
 
-impl std::fmt::Display for BasicBlock {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "{} ({}):", self.label, self.node.index())?;
-        for inst in &self.insts {
-            writeln!(f, "\t{}", inst)?;
+    // Recompute `definitions` after instructions were added, removed or renamed
+    pub fn update_definitions(&mut self, insts: &InstArena) {
+        self.definitions.clear();
+        for &id in &self.insts {
+            if let Some(dest) = insts[id].dest
+                && !self.definitions.contains(&dest)
+            {
+                self.definitions.push(dest);
+            }
         }
+    }
 
-        // writeln!(f, "Definitions:")?;
-        // for def in &self.definitions {
-        //     writeln!(f, "{:?}", def)?;
-        // }
+    pub fn terminator(&self, insts: &InstArena) -> Option<InstId> {
+        self.insts
+            .last()
+            .copied()
+            .filter(|&id| insts[id].is_terminator())
+    }
+
+    // Index at which instructions that have to run last in the block (copies out of SSA, ...) go
+    pub fn end_index(&self, insts: &InstArena) -> usize {
+        self.insts.len() - usize::from(self.terminator(insts).is_some())
+    }
 
-        Ok(())
+    // The leading φ-functions
+    pub fn phis<'a>(&'a self, insts: &'a InstArena) -> impl Iterator<Item = InstId> + 'a {
+        self.insts
+            .iter()
+            .copied()
+            .take_while(|&id| insts[id].is_phi())
     }
 }
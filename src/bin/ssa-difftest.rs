@@ -0,0 +1,78 @@
+use std::process;
+
+use ssa::difftest::{test_directory, PIPELINES};
+
+const USAGE: &str = "usage: ssa-difftest DIR [--passes=PASS,...]
+
+Runs every program in DIR, in the layout of the Bril benchmarks (NAME.json or just
+NAME.bril, with NAME.out and the ARGS of NAME.bril if there are any), before and after each of
+a list of pipelines, or only after --passes if it is given, and reports the pipelines
+that change what a program does, with a minimized program for each.";
+
+#[derive(Debug, Default)]
+struct Options {
+    dir: Option<String>,
+    passes: Option<String>,
+}
+
+fn main() {
+    let options = parse_args().unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1);
+    });
+    let Some(dir) = &options.dir else {
+        eprintln!("{USAGE}");
+        process::exit(1);
+    };
+    let pipelines: Vec<&str> = match &options.passes {
+        Some(passes) => vec![passes],
+        None => PIPELINES.to_vec(),
+    };
+
+    match test_directory(dir, &pipelines) {
+        Ok((report, failures)) => {
+            print!("{report}");
+            if failures > 0 {
+                process::exit(1);
+            }
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Err(USAGE.to_string());
+        }
+        if !arg.starts_with("--") {
+            if options.dir.is_some() {
+                return Err(format!("unexpected argument {arg}\n{USAGE}"));
+            }
+            options.dir = Some(arg);
+            continue;
+        }
+
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), value.to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("{arg} expects a value"))?;
+                (arg, value)
+            }
+        };
+        match flag.as_str() {
+            "--passes" => options.passes = Some(value),
+            _ => return Err(format!("unknown option {flag}\n{USAGE}")),
+        }
+    }
+
+    Ok(options)
+}
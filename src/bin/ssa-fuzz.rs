@@ -0,0 +1,93 @@
+use std::process;
+
+use bril_rs::output_program;
+use ssa::difftest::PIPELINES;
+use ssa::fuzz::{fuzz, random_program};
+
+const USAGE: &str = "usage: ssa-fuzz [--count=COUNT] [--seed=SEED] [--passes=PASS,...]
+       ssa-fuzz --print=SEED
+
+Checks SSA construction step by step on COUNT random programs (100 if not given), the ones
+for the seeds from SEED on (0 if not given), and then runs each of them before and after
+every pipeline ssa-difftest runs, or only after --passes if it is given. Reports the programs
+that fail, with a minimized program for each. --print writes the random program for SEED.";
+
+#[derive(Debug)]
+struct Options {
+    count: u64,
+    seed: u64,
+    passes: Option<String>,
+    print: Option<u64>,
+}
+
+fn main() {
+    let options = parse_args().unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1);
+    });
+    if let Some(seed) = options.print {
+        output_program(&random_program(seed));
+        return;
+    }
+    let pipelines: Vec<&str> = match &options.passes {
+        Some(passes) => vec![passes],
+        None => PIPELINES.to_vec(),
+    };
+
+    match fuzz(options.seed, options.count, &pipelines) {
+        Ok((report, failures)) => {
+            print!("{report}");
+            if failures > 0 {
+                process::exit(1);
+            }
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        count: 100,
+        seed: 0,
+        passes: None,
+        print: None,
+    };
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Err(USAGE.to_string());
+        }
+        if !arg.starts_with("--") {
+            return Err(format!("unexpected argument {arg}\n{USAGE}"));
+        }
+
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), value.to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("{arg} expects a value"))?;
+                (arg, value)
+            }
+        };
+        if flag == "--passes" {
+            options.passes = Some(value);
+            continue;
+        }
+        let number = value
+            .parse()
+            .map_err(|_| format!("{flag} expects a number, got {value}"))?;
+        match flag.as_str() {
+            "--count" => options.count = number,
+            "--seed" => options.seed = number,
+            "--print" => options.print = Some(number),
+            _ => return Err(format!("unknown option {flag}\n{USAGE}")),
+        }
+    }
+
+    Ok(options)
+}
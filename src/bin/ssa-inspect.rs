@@ -0,0 +1,70 @@
+use std::process;
+
+use ssa::braun::compare_ssa_construction;
+use ssa::cfg::CfgBuilder;
+use ssa::utils::read_program;
+
+const USAGE: &str = "usage: ssa-inspect [FILE] [--compare-ssa] [--memory-ssa]
+
+Reads a Bril program from FILE (JSON, or text if it ends in .bril), or stdin if FILE
+is missing or `-`.
+--compare-ssa builds SSA form of every function with both construction algorithms and
+checks that the results agree. --memory-ssa prints every function, with the Memory SSA
+access of each instruction next to it.";
+
+#[derive(Debug, Default)]
+struct Options {
+    input: Option<String>,
+    compare_ssa: bool,
+    memory_ssa: bool,
+}
+
+fn main() {
+    let options = parse_args().unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1);
+    });
+    let program = read_program(options.input.as_deref()).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1);
+    });
+
+    let mut failed = false;
+    for func in &program.functions {
+        if options.compare_ssa
+            && let Err(diagnostics) = compare_ssa_construction(func)
+        {
+            eprintln!("@{}: SSA construction algorithms disagree:", func.name);
+            for diagnostic in diagnostics {
+                eprintln!("\t{diagnostic}");
+            }
+            failed = true;
+        }
+        if options.memory_ssa {
+            let cfg = CfgBuilder::new().build(func);
+            let idoms = cfg.get_idoms();
+            let mssa = cfg.memory_ssa(&idoms, &cfg.dj_graph(&idoms));
+            println!("; @{} memory SSA", cfg.name);
+            print!("{}", cfg.memory_ssa_listing(&mssa));
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            "--compare-ssa" => options.compare_ssa = true,
+            "--memory-ssa" => options.memory_ssa = true,
+            _ if !arg.starts_with("--") && options.input.is_none() => options.input = Some(arg),
+            _ => return Err(format!("unexpected argument {arg}\n{USAGE}")),
+        }
+    }
+
+    Ok(options)
+}
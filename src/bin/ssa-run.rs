@@ -0,0 +1,106 @@
+use std::{fs, process};
+
+use ssa::cfg::{Cfg, CfgBuilder};
+use ssa::interp::{parse_arg, Interpreter};
+use ssa::profile::profile_to_json;
+use ssa::utils::read_program;
+
+const USAGE: &str = "usage: ssa-run [FILE] [--args=ARGS] [--write-profile=PROFILE]
+
+Runs a Bril program from FILE (JSON, or text if it ends in .bril), or stdin if FILE
+is missing or `-`, with the space separated ARGS as arguments of main. What it prints goes to stdout, and the number
+of instructions it ran to stderr. --write-profile also writes the counts of the run to
+PROFILE, in the format `ssa --profile` reads.";
+
+#[derive(Debug, Default)]
+struct Options {
+    input: Option<String>,
+    args: Vec<String>,
+    write_profile: Option<String>,
+}
+
+fn main() {
+    let options = parse_args().unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1);
+    });
+    let program = read_program(options.input.as_deref()).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1);
+    });
+    let cfgs: Vec<Cfg> = program
+        .functions
+        .iter()
+        .map(|func| CfgBuilder::new().build(func))
+        .collect();
+    if let Err(err) = run_program(&cfgs, &options) {
+        eprintln!("{err}");
+        process::exit(1);
+    }
+}
+
+fn run_program(cfgs: &[Cfg], options: &Options) -> Result<(), String> {
+    let main = cfgs
+        .iter()
+        .find(|cfg| cfg.name == "main")
+        .ok_or("no @main to run")?;
+    if main.args.len() != options.args.len() {
+        return Err(format!(
+            "@main takes {} arguments, got {}",
+            main.args.len(),
+            options.args.len()
+        ));
+    }
+    let args = main
+        .args
+        .iter()
+        .zip(&options.args)
+        .map(|(&arg, text)| parse_arg(text, main.values.ty(arg).unwrap()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let execution = Interpreter::new(cfgs)
+        .run("main", args)
+        .map_err(|err| format!("error: {err}"))?;
+
+    print!("{}", execution.output);
+    eprintln!("total_dyn_inst: {}", execution.instructions);
+    if let Some(path) = &options.write_profile {
+        fs::write(path, profile_to_json(&execution.profile))
+            .map_err(|err| format!("cannot write {path}: {err}"))?;
+    }
+    Ok(())
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Err(USAGE.to_string());
+        }
+        if !arg.starts_with("--") {
+            if options.input.is_some() {
+                return Err(format!("unexpected argument {arg}\n{USAGE}"));
+            }
+            options.input = Some(arg);
+            continue;
+        }
+
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), value.to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("{arg} expects a value"))?;
+                (arg, value)
+            }
+        };
+        match flag.as_str() {
+            "--args" => options.args = value.split_whitespace().map(str::to_string).collect(),
+            "--write-profile" => options.write_profile = Some(value),
+            _ => return Err(format!("unknown option {flag}\n{USAGE}")),
+        }
+    }
+
+    Ok(options)
+}
@@ -0,0 +1,315 @@
+use std::collections::{HashMap, HashSet};
+
+use bril_rs::{EffectOps, Function, ValueOps};
+
+use crate::cfg::{BlockId, Cfg, CfgBuilder};
+use crate::inst::{Inst, InstId, Op};
+use crate::value::ValueId;
+use crate::verify::Diagnostic;
+
+// SSA construction straight from the CFG, without dominators or dominance frontiers
+// "Simple and Efficient Construction of Static Single Assignment Form", Braun et al., CC 2013
+// https://c9x.me/compile/bib/braun13cc.pdf
+//
+// Blocks are filled in reverse postorder. A block is sealed once all its predecessors are filled:
+// until then, reading a variable in it creates an empty φ-function that gets its operands on sealing.
+// φ-functions that only ever see one value are removed as soon as that becomes known.
+//
+// A failing guard goes to its label with the variables as they were when the speculation started. Those the
+// speculation may define are read where it starts, and reading them along the edge a guard aborts along
+// gives these values rather than those at the end of the block.
+#[derive(Default)]
+struct Builder {
+    // The value a variable has at the end of a block (as much of it as has been filled)
+    current_def: HashMap<(ValueId, BlockId), ValueId>,
+    filled: HashSet<BlockId>,
+    sealed: HashSet<BlockId>,
+    incomplete_phis: HashMap<BlockId, Vec<(ValueId, InstId)>>,
+    // The variable of every φ-function placed, their destinations are replaced on the way
+    phi_vars: HashMap<InstId, ValueId>,
+    // Removed φ-functions, and the value each of them was replaced with
+    replaced: HashMap<ValueId, ValueId>,
+    // The φ-functions using a value
+    phi_users: HashMap<ValueId, HashSet<InstId>>,
+    // φ-functions whose operands are being read right now, they aren't complete enough to be removed
+    filling: HashSet<InstId>,
+    // The variables defined on the way from a block starting a speculation to the guards it reaches
+    speculated: HashMap<BlockId, HashSet<ValueId>>,
+    // The blocks ending in a guard, and the block it aborts to
+    aborts: HashMap<BlockId, BlockId>,
+    // The values of the variables in `speculated` when each speculation started
+    speculations: Vec<HashMap<ValueId, ValueId>>,
+    // The speculations open at the end of every filled block, innermost last
+    open: HashMap<BlockId, Vec<usize>>,
+}
+
+impl Builder {
+    fn resolve(&self, mut value: ValueId) -> ValueId {
+        while let Some(&next) = self.replaced.get(&value) {
+            value = next;
+        }
+        value
+    }
+
+    fn write_variable(&mut self, var: ValueId, block: BlockId, value: ValueId) {
+        self.current_def.insert((var, block), value);
+    }
+
+    fn read_variable(&mut self, cfg: &mut Cfg, var: ValueId, block: BlockId) -> ValueId {
+        match self.current_def.get(&(var, block)) {
+            Some(&value) => self.resolve(value),
+            None => self.read_variable_recursive(cfg, var, block),
+        }
+    }
+
+    fn read_variable_recursive(&mut self, cfg: &mut Cfg, var: ValueId, block: BlockId) -> ValueId {
+        let preds = cfg.predecessors(block);
+        let value = if !self.sealed.contains(&block) {
+            let phi = self.new_phi(cfg, var, block);
+            self.incomplete_phis
+                .entry(block)
+                .or_default()
+                .push((var, phi));
+            cfg.insts[phi].dest.unwrap()
+        } else if preds.is_empty() {
+            // The value on entry to the function: an argument, or undefined
+            var
+        } else if let [pred] = preds[..] {
+            self.read_variable_from(cfg, var, pred, block)
+        } else {
+            // The φ-function goes in first, so that a loop back to this block finds it and stops there
+            let phi = self.new_phi(cfg, var, block);
+            self.write_variable(var, block, cfg.insts[phi].dest.unwrap());
+            self.add_phi_operands(cfg, var, phi)
+        };
+        self.write_variable(var, block, value);
+        value
+    }
+
+    // The value of a variable on the way from `pred` to `block`
+    fn read_variable_from(
+        &mut self,
+        cfg: &mut Cfg,
+        var: ValueId,
+        pred: BlockId,
+        block: BlockId,
+    ) -> ValueId {
+        if self.aborts.get(&pred) == Some(&block)
+            && let Some(&speculation) = self.open[&pred].last()
+            && let Some(&value) = self.speculations[speculation].get(&var)
+        {
+            return self.resolve(value);
+        }
+        self.read_variable(cfg, var, pred)
+    }
+
+    fn new_phi(&mut self, cfg: &mut Cfg, var: ValueId, block: BlockId) -> InstId {
+        let dest = cfg.values.new_version(var);
+        let at = cfg.block(block).phis(&cfg.insts).count();
+        let phi = Inst::new(Op::Value(ValueOps::Phi), Some(dest), vec![]);
+        let phi = cfg.insert_inst(block, at, phi);
+        self.phi_vars.insert(phi, var);
+        phi
+    }
+
+    fn add_phi_operands(&mut self, cfg: &mut Cfg, var: ValueId, phi: InstId) -> ValueId {
+        self.filling.insert(phi);
+        let block = cfg.insts.block_of(phi);
+        for pred in cfg.predecessors(block) {
+            let value = self.read_variable_from(cfg, var, pred, block);
+            self.phi_users.entry(value).or_default().insert(phi);
+            let label = cfg.label(pred).clone();
+            cfg.insts.update(phi, |inst| {
+                inst.args.push(value);
+                inst.labels.push(label);
+            });
+        }
+        self.filling.remove(&phi);
+        self.try_remove_trivial_phi(cfg, phi)
+    }
+
+    fn try_remove_trivial_phi(&mut self, cfg: &mut Cfg, phi: InstId) -> ValueId {
+        let dest = cfg.insts[phi].dest.unwrap();
+        let mut same = None;
+        for &arg in &cfg.insts[phi].args {
+            let arg = self.resolve(arg);
+            if Some(arg) == same || arg == dest {
+                continue;
+            }
+            if same.is_some() {
+                // Merges at least two values
+                return dest;
+            }
+            same = Some(arg);
+        }
+        // A φ-function that only sees itself sits in unreachable code, or reads an undefined variable
+        let same = same.unwrap_or(self.phi_vars[&phi]);
+
+        cfg.remove_inst(phi);
+        self.replaced.insert(dest, same);
+
+        // Replacing this φ-function may have made the ones using it trivial as well
+        let users = self.phi_users.remove(&dest).unwrap_or_default();
+        self.phi_users
+            .entry(same)
+            .or_default()
+            .extend(users.iter().copied());
+        for user in users {
+            if user != phi && cfg.insts.contains(user) && !self.filling.contains(&user) {
+                self.try_remove_trivial_phi(cfg, user);
+            }
+        }
+
+        self.resolve(same)
+    }
+
+    fn try_seal(&mut self, cfg: &mut Cfg, block: BlockId) {
+        if self.sealed.contains(&block)
+            || !cfg
+                .predecessors(block)
+                .iter()
+                .all(|pred| self.filled.contains(pred))
+        {
+            return;
+        }
+        self.seal(cfg, block);
+    }
+
+    fn seal(&mut self, cfg: &mut Cfg, block: BlockId) {
+        for (var, phi) in self.incomplete_phis.remove(&block).unwrap_or_default() {
+            self.add_phi_operands(cfg, var, phi);
+        }
+        self.sealed.insert(block);
+    }
+
+    fn fill(&mut self, cfg: &mut Cfg, block: BlockId) {
+        // Blocks come after one of their predecessors, except for the entry block and unreachable ones. A
+        // guard that aborts closes the speculation it was in.
+        let mut open = cfg
+            .predecessors(block)
+            .into_iter()
+            .find_map(|pred| {
+                let mut open = self.open.get(&pred)?.clone();
+                if self.aborts.get(&pred) == Some(&block) {
+                    open.pop();
+                }
+                Some(open)
+            })
+            .unwrap_or_default();
+
+        let insts = cfg.block(block).insts.clone();
+        for id in insts {
+            // φ-functions created by earlier reads in this block already refer to values
+            if cfg.insts[id].is_phi() {
+                continue;
+            }
+            let args: Vec<ValueId> = cfg.insts[id]
+                .args
+                .clone()
+                .into_iter()
+                .map(|arg| self.read_variable(cfg, arg, block))
+                .collect();
+            let dest = cfg.insts[id].dest.map(|var| {
+                let value = cfg.values.new_version(var);
+                self.write_variable(var, block, value);
+                value
+            });
+            cfg.insts.update(id, |inst| {
+                inst.args = args;
+                inst.dest = dest;
+            });
+
+            match cfg.insts[id].op {
+                Op::Effect(EffectOps::Speculate) => {
+                    let vars = self.speculated.get(&block).cloned().unwrap_or_default();
+                    let values = vars
+                        .into_iter()
+                        .map(|var| (var, self.read_variable(cfg, var, block)))
+                        .collect();
+                    open.push(self.speculations.len());
+                    self.speculations.push(values);
+                }
+                Op::Effect(EffectOps::Commit) => {
+                    open.pop();
+                }
+                _ => {}
+            }
+        }
+        self.open.insert(block, open);
+        self.filled.insert(block);
+    }
+}
+
+impl Cfg {
+    // Alternative to insert_phi_nodes + rename_variables, for functions that are not in SSA form yet
+    pub fn construct_ssa_braun(&mut self) {
+        let mut builder = Builder::default();
+        for (guard, region, target) in self.guard_regions() {
+            builder.aborts.insert(guard, target);
+            let defined: HashSet<ValueId> = region
+                .iter()
+                .flat_map(|&block| self.block(block).insts.iter())
+                .filter_map(|&id| self.insts[id].dest)
+                .collect();
+            for &block in &region {
+                if self.starts_speculation(block) {
+                    builder
+                        .speculated
+                        .entry(block)
+                        .or_default()
+                        .extend(&defined);
+                }
+            }
+        }
+
+        // Unreachable blocks go last, their predecessors may never all be filled before them
+        let mut order = self.reverse_postorder();
+        let reachable: HashSet<BlockId> = order.iter().copied().collect();
+        order.extend(self.layout.iter().filter(|id| !reachable.contains(id)));
+
+        for &block in &order {
+            builder.try_seal(self, block);
+            builder.fill(self, block);
+            for succ in self.successors(block) {
+                builder.try_seal(self, succ);
+            }
+        }
+        // Everything is filled by now
+        for &block in &order {
+            builder.try_seal(self, block);
+        }
+
+        // Uses of removed φ-functions
+        let ids: Vec<InstId> = self.insts.iter().map(|(id, _)| id).collect();
+        for id in ids {
+            self.insts.update(id, |inst| {
+                for arg in &mut inst.args {
+                    *arg = builder.resolve(*arg);
+                }
+            });
+        }
+
+        for block in self.blocks.values_mut() {
+            block.update_definitions(&self.insts);
+        }
+        self.in_ssa = true;
+    }
+}
+
+// Dominance frontiers + renaming on one copy of the function, Braun et al. on another.
+// The second result has to be valid SSA, and see the same definitions at every use as the first.
+pub fn compare_ssa_construction(func: &Function) -> Result<(), Vec<Diagnostic>> {
+    let mut cytron = CfgBuilder::new().build(func);
+    cytron.remove_unreachable();
+    let idoms = cytron.get_idoms();
+    let df = cytron.get_dominance_frontiers(&idoms);
+    cytron.insert_phi_nodes(&df);
+    cytron.rename_variables(&idoms);
+
+    let mut braun = CfgBuilder::new().build(func);
+    braun.remove_unreachable();
+    braun.construct_ssa_braun();
+    braun.verify()?;
+    braun.verify_ssa(&braun.get_idoms())?;
+    braun.verify_equivalent_ssa(&cytron)
+}
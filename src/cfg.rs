@@ -1,47 +1,427 @@
-use bril_rs::{Code, EffectOps, Function, Instruction, Type, ValueOps};
+use bril_rs::{Argument, Code, EffectOps, Function, Instruction, Literal, Type, ValueOps};
 use petgraph::{
-    graph::NodeIndex,
-    visit::{Dfs, EdgeRef},
+    stable_graph::{NodeIndex, StableGraph},
+    visit::{Dfs, DfsPostOrder, EdgeRef},
     Directed,
-    Direction::{self, Outgoing},
+    Direction::{self, Incoming, Outgoing},
     Graph,
 };
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 
-use crate::utils::{get_new_block, graph_to_svg};
-use crate::{basic_block::BasicBlock, utils};
+use crate::analyses::dj_graph::DjGraph;
+use crate::basic_block::BasicBlock;
+use crate::inst::{Inst, InstArena, InstId, Op};
+use crate::utils::{get_new_block, graph_to_dot};
+use crate::value::{ValueId, Values};
 
-type Label = String;
+pub type Label = String;
 
-type IDoms = HashMap<NodeIndex, Option<NodeIndex>>;
-type DominanceFrontiers = HashMap<NodeIndex, HashSet<NodeIndex>>;
+// What is known about an edge of the control flow graph
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CfgEdge {
+    // Times the edge was taken, when there is a profile of the function
+    pub count: Option<u64>,
+}
+
+// Blocks are identified by their node in `Cfg::graph`, which stays the same until the block is removed
+pub type BlockId = NodeIndex;
+
+pub type IDoms = HashMap<NodeIndex, Option<NodeIndex>>;
+pub type DominanceFrontiers = HashMap<NodeIndex, HashSet<NodeIndex>>;
+
+// Nodes are instructions, with an edge from every definition to each of its uses
+#[derive(Debug, Clone, Default)]
+pub struct SsaGraph {
+    pub graph: Graph<InstId, (), Directed, u32>,
+    pub nodes: HashMap<InstId, NodeIndex>,
+}
+
+// What `Cfg::rename_along_dominator_tree` renames
+pub struct Renaming<'a> {
+    pub values: &'a [ValueId],
+    // The φ-functions placed for the values, and the value each of them stands for
+    pub placed: &'a HashMap<InstId, ValueId>,
+    // Whether every definition of a value gets a new version, like in SSA construction. Otherwise the
+    // destinations stay as they are.
+    pub new_versions: bool,
+    // The name each value has at the end of the blocks that define it already
+    pub at_end: &'a HashMap<NodeIndex, Vec<(ValueId, ValueId)>>,
+}
 
 #[derive(Debug, Clone, Default)]
 pub struct Cfg {
+    pub name: String,
+    pub args: Vec<ValueId>,
+    pub return_type: Option<Type>,
     pub entry_label: Label,
     pub blocks: HashMap<Label, BasicBlock>,
-    pub graph: Graph<Label, (), Directed, u32>,
+    // Every instruction of the function, the blocks only hold their ids
+    pub insts: InstArena,
+    // Every variable and SSA value the instructions refer to
+    pub values: Values,
+    pub graph: StableGraph<Label, CfgEdge, Directed, u32>,
+    // Canonical order of the blocks: source order, entry block first.
+    // Anything that walks over all blocks goes through this, so that output doesn't change from run to run.
+    pub layout: Vec<BlockId>,
+    // Set once variables have been renamed, cleared again when the φ-functions are removed
+    pub in_ssa: bool,
+    // Times the function was called, when there is a profile of it
+    pub entry_count: Option<u64>,
 }
 
-#[allow(dead_code)]
 impl Cfg {
-    pub fn remove_unreachable(&mut self) {
+    // The blocks a path from the entry block reaches
+    pub fn reachable(&self) -> HashSet<NodeIndex> {
         let mut dfs = Dfs::new(&self.graph, self.blocks[&self.entry_label].node);
-        let mut reachable = Vec::with_capacity(self.graph.node_count());
+        let mut reachable = HashSet::with_capacity(self.graph.node_count());
         while let Some(ni) = dfs.next(&self.graph) {
-            reachable.push(ni);
+            reachable.insert(ni);
         }
+        reachable
+    }
 
-        let mut unreachable = self
+    // Returns whether anything was removed
+    pub fn remove_unreachable(&mut self) -> bool {
+        let reachable = self.reachable();
+        let unreachable = self
             .graph
             .node_indices()
             .filter(|ni| !reachable.contains(ni))
             .collect::<Vec<_>>();
-        unreachable.sort();
-        unreachable.reverse();
+        self.layout.retain(|ni| reachable.contains(ni));
+        let mut removed = HashSet::new();
         for ni in unreachable {
-            self.graph.remove_node(ni);
+            let label = self.graph.remove_node(ni).unwrap();
+            let block = self.blocks.remove(&label).unwrap();
+            for id in block.insts {
+                self.insts.free(id);
+            }
+            removed.insert(label);
         }
+
+        // φ-functions lose the arguments coming from blocks that are gone
+        if !removed.is_empty() {
+            for block in self.blocks.values() {
+                for &id in &block.insts {
+                    self.insts.update(id, |inst| {
+                        if inst.is_phi() {
+                            let (kept_args, kept_labels): (Vec<_>, Vec<_>) = inst
+                                .args
+                                .drain(..)
+                                .zip(inst.labels.drain(..))
+                                .filter(|(_, label)| !removed.contains(label))
+                                .unzip();
+                            inst.args = kept_args;
+                            inst.labels = kept_labels;
+                        }
+                    });
+                }
+            }
+        }
+        !removed.is_empty()
+    }
+
+    pub fn block_id(&self, label: &str) -> BlockId {
+        self.blocks[label].node
+    }
+
+    pub fn label(&self, id: BlockId) -> &Label {
+        &self.graph[id]
+    }
+
+    pub fn block(&self, id: BlockId) -> &BasicBlock {
+        &self.blocks[&self.graph[id]]
+    }
+
+    pub fn block_mut(&mut self, id: BlockId) -> &mut BasicBlock {
+        self.blocks.get_mut(&self.graph[id]).unwrap()
+    }
+
+    // After the instructions of one block changed
+    pub fn update_block_definitions(&mut self, id: BlockId) {
+        let block = self.blocks.get_mut(&self.graph[id]).unwrap();
+        block.update_definitions(&self.insts);
+    }
+
+    pub fn blocks_in_order(&self) -> impl Iterator<Item = &BasicBlock> {
+        self.layout.iter().map(|&id| self.block(id))
+    }
+
+    // One entry per edge, so a block that branches here twice shows up twice
+    pub fn predecessors(&self, id: BlockId) -> Vec<BlockId> {
+        self.graph
+            .edges_directed(id, Incoming)
+            .map(|edge| edge.source())
+            .collect()
+    }
+
+    pub fn successors(&self, id: BlockId) -> Vec<BlockId> {
+        self.graph
+            .edges_directed(id, Outgoing)
+            .map(|edge| edge.target())
+            .collect()
+    }
+
+    pub fn push_inst(&mut self, block: BlockId, inst: Inst) -> InstId {
+        let id = self.insts.alloc(inst, block);
+        self.block_mut(block).insts.push(id);
+        id
+    }
+
+    pub fn insert_inst(&mut self, block: BlockId, index: usize, inst: Inst) -> InstId {
+        let id = self.insts.alloc(inst, block);
+        self.block_mut(block).insts.insert(index, id);
+        id
+    }
+
+    // Unlinks the instruction from its block and frees it. `definitions` is left to the caller,
+    // so that removing many instructions doesn't recompute it every time.
+    pub fn remove_inst(&mut self, id: InstId) -> Inst {
+        let block = self.insts.block_of(id);
+        self.block_mut(block).insts.retain(|&other| other != id);
+        self.insts.free(id)
+    }
+
+    // An unused label for a block created by a transformation
+    pub fn new_label(&self) -> Label {
+        (self.blocks.len()..)
+            .map(get_new_block)
+            .find(|label| !self.blocks.contains_key(label))
+            .unwrap()
+    }
+
+    // An empty block with no edges, placed in the layout right after `after`
+    pub fn add_block(&mut self, after: BlockId) -> BlockId {
+        let label = self.new_label();
+        let node = self.graph.add_node(label.clone());
+        self.blocks.insert(
+            label.clone(),
+            BasicBlock {
+                node,
+                label,
+                insts: Vec::new(),
+                definitions: Vec::new(),
+            },
+        );
+        let at = self
+            .layout
+            .iter()
+            .position(|&id| id == after)
+            .map_or(self.layout.len(), |i| i + 1);
+        self.layout.insert(at, node);
+        node
+    }
+
+    // Puts a new block with nothing but a jump to `to` on one of the edges from `from` to `to`
+    pub fn split_edge(&mut self, from: BlockId, to: BlockId) -> BlockId {
+        let middle = self.add_block(from);
+        let weight = self.redirect_edge(from, to, middle);
+        self.graph.add_edge(middle, to, weight);
+        self.push_inst(middle, jump(self.label(to).clone()));
+
+        let from_label = self.label(from).clone();
+        let middle_label = self.label(middle).clone();
+        self.relabel_phi_incoming(to, &from_label, &middle_label);
+        middle
+    }
+
+    // Moves the edges coming into `block` from `preds` (one entry per edge) onto a new block that jumps to `block`,
+    // placed right before it. What the φ-functions of `block` got along those edges is merged by φ-functions
+    // in the new block, unless it's the same value on all of them.
+    pub fn split_predecessors(&mut self, block: BlockId, preds: &[BlockId]) -> BlockId {
+        let at = self.layout.iter().position(|&id| id == block).unwrap();
+        let after = self.layout[at.saturating_sub(1)];
+        let new = self.add_block(after);
+        let mut count = Some(0);
+        for &pred in preds {
+            let moved = self.redirect_edge(pred, block, new);
+            count = count.zip(moved.count).map(|(a, b)| a + b);
+        }
+        self.graph.add_edge(new, block, CfgEdge { count });
+        self.push_inst(new, jump(self.label(block).clone()));
+
+        let moved: HashSet<Label> = preds.iter().map(|&pred| self.label(pred).clone()).collect();
+        let new_label = self.label(new).clone();
+        let phis: Vec<InstId> = self.block(block).phis(&self.insts).collect();
+        // The merges go in before the jump, in the order of the φ-functions they are for
+        let mut merges = 0;
+        for phi in phis {
+            let inst = &self.insts[phi];
+            let (args, labels): (Vec<_>, Vec<_>) = inst
+                .args
+                .iter()
+                .copied()
+                .zip(inst.labels.iter().cloned())
+                .filter(|(_, label)| moved.contains(label))
+                .unzip();
+            let merged = if args.iter().all(|&arg| arg == args[0]) {
+                args[0]
+            } else {
+                let dest = self.values.new_version(inst.dest.unwrap());
+                let merge = Inst {
+                    labels,
+                    ..Inst::new(Op::Value(ValueOps::Phi), Some(dest), args)
+                };
+                self.insert_inst(new, merges, merge);
+                merges += 1;
+                dest
+            };
+            self.insts.update(phi, |inst| {
+                let (mut args, mut labels): (Vec<_>, Vec<_>) = inst
+                    .args
+                    .drain(..)
+                    .zip(inst.labels.drain(..))
+                    .filter(|(_, label)| !moved.contains(label))
+                    .unzip();
+                args.push(merged);
+                labels.push(new_label.clone());
+                inst.args = args;
+                inst.labels = labels;
+            });
+        }
+        self.update_block_definitions(new);
+        new
+    }
+
+    // Makes one of the edges from `from` to `to` go to `new_to` instead, keeping its weight, which is returned.
+    // φ-functions are left to the caller, on both ends.
+    pub fn redirect_edge(&mut self, from: BlockId, to: BlockId, new_to: BlockId) -> CfgEdge {
+        let edge = self.graph.find_edge(from, to).expect("no such edge");
+        let weight = self.graph.remove_edge(edge).unwrap();
+        self.graph.add_edge(from, new_to, weight);
+
+        // Only one of the labels is replaced, `from` may branch to `to` twice
+        let to_label = self.label(to).clone();
+        let new_label = self.label(new_to).clone();
+        let terminator = self
+            .block(from)
+            .terminator(&self.insts)
+            .expect("block with a successor but no terminator");
+        self.insts.update(terminator, |inst| {
+            if let Some(label) = inst.labels.iter_mut().find(|label| **label == to_label) {
+                *label = new_label;
+            }
+        });
+        weight
+    }
+
+    // Turns the branch ending `block` into a jump to `keep`, one of its targets.
+    // The other target loses its edge, and its φ-functions the argument they had for it.
+    pub fn fold_branch(&mut self, block: BlockId, keep: BlockId) {
+        let terminator = self.block(block).terminator(&self.insts).unwrap();
+        let folded = Inst {
+            pos: self.insts[terminator].pos.clone(),
+            ..jump(self.label(keep).clone())
+        };
+        self.insts.replace(terminator, folded);
+
+        let mut kept = false;
+        let edges: Vec<_> = self
+            .graph
+            .edges_directed(block, Outgoing)
+            .map(|edge| (edge.id(), edge.target()))
+            .collect();
+        let label = self.label(block).clone();
+        for (edge, target) in edges {
+            if target == keep && !kept {
+                kept = true;
+                continue;
+            }
+            self.graph.remove_edge(edge);
+            self.remove_phi_incoming(target, &label);
+        }
+    }
+
+    // Points the φ-function arguments of `block` coming from `pred` at `new_pred` instead, one per φ-function
+    pub fn relabel_phi_incoming(&mut self, block: BlockId, pred: &Label, new_pred: &Label) {
+        let phis: Vec<InstId> = self.block(block).phis(&self.insts).collect();
+        for phi in phis {
+            self.insts.update(phi, |inst| {
+                if let Some(label) = inst.labels.iter_mut().find(|label| *label == pred) {
+                    *label = new_pred.clone();
+                }
+            });
+        }
+    }
+
+    // Drops the φ-function arguments of `block` coming from `pred`, one per φ-function
+    pub fn remove_phi_incoming(&mut self, block: BlockId, pred: &Label) {
+        let phis: Vec<InstId> = self.block(block).phis(&self.insts).collect();
+        for phi in phis {
+            self.insts.update(phi, |inst| {
+                if let Some(i) = inst.labels.iter().position(|label| label == pred) {
+                    inst.args.remove(i);
+                    inst.labels.remove(i);
+                }
+            });
+        }
+    }
+
+    // An edge is critical if it goes from a block with several successors to a block with several predecessors:
+    // nothing can be placed on it without also running on other paths. Returns whether anything was split.
+    pub fn split_critical_edges(&mut self) -> bool {
+        let critical: Vec<(BlockId, BlockId)> = self
+            .layout
+            .iter()
+            .filter(|&&from| self.successors(from).len() > 1)
+            .flat_map(|&from| self.successors(from).into_iter().map(move |to| (from, to)))
+            .filter(|&(_, to)| self.predecessors(to).len() > 1)
+            .collect();
+        for &(from, to) in &critical {
+            self.split_edge(from, to);
+        }
+        !critical.is_empty()
+    }
+
+    pub fn starts_speculation(&self, block: BlockId) -> bool {
+        self.block(block)
+            .insts
+            .iter()
+            .any(|&id| matches!(self.insts[id].op, Op::Effect(EffectOps::Speculate)))
+    }
+
+    // For every guard, the block it ends, the blocks the speculation it is in may have gone through to get to
+    // it, back to the blocks that start a speculation, and the block it aborts to
+    pub fn guard_regions(&self) -> Vec<(BlockId, HashSet<BlockId>, BlockId)> {
+        let mut regions = Vec::new();
+        for &block in &self.layout {
+            let Some(guard) = self.block(block).terminator(&self.insts) else {
+                continue;
+            };
+            if !self.insts[guard].is_guard() {
+                continue;
+            }
+            let mut region = HashSet::new();
+            let mut stack = vec![block];
+            while let Some(node) = stack.pop() {
+                if region.insert(node) && !self.starts_speculation(node) {
+                    stack.extend(self.predecessors(node));
+                }
+            }
+            regions.push((block, region, self.block_id(&self.insts[guard].labels[0])));
+        }
+        regions
+    }
+
+    // Reachable blocks only
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut dfs = DfsPostOrder::new(&self.graph, self.block_id(&self.entry_label));
+        let mut order = Vec::with_capacity(self.graph.node_count());
+        while let Some(ni) = dfs.next(&self.graph) {
+            order.push(ni);
+        }
+        order.reverse();
+        order
+    }
+
+    // Every variable defined anywhere, in layout order
+    fn all_definitions(&self) -> Vec<ValueId> {
+        let mut seen = HashSet::new();
+        self.blocks_in_order()
+            .flat_map(|block| &block.definitions)
+            .filter(|def| seen.insert(*def))
+            .cloned()
+            .collect()
     }
 
     pub fn get_idoms(&self) -> IDoms {
@@ -98,19 +478,10 @@ impl Cfg {
             }
         }
 
-        // idoms
-        //     .into_iter()
-        //     .map(|(key, val)| {
-        //         (
-        //             self.graph[key].clone(),
-        //             val.map(|val| self.graph[val].clone()),
-        //         )
-        //     })
-        //     .collect()
         idoms
     }
 
-    pub fn get_dominance_frontiers(&mut self, idoms: IDoms) -> DominanceFrontiers {
+    pub fn get_dominance_frontiers(&self, idoms: &IDoms) -> DominanceFrontiers {
         // https://ethz.ch/content/dam/ethz/special-interest/infk/inst-cs/lst-dam/documents/Education/Classes/Spring2016/2810_Advanced_Compiler_Design/Homework/slides_hw1.pdf
 
         let mut df: HashMap<NodeIndex, HashSet<NodeIndex>> = self
@@ -127,7 +498,9 @@ impl Cfg {
             if predecessors.len() > 1 {
                 for pred in predecessors {
                     let mut runner = Some(pred);
-                    while let Some(inner) = runner && runner != idoms[&node] {
+                    while let Some(inner) = runner
+                        && runner != idoms[&node]
+                    {
                         df.get_mut(&inner).unwrap().insert(node);
                         runner = idoms[&inner];
                     }
@@ -135,267 +508,555 @@ impl Cfg {
             }
         }
 
-        println!("df: {:?}", df);
         df
     }
 
-    pub fn insert_phi_nodes(&mut self, df: DominanceFrontiers) {
-        let all_defs: HashSet<(String, Type)> = self
-            .graph
-            .node_weights()
-            .map(|label| HashSet::from_iter(self.blocks[label].definitions.clone()))
-            .fold(HashSet::new(), |acc, el| acc.union(&el).cloned().collect());
+    pub fn insert_phi_nodes(&mut self, df: &DominanceFrontiers) {
+        let all_defs = self.all_definitions();
+        self.insert_phi_nodes_with(&all_defs, |def_blocks| {
+            iterated_dominance_frontier(df, def_blocks)
+        });
+    }
 
-        println!("all_defs: {:?}", all_defs);
+    // Same φ-functions as insert_phi_nodes, without materializing the dominance frontiers
+    pub fn insert_phi_nodes_dj(&mut self, dj: &DjGraph) {
+        let all_defs = self.all_definitions();
+        self.insert_phi_nodes_with(&all_defs, |def_blocks| dj.iterated_frontier(def_blocks));
+    }
 
-        for def in &all_defs {
-            let def_blocks: HashSet<NodeIndex> = self
-                .graph
-                .node_weights()
-                .filter_map(|label: &String| {
-                    let block = self.blocks.get(label).unwrap();
-                    if block.has_definition(def) {
-                        Some(block.node)
-                    } else {
-                        None
-                    }
-                })
+    // φ-functions for the variables in `vars` only, e.g. new ones in a function that is in SSA form otherwise
+    pub fn insert_phi_nodes_for(&mut self, vars: &[ValueId], dj: &DjGraph) {
+        self.insert_phi_nodes_with(vars, |def_blocks| dj.iterated_frontier(def_blocks));
+    }
+
+    // `idf` maps the blocks defining a variable to the blocks that need a φ-function for it
+    fn insert_phi_nodes_with(
+        &mut self,
+        vars: &[ValueId],
+        idf: impl Fn(&HashSet<NodeIndex>) -> HashSet<NodeIndex>,
+    ) {
+        // A failing guard goes to its label with the variables as they were when the speculation started,
+        // which is another definition of whatever the speculation defined, even without a join there
+        let guards = self.guard_regions();
+
+        for &def in vars {
+            let mut def_blocks: HashSet<NodeIndex> = self
+                .blocks
+                .values()
+                .filter(|block| block.has_definition(def))
+                .map(|block| block.node)
                 .collect();
-            println!("{:?} : {:?}", def, def_blocks);
-            let mut done_blocks: HashSet<NodeIndex> = HashSet::new();
-            let mut work_queue: VecDeque<NodeIndex> = VecDeque::from_iter(def_blocks.clone());
-
-            while let Some(block) = work_queue.pop_front() {
-                for &b in df.get(&block).unwrap() {
-                    if !done_blocks.contains(&b) {
-                        let bl = &self.graph[b];
-                        // let incoming_nodes = self
-                        //     .graph
-                        //     .edges_directed(b, Direction::Incoming)
-                        //     .count();
-                        let inst = Instruction::Value {
-                            // args: vec![def.0.clone(); incoming_nodes],
-                            args: vec![],
-                            dest: def.0.clone(),
-                            funcs: vec![],
-                            labels: vec![],
-                            op: ValueOps::Phi,
-                            pos: None,
-                            op_type: def.1.clone(),
-                        };
-                        self.blocks.get_mut(bl).unwrap().insts.insert(0, inst);
-                        done_blocks.insert(b);
-                        println!("{:?} ==> {:?}", def, b);
-                        if !def_blocks.contains(&b) {
-                            work_queue.push_back(b);
-                        }
-                    }
-                }
+            let aborts: HashSet<NodeIndex> = guards
+                .iter()
+                .filter(|(_, region, _)| !region.is_disjoint(&def_blocks))
+                .map(|&(_, _, target)| target)
+                .collect();
+            def_blocks.extend(&aborts);
+
+            for &b in idf(&def_blocks).union(&aborts) {
+                let inst = Inst::new(Op::Value(ValueOps::Phi), Some(def), vec![]);
+                self.insert_inst(b, 0, inst);
             }
         }
     }
 
-    pub fn rename_variables(&mut self, idoms: IDoms) {
-        let all_defs: HashSet<(String, Type)> = self
-            .graph
-            .node_weights()
-            .map(|label| HashSet::from_iter(self.blocks[label].definitions.clone()))
-            .fold(HashSet::new(), |acc, el| acc.union(&el).cloned().collect());
+    pub fn rename_variables(&mut self, idoms: &IDoms) {
+        let vars: Vec<ValueId> = self
+            .all_definitions()
+            .into_iter()
+            .chain(self.args.clone())
+            .collect();
+        self.rename_variables_of(idoms, &vars);
+    }
 
-        let mut reaching_variables: HashMap<String, Vec<usize>> = all_defs
+    // Renaming for the variables in `vars` only, everything else keeps its name
+    pub fn rename_variables_of(&mut self, idoms: &IDoms, vars: &[ValueId]) {
+        // The φ-functions placed for `vars`, which still define the variable itself
+        let placed: HashMap<InstId, ValueId> = self
+            .insts
             .iter()
-            .map(|(var, _)| (var.clone(), vec![0]))
+            .filter(|(_, data)| data.inst.is_phi())
+            .filter_map(|(id, data)| {
+                let dest = data.inst.dest?;
+                vars.contains(&dest).then_some((id, dest))
+            })
             .collect();
+        let renaming = Renaming {
+            values: vars,
+            placed: &placed,
+            new_versions: true,
+            at_end: &HashMap::new(),
+        };
+        self.rename_along_dominator_tree(idoms, &renaming);
 
-        let mut dom_tree: HashMap<NodeIndex, HashSet<NodeIndex>> = HashMap::new();
-        let mut root = NodeIndex::default();
-        for (child, parent) in idoms.into_iter() {
-            if let Some(parent) = parent {
-                let children = dom_tree.entry(parent).or_insert(HashSet::new());
-                children.insert(child);
-            } else {
-                root = child;
-            }
+        for block in self.blocks.values_mut() {
+            block.update_definitions(&self.insts);
         }
+        self.in_ssa = true;
+    }
 
-        // let mut root = NodeIndex::default();
-        // let dom_tree = idoms.into_iter().filter_map(|(child, parent)| {
-        //     if let Some(parent) = parent {
-        //         Some((parent, child))
-        //     } else {
-        //         root = child;
-        //         None
-        //     }
-        // }).collect();
-        // let dom_tree = utils::assoc_list_to_directed_graph(dom_tree);
-
-        println!("reaching_variables: {:?}", reaching_variables);
-        println!("dom_tree: {:?}", dom_tree);
-        println!("root: {:?}", root);
-
-        let mut visited = HashSet::new();
-        self.rename_variables_recursive(&dom_tree, root, &mut reaching_variables, &mut visited);
+    // The walk over the dominator tree that renaming is: every use of a value gets the name of the nearest
+    // definition above it, starting with the value itself for its value on entry, and the φ-functions placed
+    // for it get an argument from every predecessor. The instructions of the blocks `at_end` gives names
+    // for are left alone.
+    pub fn rename_along_dominator_tree(&mut self, idoms: &IDoms, renaming: &Renaming) {
+        let (root, dom_tree) = dominator_tree(idoms);
+        let mut reaching: HashMap<ValueId, Vec<ValueId>> = renaming
+            .values
+            .iter()
+            .map(|&value| (value, vec![value]))
+            .collect();
+        self.rename_recursive(&dom_tree, root, renaming, &mut reaching, &mut Vec::new());
     }
 
-    fn rename_variables_recursive(
+    fn rename_recursive(
         &mut self,
-        dom_tree: &HashMap<NodeIndex, HashSet<NodeIndex>>,
+        dom_tree: &HashMap<NodeIndex, BTreeSet<NodeIndex>>,
         block: NodeIndex,
-        reaching_variables: &mut HashMap<String, Vec<usize>>,
-        visited: &mut HashSet<NodeIndex>,
+        renaming: &Renaming,
+        reaching: &mut HashMap<ValueId, Vec<ValueId>>,
+        // The names every value had when each of the speculations open on this path started
+        speculations: &mut Vec<HashMap<ValueId, ValueId>>,
     ) {
-        println!("current node: {:?}", block);
-        visited.insert(block.clone());
-        let start_versions = reaching_variables
+        let start_depths: HashMap<ValueId, usize> = reaching
             .iter()
-            .map(|(var, versions)| (var.clone(), versions.last().copied().unwrap()))
-            .collect::<HashMap<_, _>>();
-
-        let bl = &self.graph[block];
-        for inst in &mut self.blocks.get_mut(bl).unwrap().insts {
-            if !matches!(
-                inst,
-                Instruction::Value {
-                    op: ValueOps::Phi,
-                    ..
-                }
-            ) {
-                match inst {
-                    Instruction::Value { args, .. } | Instruction::Effect { args, .. } => {
-                        for arg in args {
-                            let latest_version = reaching_variables
-                                .get(arg)
-                                .unwrap()
-                                .last()
-                                .copied()
-                                .unwrap();
-                            *arg = format!("{}.{}", arg, latest_version);
+            .map(|(&value, versions)| (value, versions.len()))
+            .collect();
+        let start_speculations = speculations.clone();
+        let at_end = renaming.at_end.get(&block);
+        let renamed_here = |value: &ValueId| {
+            at_end.is_none_or(|names| names.iter().all(|(defined, _)| defined != value))
+        };
+
+        for id in self.block(block).insts.clone() {
+            if let Some(&value) = renaming.placed.get(&id) {
+                let dest = if renaming.new_versions {
+                    let version = self.values.new_version(value);
+                    self.insts.update(id, |inst| inst.dest = Some(version));
+                    version
+                } else {
+                    self.insts[id].dest.unwrap()
+                };
+                reaching.get_mut(&value).unwrap().push(dest);
+                continue;
+            }
+
+            self.insts.update(id, |inst| {
+                if !inst.is_phi() {
+                    for arg in &mut inst.args {
+                        // Variables that are never defined (not even as an argument) stay as they are
+                        if renamed_here(arg)
+                            && let Some(&latest) = reaching.get(arg).and_then(|versions| versions.last())
+                        {
+                            *arg = latest;
                         }
                     }
-                    _ => {}
                 }
+
+                if renaming.new_versions
+                    && let Some(dest) = &mut inst.dest
+                    && let Some(versions) = reaching.get_mut(dest)
+                {
+                    let version = self.values.new_version(*dest);
+                    versions.push(version);
+                    *dest = version;
+                }
+            });
+
+            match self.insts[id].op {
+                Op::Effect(EffectOps::Speculate) => speculations.push(
+                    reaching
+                        .iter()
+                        .map(|(&value, versions)| (value, *versions.last().unwrap()))
+                        .collect(),
+                ),
+                Op::Effect(EffectOps::Commit) => {
+                    speculations.pop();
+                }
+                _ => {}
             }
+        }
+        for &(value, name) in at_end.into_iter().flatten() {
+            reaching.get_mut(&value).unwrap().push(name);
+        }
 
-            match inst {
-                Instruction::Constant { dest, .. } | Instruction::Value { dest, .. } => {
-                    let prev_version = reaching_variables
-                        .get(dest)
-                        .unwrap()
-                        .last()
+        // A failing guard rolls the variables back to what they were when the speculation started
+        let aborts_to = self
+            .block(block)
+            .terminator(&self.insts)
+            .filter(|&id| self.insts[id].is_guard())
+            .map(|id| self.block_id(&self.insts[id].labels[0]));
+        let rolled_back = speculations.last().filter(|_| aborts_to.is_some());
+
+        let label = self.label(block).clone();
+        for succ in self.successors(block) {
+            let snapshot = rolled_back.filter(|_| aborts_to == Some(succ));
+            let name = |value: &ValueId| {
+                let versions = reaching.get(value)?;
+                Some(match snapshot {
+                    Some(snapshot) => snapshot[value],
+                    None => *versions.last().unwrap(),
+                })
+            };
+            let phis: Vec<InstId> = self.block(succ).phis(&self.insts).collect();
+            for phi in phis {
+                self.insts.update(phi, |inst| {
+                    if let Some(value) = renaming.placed.get(&phi) {
+                        inst.args.push(name(value).unwrap());
+                        inst.labels.push(label.clone());
+                    } else {
+                        for (arg, from) in inst.args.iter_mut().zip(&inst.labels) {
+                            if *from == label
+                                && let Some(latest) = name(arg)
+                            {
+                                *arg = latest;
+                            }
+                        }
+                    }
+                });
+            }
+        }
+
+        if let Some(children) = dom_tree.get(&block) {
+            for &child in children {
+                self.rename_recursive(dom_tree, child, renaming, reaching, speculations);
+            }
+        }
+
+        for (value, versions) in reaching {
+            versions.truncate(start_depths[value]);
+        }
+        *speculations = start_speculations;
+    }
+
+    // Removes the φ-functions in `phis` nothing reads, other than the φ-function itself, until there are no
+    // more of them. Returns the ones that stay.
+    pub fn remove_unused_phis(&mut self, mut phis: HashSet<InstId>) -> HashSet<InstId> {
+        loop {
+            let used: HashSet<ValueId> = self
+                .insts
+                .iter()
+                .flat_map(|(_, data)| {
+                    data.inst
+                        .args
+                        .iter()
                         .copied()
-                        .unwrap();
+                        .filter(move |&arg| Some(arg) != data.inst.dest)
+                })
+                .collect();
+            let dead: Vec<InstId> = phis
+                .iter()
+                .copied()
+                .filter(|&id| !used.contains(&self.insts[id].dest.unwrap()))
+                .collect();
+            if dead.is_empty() {
+                return phis;
+            }
+            for id in dead {
+                phis.remove(&id);
+                self.remove_inst(id);
+            }
+        }
+    }
+
+    // Replaces every φ-function with copies at the end of its predecessors.
+    // The arguments are copied into temporaries first, so that φ-functions that read each other's
+    // results (the swap problem) still see the values from before any of the copies.
+    // Returns whether blocks were added.
+    pub fn remove_phi_nodes(&mut self) -> bool {
+        // Copies at the end of a block ending in a guard run speculatively, and the ones for the block it
+        // aborts to would be rolled back right before they are needed. They get a block of their own.
+        let aborts: Vec<(BlockId, BlockId)> = self
+            .layout
+            .iter()
+            .filter_map(|&block| {
+                let guard = self.block(block).terminator(&self.insts)?;
+                let inst = &self.insts[guard];
+                inst.is_guard()
+                    .then(|| (block, self.block_id(&inst.labels[0])))
+            })
+            .filter(|&(_, target)| self.block(target).phis(&self.insts).next().is_some())
+            .collect();
+        let split = !aborts.is_empty();
+        for (block, target) in aborts {
+            self.split_edge(block, target);
+        }
 
-                    reaching_variables
-                        .get_mut(dest)
-                        .unwrap()
-                        .push(prev_version + 1);
+        let undefined = self.maybe_undefined();
+        let never_defined: HashSet<ValueId> = undefined
+            .iter()
+            .copied()
+            .filter(|&value| self.insts.defs(value).is_empty())
+            .collect();
+        let used = self.used_phis();
+
+        // predecessor label -> (φ destination, argument)
+        let mut copies: HashMap<Label, Vec<(ValueId, ValueId)>> = HashMap::new();
+        for &id in &self.layout {
+            let block = self.blocks.get_mut(&self.graph[id]).unwrap();
+            let phis: Vec<InstId> = block
+                .insts
+                .iter()
+                .copied()
+                .filter(|&inst| self.insts[inst].is_phi())
+                .collect();
+            block.insts.retain(|inst| !phis.contains(inst));
 
-                    *dest = format!("{}.{}", dest, prev_version + 1);
+            for phi in phis {
+                let phi = self.insts.free(phi);
+                let dest = phi.dest.unwrap();
+                if !used.contains(&dest) {
+                    continue;
+                }
+                for (arg, label) in phi.args.into_iter().zip(phi.labels) {
+                    // An argument that is never defined means that the variable is undefined along that edge
+                    if !never_defined.contains(&arg) {
+                        copies.entry(label).or_default().push((dest, arg));
+                    }
                 }
-                _ => {}
             }
         }
 
-        for succ in self
-            .graph
-            .edges_directed(block, Outgoing)
-            .map(|edge| edge.target())
-        {
-            let sbl = &self.graph[succ];
-            for inst in &mut self.blocks.get_mut(sbl).unwrap().insts {
-                if let Instruction::Value {
-                    op: ValueOps::Phi,
-                    dest,
-                    args,
-                    labels,
-                    ..
-                } = inst
-                {
-                    let dest = utils::extract_first_part(dest);
-                    let version = reaching_variables
-                        .get(dest)
-                        .unwrap()
-                        .last()
-                        .copied()
-                        .unwrap();
-                    args.push(format!("{dest}.{version}"));
-                    labels.push(bl.clone());
+        // A φ-function that took an undefined argument leaves its destination undefined, and the copy out of it
+        // into another φ-function would fail on reading it, even where the program never reads that value.
+        // Such values start out as a placeholder of their type on entry instead.
+        let entry = self.block_id(&self.entry_label);
+        let mut placeholders = Vec::new();
+        for (_, arg) in copies.values().flatten() {
+            if undefined.contains(arg)
+                && !placeholders.iter().any(|(value, _)| value == arg)
+                && let Some(literal) = self.values.ty(*arg).and_then(placeholder)
+            {
+                placeholders.push((*arg, literal));
+            }
+        }
+        placeholders.sort_by_key(|&(value, _)| value);
+        for (i, (value, literal)) in placeholders.into_iter().enumerate() {
+            self.insert_inst(entry, i, Inst::new(Op::Const(literal), Some(value), vec![]));
+        }
+
+        // Blocks are visited in layout order, so that the temporaries are numbered the same way every run
+        for &id in &self.layout {
+            let Some(copies) = copies.remove(&self.graph[id]) else {
+                continue;
+            };
+            let temps: Vec<ValueId> = copies
+                .iter()
+                .map(|&(dest, _)| self.values.new_version(dest))
+                .collect();
+            let to_temps = copies
+                .iter()
+                .zip(&temps)
+                .map(|(&(_, arg), &temp)| copy(temp, arg));
+            let finals = copies
+                .iter()
+                .zip(&temps)
+                .map(|(&(dest, _), &temp)| copy(dest, temp));
+
+            let block = self.blocks.get_mut(&self.graph[id]).unwrap();
+            let at = block.end_index(&self.insts);
+            let new_insts: Vec<InstId> = to_temps
+                .chain(finals)
+                .map(|inst| self.insts.alloc(inst, block.node))
+                .collect();
+            block.insts.splice(at..at, new_insts);
+            block.update_definitions(&self.insts);
+        }
+        let versions: Vec<ValueId> = self
+            .values
+            .iter()
+            .filter(|(_, data)| data.version != 0)
+            .map(|(id, _)| id)
+            .collect();
+        for version in versions {
+            self.values.make_variable(version);
+        }
+        self.in_ssa = false;
+        split
+    }
+
+    // Destinations of the φ-functions whose value is read by something other than a φ-function, directly or
+    // through other φ-functions. The rest only feed each other and need no copies.
+    fn used_phis(&self) -> HashSet<ValueId> {
+        let mut phi_args: HashMap<ValueId, &[ValueId]> = HashMap::new();
+        let mut work = Vec::new();
+        for &block in &self.layout {
+            for &id in &self.block(block).insts {
+                let inst = &self.insts[id];
+                if inst.is_phi() {
+                    phi_args.insert(inst.dest.unwrap(), &inst.args);
+                } else {
+                    work.extend(inst.args.iter().copied());
                 }
             }
         }
 
-        if let Some(children) = dom_tree.get(&block) {
-            for child in children {
-                if !visited.contains(child) {
-                    self.rename_variables_recursive(
-                        dom_tree,
-                        child.clone(),
-                        reaching_variables,
-                        visited,
-                    );
+        let mut used = HashSet::new();
+        while let Some(value) = work.pop() {
+            if let Some(args) = phi_args.get(&value)
+                && used.insert(value)
+            {
+                work.extend(args.iter().copied());
+            }
+        }
+        used
+    }
+
+    // Values that are undefined on some path to their definition: the ones without a definition, and the
+    // destinations of φ-functions with such an argument
+    fn maybe_undefined(&self) -> HashSet<ValueId> {
+        let phis: Vec<&Inst> = self
+            .layout
+            .iter()
+            .flat_map(|&block| self.block(block).phis(&self.insts))
+            .map(|phi| &self.insts[phi])
+            .collect();
+        let mut undefined: HashSet<ValueId> = phis
+            .iter()
+            .flat_map(|phi| &phi.args)
+            .copied()
+            .filter(|&arg| self.insts.defs(arg).is_empty() && !self.args.contains(&arg))
+            .collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for phi in &phis {
+                if phi.args.iter().any(|arg| undefined.contains(arg)) {
+                    changed |= undefined.insert(phi.dest.unwrap());
                 }
             }
         }
+        undefined
+    }
 
-        for (var, versions) in reaching_variables {
-            let start_version = start_versions[var];
-            while versions.last() != Some(&start_version) {
-                versions.pop();
+    // Values only get their Bril names here
+    pub fn to_function(&self) -> Function {
+        let names = self.values.names();
+        let mut instrs = Vec::new();
+        for block in self.blocks_in_order() {
+            instrs.push(Code::Label {
+                label: block.label.clone(),
+                pos: None,
+            });
+            for &id in &block.insts {
+                let inst = &self.insts[id];
+                instrs.push(Code::Instruction(inst.to_bril(&self.values, &names)));
+                // Blocks get reordered, the one a guard falls through to may not come next anymore
+                if inst.is_guard() {
+                    let next = jump(inst.labels[1].clone());
+                    instrs.push(Code::Instruction(next.to_bril(&self.values, &names)));
+                }
             }
         }
+
+        Function {
+            args: self
+                .args
+                .iter()
+                .map(|&arg| Argument {
+                    name: names[arg.index()].clone(),
+                    arg_type: self.values.ty(arg).unwrap().clone(),
+                })
+                .collect(),
+            instrs,
+            name: self.name.clone(),
+            pos: None,
+            return_type: self.return_type.clone(),
+        }
     }
 
-    pub fn output_graphviz(&self, filename: &str) {
-        graph_to_svg(filename, &self.graph);
+    // Edges are labelled with their counts, if the function was profiled
+    pub fn graphviz(&self) -> String {
+        graph_to_dot(&self.graph, edge_label)
     }
 
-    // Nodes identify instructions
-    // A tuple of Label (which uniquely identifies the block to which the instruction belongs) and the instruction index within the block
     // To be called only after the SSA construction is completed
-    pub fn ssa_graph<'cfg>(&'cfg self) -> (Graph<(&'cfg Label, usize), (), Directed, u32>, HashMap<&'cfg String, (&'cfg Label, usize)>) {
-        let mut ssa_graph: Graph<(&'cfg Label, usize), ()> = Graph::new();
-        let mut names: HashMap<&String, (&Label, usize)> = HashMap::new();
-        let mut node_indices: HashMap<(&Label, usize), NodeIndex> = HashMap::new();
-
-        for (label, block) in &self.blocks {
-            for (i, inst) in block.insts.iter().enumerate() {
-                let ni = ssa_graph.add_node((label, i));
-                node_indices.insert((label, i), ni);
-                match inst {
-                    Instruction::Constant { dest, .. } | Instruction::Value { dest, .. } => {
-                        names.insert(dest, (label, i));
-                    }
-                    Instruction::Effect { .. } => {}
-                }
+    pub fn ssa_graph(&self) -> SsaGraph {
+        let mut ssa_graph = SsaGraph::default();
+
+        for block in self.blocks_in_order() {
+            for &id in &block.insts {
+                let ni = ssa_graph.graph.add_node(id);
+                ssa_graph.nodes.insert(id, ni);
             }
         }
 
-        for (label, block) in &self.blocks {
-            for (i, inst) in block.insts.iter().enumerate() {
-                match inst {
-                    Instruction::Constant { .. } => {}
-                    Instruction::Value { args, .. } | Instruction::Effect { args, .. } => {
-                        for arg in args {
-                            let src = node_indices[&names[arg]];
-                            let dest = node_indices[&(label, i)];
-                            ssa_graph.add_edge(src, dest, ());
-                        }
-                    }
+        for block in self.blocks_in_order() {
+            for &id in &block.insts {
+                // Function arguments (and undefined variables) have no defining instruction
+                for def in self.insts[id]
+                    .args
+                    .iter()
+                    .filter_map(|&arg| self.insts.def(arg))
+                {
+                    let src = ssa_graph.nodes[&def];
+                    let dest = ssa_graph.nodes[&id];
+                    ssa_graph.graph.add_edge(src, dest, ());
                 }
             }
         }
 
-        (ssa_graph, names)
+        ssa_graph
+    }
+}
+
+// Worklist over the dominance frontiers of the blocks in `blocks`, and of every block added on the way
+pub fn iterated_dominance_frontier(
+    df: &DominanceFrontiers,
+    blocks: &HashSet<NodeIndex>,
+) -> HashSet<NodeIndex> {
+    let mut idf: HashSet<NodeIndex> = HashSet::new();
+    let mut work_queue: VecDeque<NodeIndex> = VecDeque::from_iter(blocks.iter().copied());
+
+    while let Some(block) = work_queue.pop_front() {
+        for &b in df.get(&block).unwrap() {
+            if idf.insert(b) && !blocks.contains(&b) {
+                work_queue.push_back(b);
+            }
+        }
+    }
+    idf
+}
+
+// The root of the dominator tree, and the children of every block in it
+pub fn dominator_tree(idoms: &IDoms) -> (NodeIndex, HashMap<NodeIndex, BTreeSet<NodeIndex>>) {
+    let mut tree: HashMap<NodeIndex, BTreeSet<NodeIndex>> = HashMap::new();
+    let mut root = NodeIndex::default();
+    for (&child, &parent) in idoms {
+        match parent {
+            Some(parent) => {
+                tree.entry(parent).or_default().insert(child);
+            }
+            None => root = child,
+        }
     }
+    (root, tree)
+}
+
+// Whether `a` dominates `b`, by walking up the dominator tree from `b`
+pub fn dominates(idoms: &IDoms, a: NodeIndex, b: NodeIndex) -> bool {
+    let mut runner = Some(b);
+    while let Some(node) = runner {
+        if node == a {
+            return true;
+        }
+        runner = idoms.get(&node).copied().flatten();
+    }
+    false
 }
 
 impl std::fmt::Display for Cfg {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for block in self.blocks.values() {
-            writeln!(f, "{block}")?;
+        let names = self.values.names();
+        for block in self.blocks_in_order() {
+            writeln!(f, "{} ({}):", block.label, block.node.index())?;
+            for &id in &block.insts {
+                writeln!(f, "\t{}", self.insts[id].to_bril(&self.values, &names))?;
+            }
+            writeln!(f)?;
         }
 
-        for node in self.graph.node_indices() {
+        for &node in &self.layout {
             let neighbours: Vec<_> = self.graph.neighbors(node).map(|n| &self.graph[n]).collect();
             writeln!(f, "{} -> {:?}", self.graph[node], neighbours)?;
         }
@@ -404,57 +1065,79 @@ impl std::fmt::Display for Cfg {
     }
 }
 
+#[derive(Default)]
 pub struct CfgBuilder {
     cur_label: String,
+    // Blocks that already have their place in the layout
+    placed: HashSet<BlockId>,
+    // Anonymous blocks are numbered per builder so that names don't depend on which
+    // functions happened to be built before this one (or concurrently with it)
+    next_block: usize,
+    // Every label the function names, which anonymous blocks must not be called
+    taken: HashSet<Label>,
 }
 
 impl CfgBuilder {
     pub fn new() -> Self {
-        Self {
-            cur_label: "".to_string(),
-        }
+        Self::default()
     }
 
-    pub fn build(&mut self, func: Function) -> Cfg {
-        let mut insts = func.instrs.iter().peekable();
+    pub fn build(&mut self, func: &Function) -> Cfg {
+        self.placed.clear();
+        self.taken = func
+            .instrs
+            .iter()
+            .flat_map(|code| match code {
+                Code::Label { label, .. } => std::slice::from_ref(label),
+                Code::Instruction(Instruction::Effect { labels, .. })
+                | Code::Instruction(Instruction::Value { labels, .. }) => labels,
+                Code::Instruction(Instruction::Constant { .. }) => &[],
+            })
+            .cloned()
+            .collect();
 
+        let mut insts = func.instrs.iter().peekable();
         let entry_label = if let Some(Code::Label { label, .. }) = insts.peek() {
             insts.next();
             label.to_owned()
+        } else if self.taken.contains("entry") {
+            self.fresh_label()
         } else {
             "entry".to_string()
         };
 
         let mut cfg = Cfg {
+            name: func.name.clone(),
+            return_type: func.return_type.clone(),
             entry_label: entry_label.clone(),
             ..Default::default()
         };
+        cfg.args = func
+            .args
+            .iter()
+            .map(|arg| cfg.values.define(&arg.name, &arg.arg_type))
+            .collect();
 
         self.add_new_block(&mut cfg, Some(&entry_label), true);
 
         while let Some(inst) = insts.next() {
             if let Code::Instruction(inst) = inst {
                 let block = cfg.blocks.get_mut(&self.cur_label).unwrap();
-                block.insts.push(inst.clone());
-                match inst {
-                    Instruction::Constant {
-                        dest,
-                        const_type: r#type,
-                        ..
-                    }
-                    | Instruction::Value {
-                        dest,
-                        op_type: r#type,
-                        ..
-                    } => {
-                        block.definitions.push((dest.clone(), r#type.clone()));
-                    }
-                    _ => {}
+                let inst = Inst::from_bril(inst, &mut cfg.values);
+                if let Some(dest) = inst.dest
+                    && !block.has_definition(dest)
+                {
+                    block.definitions.push(dest);
                 }
+                block.insts.push(cfg.insts.alloc(inst, block.node));
             }
 
             match inst {
                 Code::Label { label, .. } => {
+                    // Bril lets a block fall through into the next label, make that edge explicit
+                    if cfg.blocks[&self.cur_label].terminator(&cfg.insts).is_none() {
+                        self.terminate_cur_block(&mut cfg, Some(label));
+                    }
                     self.add_new_block(&mut cfg, Some(label), true);
                 }
                 Code::Instruction(Instruction::Effect { op, labels, .. }) => match op {
@@ -478,38 +1161,137 @@ impl CfgBuilder {
                             self.add_new_block(&mut cfg, None, true);
                         }
                     }
+                    // A guard either aborts the speculation and goes to its label, or carries on with
+                    // the next instruction. That one starts a block of its own, named as a second label
+                    // of the guard, so that the guard has a label for every edge like a branch.
+                    EffectOps::Guard => {
+                        self.add_new_edge_from_cur_block(&mut cfg, &labels[0]);
+                        let guard = cfg.blocks[&self.cur_label].terminator(&cfg.insts).unwrap();
+                        let next = match insts.peek() {
+                            Some(Code::Label { label, .. }) => label.clone(),
+                            _ => self.fresh_label(),
+                        };
+                        self.add_new_edge_from_cur_block(&mut cfg, &next);
+                        cfg.insts
+                            .update(guard, |inst| inst.labels.push(next.clone()));
+                        if !matches!(insts.peek(), Some(Code::Label { .. })) {
+                            self.add_new_block(&mut cfg, Some(&next), true);
+                        }
+                    }
                     _ => {}
                 },
                 _ => {}
             }
         }
 
+        if cfg.blocks[&self.cur_label].terminator(&cfg.insts).is_none() {
+            self.terminate_cur_block(&mut cfg, None);
+        }
+
+        // Jump targets that never show up as a label
+        for ni in cfg.graph.node_indices() {
+            if self.placed.insert(ni) {
+                cfg.layout.push(ni);
+            }
+        }
+
+        // A φ-function in the entry block would have no edge to take the initial value from,
+        // so a jump back to the first label gets its own entry block
+        let entry_node = cfg.blocks[&cfg.entry_label].node;
+        if cfg
+            .graph
+            .edges_directed(entry_node, Incoming)
+            .next()
+            .is_some()
+        {
+            let old_entry = cfg.entry_label.clone();
+            cfg.entry_label = self.add_new_block(&mut cfg, None, true).label.clone();
+            self.terminate_cur_block(&mut cfg, Some(&old_entry));
+            cfg.layout.rotate_right(1);
+        }
+
         cfg
     }
 
+    // Ends the current block with a jump to `next`, or a return if there is nothing to fall into
+    fn terminate_cur_block(&mut self, cfg: &mut Cfg, next: Option<&String>) {
+        let inst = match next {
+            Some(next) => {
+                self.add_new_edge_from_cur_block(cfg, next);
+                jump(next.clone())
+            }
+            None => Inst::new(Op::Effect(EffectOps::Return), None, vec![]),
+        };
+        let block = cfg.blocks[&self.cur_label].node;
+        cfg.push_inst(block, inst);
+    }
+
     fn add_new_block<'a>(
         &'a mut self,
         cfg: &'a mut Cfg,
         label: Option<&String>,
         switch_to_new_block: bool,
-    ) -> &mut BasicBlock {
-        let label = label.cloned().unwrap_or_else(get_new_block);
+    ) -> &'a mut BasicBlock {
+        let label = label.cloned().unwrap_or_else(|| self.fresh_label());
         if switch_to_new_block {
             self.cur_label = label.clone();
         }
-        cfg.blocks
+        let block = cfg
+            .blocks
             .entry(label.clone())
             .or_insert_with(|| BasicBlock {
                 node: cfg.graph.add_node(label.clone()),
                 label,
                 insts: Vec::new(),
                 definitions: Vec::new(),
-            })
+            });
+        // Blocks go into the layout where their code starts, not where they are first jumped to
+        if switch_to_new_block && self.placed.insert(block.node) {
+            cfg.layout.push(block.node);
+        }
+        block
+    }
+
+    // The next anonymous block name the function doesn't use itself
+    fn fresh_label(&mut self) -> Label {
+        loop {
+            let label = get_new_block(self.next_block);
+            self.next_block += 1;
+            if !self.taken.contains(&label) {
+                return label;
+            }
+        }
     }
 
     fn add_new_edge_from_cur_block(&mut self, cfg: &mut Cfg, dest: &String) {
         let dest_node = self.add_new_block(cfg, Some(dest), false).node;
         let src_node = cfg.blocks.get(&self.cur_label).unwrap().node;
-        cfg.graph.add_edge(src_node, dest_node, ());
+        cfg.graph.add_edge(src_node, dest_node, CfgEdge::default());
+    }
+}
+
+fn edge_label(edge: &CfgEdge) -> Option<String> {
+    edge.count.map(|count| count.to_string())
+}
+
+// Some value of the type, for a variable whose value doesn't matter. There is no pointer to make out of nothing.
+fn placeholder(ty: &Type) -> Option<Literal> {
+    match ty {
+        Type::Int => Some(Literal::Int(0)),
+        Type::Bool => Some(Literal::Bool(false)),
+        Type::Float => Some(Literal::Float(0.0)),
+        Type::Char => Some(Literal::Char('\0')),
+        Type::Pointer(_) => None,
+    }
+}
+
+pub fn copy(dest: ValueId, src: ValueId) -> Inst {
+    Inst::new(Op::Value(ValueOps::Id), Some(dest), vec![src])
+}
+
+pub fn jump(target: Label) -> Inst {
+    Inst {
+        labels: vec![target],
+        ..Inst::new(Op::Effect(EffectOps::Jump), None, vec![])
     }
 }
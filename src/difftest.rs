@@ -0,0 +1,304 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use bril_rs::{load_program_from_read, Code, Program};
+
+use crate::cfg::{Cfg, CfgBuilder};
+use crate::interp::{parse_arg, Error, ErrorKind, Interpreter};
+use crate::passes::PassManager;
+use crate::text::{parse_program, program_to_text};
+
+// Differential testing of the passes against the interpreter.
+//
+// The programs come as a directory laid out like the Bril benchmarks, which are tested with turnt:
+// `NAME.json` is the program (what bril2json makes of `NAME.bril`), `NAME.out` is what it prints,
+// and an `# ARGS:` line in `NAME.bril` holds the arguments of main. Without a JSON file, the program
+// is read from `NAME.bril` itself.
+//
+// Every program runs once as it is and once after each pipeline of passes. A pipeline fails on a program if
+// that changes what it prints, what main returns or whether it runs into an error (a read of an undefined
+// variable may go away), or if a pass panics or leaves something the verifiers reject. The program is then
+// cut down by delta debugging to as few instructions as still make the pipeline fail.
+// "Simplifying and Isolating Failure-Inducing Input", Zeller and Hildebrandt, TSE 2002
+
+pub const PIPELINES: &[&str] = &[
+    "to-ssa",
+    "to-ssa,from-ssa",
+    "to-ssa-braun,from-ssa",
+    "to-ssa,sccp,dce,from-ssa",
+    "pre,to-ssa,from-ssa",
+    "pre,to-ssa,sccp,from-ssa",
+    "to-ssa,from-ssa,to-ssa,sccp,dce,from-ssa",
+    "to-ssa,from-ssa,pre,to-ssa,from-ssa",
+    "to-ssa,unroll,from-ssa",
+    "to-ssa,lcssa,from-ssa",
+    "canonicalize-loops,to-ssa,canonicalize-loops,from-ssa",
+    "to-ssa,mem2reg,mem-opt,dce,from-ssa",
+    "mem2reg,to-ssa,from-ssa",
+    "speculate,to-ssa,from-ssa",
+    "speculate,to-ssa,from-ssa,to-ssa",
+    "profile-layout",
+];
+
+// Programs that run longer than this are taken not to terminate
+const MAX_INSTRUCTIONS: u64 = 10_000_000;
+
+// What the program printed and what main returned, or the error it ran into
+pub type Outcome = Result<(String, Option<String>), Error>;
+
+// Returns the report, and the number of failures in it
+pub fn test_directory(dir: &str, pipelines: &[&str]) -> Result<(String, usize), String> {
+    let mut programs: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|err| format!("cannot read {dir}: {err}"))?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            path.extension().is_some_and(|ext| ext == "json")
+                || path.extension().is_some_and(|ext| ext == "bril")
+                    && !path.with_extension("json").exists()
+        })
+        .collect();
+    programs.sort();
+    let managers = pipelines
+        .iter()
+        .map(|pipeline| {
+            let mut pm = PassManager::parse(pipeline)?;
+            pm.verify_each = true;
+            Ok(pm)
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    // Panics are failures like any other, and delta debugging runs into a lot of them
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let mut report = String::new();
+    let mut failures = 0;
+    for path in &programs {
+        failures += test_program(path, pipelines, &managers, &mut report);
+    }
+    panic::set_hook(hook);
+
+    writeln!(
+        report,
+        "{} programs, {} pipelines, {failures} failures",
+        programs.len(),
+        pipelines.len()
+    )
+    .unwrap();
+    Ok((report, failures))
+}
+
+fn test_program(
+    path: &Path,
+    pipelines: &[&str],
+    managers: &[PassManager],
+    report: &mut String,
+) -> usize {
+    let name = path.file_stem().unwrap().to_string_lossy();
+    let program = if path.extension().is_some_and(|ext| ext == "bril") {
+        fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| parse_program(&text))
+    } else {
+        File::open(path)
+            .map_err(|err| err.to_string())
+            .and_then(|file| catch_panic(|| Ok(load_program_from_read(file))))
+    };
+    let program = match program {
+        Ok(program) => program,
+        Err(err) => {
+            writeln!(report, "{name}: cannot load: {err}").unwrap();
+            return 1;
+        }
+    };
+    let args = read_args(&path.with_extension("bril"));
+
+    let original = run(&program, &args);
+    if let Err(err) = &original
+        && err.kind == ErrorKind::TooManyInstructions
+    {
+        writeln!(report, "{name}: skipped, {err}").unwrap();
+        return 0;
+    }
+    let mut failures = 0;
+    if let Ok(expected) = fs::read_to_string(path.with_extension("out"))
+        && let Ok((output, _)) = &original
+        && *output != expected
+    {
+        writeln!(
+            report,
+            "{name}: FAIL before any pass, prints\n{output}instead of\n{expected}"
+        )
+        .unwrap();
+        failures += 1;
+    }
+
+    for (pipeline, pm) in pipelines.iter().zip(managers) {
+        let Some(problem) = check(&program, &args, pm, &original) else {
+            writeln!(report, "{name} [{pipeline}]: ok").unwrap();
+            continue;
+        };
+        failures += 1;
+        writeln!(report, "{name} [{pipeline}]: FAIL {problem}").unwrap();
+
+        // A smaller program has to fail in the same way: if the original ran fine, so does it
+        let minimized = minimize(&program, |variant| {
+            let outcome = run(variant, &args);
+            (outcome.is_ok() || original.is_err()) && check(variant, &args, pm, &outcome).is_some()
+        });
+        writeln!(report, "minimized:\n{}", program_to_text(&minimized)).unwrap();
+    }
+    failures
+}
+
+// The arguments of main, from the `# ARGS:` line turnt reads them from
+fn read_args(bril: &Path) -> Vec<String> {
+    let Ok(text) = fs::read_to_string(bril) else {
+        return Vec::new();
+    };
+    text.lines()
+        .find_map(|line| line.split_once("ARGS:"))
+        .map(|(_, args)| args.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+// What is wrong with the program after the passes, if anything
+pub fn check(
+    program: &Program,
+    args: &[String],
+    pm: &PassManager,
+    original: &Outcome,
+) -> Option<String> {
+    let transformed = match transform(program, pm) {
+        Ok(transformed) => transformed,
+        Err(err) => return Some(err),
+    };
+    match (original, &run(&transformed, args)) {
+        (Ok((output, result)), Ok((new_output, new_result))) => {
+            if output != new_output {
+                Some(format!("prints\n{new_output}instead of\n{output}"))
+            } else if result != new_result {
+                Some(format!("returns {new_result:?} instead of {result:?}"))
+            } else {
+                None
+            }
+        }
+        (Ok(_), Err(err)) => Some(format!("fails: {err}")),
+        // An undefined variable has no value to keep, passes may give it any (from-ssa does, with its
+        // placeholders)
+        (Err(err), Ok(_)) if err.kind == ErrorKind::UndefinedVariable => None,
+        (Err(err), Ok(_)) => Some(format!("no longer fails with: {err}")),
+        (Err(_), Err(_)) => None,
+    }
+}
+
+// The program after the passes, written out and read back like it would be between tools
+fn transform(program: &Program, pm: &PassManager) -> Result<Program, String> {
+    catch_panic(|| {
+        let mut functions = Vec::with_capacity(program.functions.len());
+        for func in &program.functions {
+            let mut cfg = CfgBuilder::new().build(func);
+            pm.run(&mut cfg, |_, _, _| {})
+                .map_err(|err| format!("@{}: {err}", cfg.name))?;
+            functions.push(cfg.to_function());
+        }
+        Ok(Program {
+            functions,
+            ..program.clone()
+        })
+    })
+}
+
+pub fn run(program: &Program, args: &[String]) -> Outcome {
+    catch_panic(|| Ok(run_program(program, args))).unwrap_or_else(|panic| Err(panic.into()))
+}
+
+fn run_program(program: &Program, args: &[String]) -> Outcome {
+    let cfgs: Vec<Cfg> = program
+        .functions
+        .iter()
+        .map(|func| CfgBuilder::new().build(func))
+        .collect();
+    let main = cfgs
+        .iter()
+        .find(|cfg| cfg.name == "main")
+        .ok_or("no @main")?;
+    if main.args.len() != args.len() {
+        return Err(format!("@main takes {} arguments", main.args.len()).into());
+    }
+    let args = main
+        .args
+        .iter()
+        .zip(args)
+        .map(|(&arg, text)| parse_arg(text, main.values.ty(arg).unwrap()))
+        .collect::<Result<_, _>>()?;
+
+    let mut interpreter = Interpreter::new(&cfgs);
+    interpreter.max_instructions = Some(MAX_INSTRUCTIONS);
+    let execution = interpreter.run("main", args)?;
+    let result = execution.result.map(|value| value.to_string());
+    Ok((execution.output, result))
+}
+
+pub fn catch_panic<T>(f: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(format!("panicked: {message}"))
+    })
+}
+
+// Delta debugging over the instructions of all functions, labels stay. Chunks of the instructions that are
+// left get removed for as long as `fails` holds, with smaller chunks when no chunk can go.
+pub fn minimize(program: &Program, fails: impl Fn(&Program) -> bool) -> Program {
+    let with = |kept: &[(usize, usize)]| {
+        let kept: HashSet<&(usize, usize)> = kept.iter().collect();
+        let mut variant = program.clone();
+        for (i, func) in variant.functions.iter_mut().enumerate() {
+            let mut j = 0;
+            func.instrs.retain(|code| {
+                let keep = matches!(code, Code::Label { .. }) || kept.contains(&(i, j));
+                j += 1;
+                keep
+            });
+        }
+        variant
+    };
+    let mut kept: Vec<(usize, usize)> = program
+        .functions
+        .iter()
+        .enumerate()
+        .flat_map(|(i, func)| {
+            func.instrs
+                .iter()
+                .enumerate()
+                .filter(|(_, code)| matches!(code, Code::Instruction(_)))
+                .map(move |(j, _)| (i, j))
+        })
+        .collect();
+
+    let mut chunks = 2;
+    while kept.len() >= 2 {
+        let size = kept.len().div_ceil(chunks);
+        let smaller = (0..kept.len()).step_by(size).find_map(|start| {
+            let end = (start + size).min(kept.len());
+            let rest: Vec<(usize, usize)> =
+                kept[..start].iter().chain(&kept[end..]).copied().collect();
+            fails(&with(&rest)).then_some(rest)
+        });
+        match smaller {
+            Some(rest) => {
+                kept = rest;
+                chunks = (chunks - 1).max(2);
+            }
+            None if chunks >= kept.len() => break,
+            None => chunks = (chunks * 2).min(kept.len()),
+        }
+    }
+    with(&kept)
+}
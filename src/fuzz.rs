@@ -0,0 +1,777 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::panic;
+
+use bril_rs::{Code, EffectOps, Function, Instruction, Program, Type, ValueOps};
+
+use crate::cfg::CfgBuilder;
+use crate::difftest;
+use crate::interp::ErrorKind;
+use crate::passes::PassManager;
+use crate::programs::{constant, effect, float_constant, value};
+use crate::text::program_to_text;
+use crate::verify::Diagnostic;
+
+// Random programs for SSA construction and the passes after it.
+//
+// A program is a single @main without arguments, made of straight-line code, if-else, do-while loops nested up
+// to MAX_DEPTH deep and early returns, over a pool of VARIABLES integer and boolean variables, FLOATS float
+// variables and memory. On top of that:
+// - branches that jump forward into the middle of a loop body, past its header, which makes the loop irreducible
+// - blocks nothing jumps to, which jump back into the rest of the function
+// - definitions nothing uses
+// - sometimes a variable that is not defined on entry, so that paths reach a use without a definition. It is
+//   assigned somewhere else: verify_ssa takes a use of a variable nothing defines for a mistake.
+// - ALLOCATIONS allocations made on entry with every cell stored to, and freed before every return. The first
+//   one has a single cell, only ever loaded from and stored to directly, which mem2reg can promote. The others
+//   have CELLS cells, used directly and through POINTERS pointers that get moved between them.
+// - speculations of straight-line code or an if-else, ending in a guard and a commit. The guard aborts to the
+//   block after them, or to a block of its own that goes there.
+//
+// Every loop counts with a counter of its own that only its preheader resets and only its latch changes, and
+// the only jumps backwards are the ones from latches to headers, so every program terminates (or stops at a use
+// of an undefined variable).
+//
+// Each program goes through CFG construction, dominators, φ-placement and renaming (checked by the verifiers,
+// and against Braun et al.), then through every pipeline the way ssa-difftest runs them. A program that fails any
+// of this is minimized, like in difftest.
+
+const MAX_DEPTH: usize = 5;
+const VARIABLES: usize = 8;
+const FLOATS: usize = 4;
+const ALLOCATIONS: usize = 3;
+const CELLS: usize = 4;
+const POINTERS: usize = 3;
+const MAX_TRIPS: i64 = 3;
+const REGION_STATEMENTS: usize = 6;
+// Statements in a whole program, loops and branches included
+const STATEMENTS: usize = 60;
+
+// SplitMix64, the programs for a seed have to be the same everywhere
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    // True one time out of `n`
+    fn one_in(&mut self, n: usize) -> bool {
+        self.below(n) == 0
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+// A branch whose first label may be pointed somewhere else once the whole function is there
+struct Site {
+    index: usize,
+    loops: Vec<usize>,
+}
+
+// A label in the middle of a loop body
+struct Target {
+    index: usize,
+    label: String,
+    loops: Vec<usize>,
+}
+
+struct Generator {
+    rng: Rng,
+    instrs: Vec<Code>,
+    labels: usize,
+    loops: usize,
+    // The loops around the current statement, innermost last
+    open_loops: Vec<usize>,
+    budget: usize,
+    dead: usize,
+    sites: Vec<Site>,
+    targets: Vec<Target>,
+    // Every label so far, for the jumps out of unreachable blocks
+    all_labels: Vec<String>,
+    // The variables assigned outside of unreachable blocks
+    assigned: HashSet<String>,
+}
+
+pub fn random_program(seed: u64) -> Program {
+    let mut generator = Generator {
+        rng: Rng(seed),
+        instrs: Vec::new(),
+        labels: 0,
+        loops: 0,
+        open_loops: Vec::new(),
+        budget: STATEMENTS,
+        dead: 0,
+        sites: Vec::new(),
+        targets: Vec::new(),
+        all_labels: Vec::new(),
+        assigned: HashSet::new(),
+    };
+    generator.region(0);
+    for var in (0..VARIABLES)
+        .map(int_var)
+        .chain((0..FLOATS).map(float_var))
+    {
+        generator
+            .instrs
+            .push(effect(EffectOps::Print, &[&var], vec![]));
+    }
+    generator.free();
+    generator.side_entries();
+
+    // Definitions on entry: the loop counters, and the variables but maybe one.
+    // Any more and most programs would stop at a use of an undefined variable right away.
+    let mut instrs = vec![constant("one", 1)];
+    for l in 0..generator.loops {
+        instrs.push(constant(&counter(l), 0));
+    }
+    let candidates: Vec<usize> = (0..2 * VARIABLES)
+        .filter(|&i| {
+            let var = if i < VARIABLES {
+                int_var(i)
+            } else {
+                bool_var(i - VARIABLES)
+            };
+            generator.assigned.contains(&var)
+        })
+        .collect();
+    let undefined = (!candidates.is_empty() && generator.rng.one_in(4))
+        .then(|| *generator.rng.pick(&candidates));
+    for i in 0..VARIABLES {
+        let x = int_var(i);
+        if undefined != Some(i) {
+            instrs.push(constant(&x, generator.rng.below(10) as i64));
+        }
+        if undefined != Some(VARIABLES + i) {
+            let cmp = if undefined == Some(i) { "one" } else { &x };
+            instrs.push(value(ValueOps::Lt, &bool_var(i), &["one", cmp], Type::Bool));
+        }
+    }
+    for i in 0..FLOATS {
+        let x = generator.rng.below(40) as f64 / 4.0 - 5.0;
+        instrs.push(float_constant(&float_var(i), x));
+    }
+
+    // Cells start out holding their offset, the single one of the first allocation 1
+    let pointer = Type::Pointer(Box::new(Type::Int));
+    instrs.push(constant("cells", CELLS as i64));
+    for o in 0..CELLS {
+        instrs.push(constant(&offset(o), o as i64));
+    }
+    for k in 0..ALLOCATIONS {
+        let size = if k == 0 { "one" } else { "cells" };
+        instrs.push(value(
+            ValueOps::Alloc,
+            &alloc_var(k),
+            &[size],
+            pointer.clone(),
+        ));
+    }
+    instrs.push(effect(EffectOps::Store, &[&alloc_var(0), "one"], vec![]));
+    for k in 1..ALLOCATIONS {
+        for o in 0..CELLS {
+            let args = [alloc_var(k), offset(o)];
+            instrs.push(value(
+                ValueOps::PtrAdd,
+                "cell",
+                &[&args[0], &args[1]],
+                pointer.clone(),
+            ));
+            instrs.push(effect(EffectOps::Store, &["cell", &args[1]], vec![]));
+        }
+    }
+    for j in 0..POINTERS {
+        let (k, o) = (1 + j % (ALLOCATIONS - 1), generator.rng.below(CELLS));
+        instrs.push(value(
+            ValueOps::PtrAdd,
+            &pointer_var(j),
+            &[&alloc_var(k), &offset(o)],
+            pointer.clone(),
+        ));
+    }
+    instrs.append(&mut generator.instrs);
+
+    let main = Function {
+        args: vec![],
+        instrs,
+        name: "main".to_string(),
+        pos: None,
+        return_type: None,
+    };
+    Program {
+        functions: vec![main],
+        imports: vec![],
+    }
+}
+
+fn int_var(i: usize) -> String {
+    format!("v{i}")
+}
+
+fn bool_var(i: usize) -> String {
+    format!("b{i}")
+}
+
+fn float_var(i: usize) -> String {
+    format!("f{i}")
+}
+
+fn counter(l: usize) -> String {
+    format!("i{l}")
+}
+
+fn alloc_var(k: usize) -> String {
+    format!("m{k}")
+}
+
+fn pointer_var(j: usize) -> String {
+    format!("p{j}")
+}
+
+fn offset(o: usize) -> String {
+    format!("o{o}")
+}
+
+impl Generator {
+    fn fresh_label(&mut self, name: &str) -> String {
+        self.labels += 1;
+        format!("{name}{}", self.labels)
+    }
+
+    // Starts a block. Labels inside loops can be jumped to from outside, unless they are headers.
+    fn label(&mut self, label: &str, header: bool) {
+        if !header && !self.open_loops.is_empty() {
+            self.targets.push(Target {
+                index: self.instrs.len(),
+                label: label.to_string(),
+                loops: self.open_loops.clone(),
+            });
+        }
+        self.all_labels.push(label.to_string());
+        self.instrs.push(Code::Label {
+            label: label.to_string(),
+            pos: None,
+        });
+    }
+
+    fn int(&mut self) -> String {
+        int_var(self.rng.below(VARIABLES))
+    }
+
+    fn bool(&mut self) -> String {
+        bool_var(self.rng.below(VARIABLES))
+    }
+
+    fn float(&mut self) -> String {
+        float_var(self.rng.below(FLOATS))
+    }
+
+    // An allocation, or a pointer into one
+    fn pointer(&mut self) -> String {
+        let i = self.rng.below(ALLOCATIONS + POINTERS);
+        if i < ALLOCATIONS {
+            alloc_var(i)
+        } else {
+            pointer_var(i - ALLOCATIONS)
+        }
+    }
+
+    fn region(&mut self, depth: usize) {
+        let statements = 1 + self.rng.below(REGION_STATEMENTS);
+        for _ in 0..statements {
+            if self.budget == 0 {
+                return;
+            }
+            self.budget -= 1;
+            self.statement(depth);
+        }
+    }
+
+    fn statement(&mut self, depth: usize) {
+        let nested = depth < MAX_DEPTH;
+        match self.rng.below(24) {
+            0..=7 => {
+                let dest = self.assignment();
+                self.assigned.insert(dest);
+            }
+            8 => {
+                // Nothing ever uses it
+                self.dead += 1;
+                let dest = format!("dead{}", self.dead);
+                let (a, b) = (self.int(), self.int());
+                self.instrs
+                    .push(value(ValueOps::Add, &dest, &[&a, &b], Type::Int));
+            }
+            9 => {
+                let var = match self.rng.below(3) {
+                    0 => self.int(),
+                    1 => self.bool(),
+                    _ => self.float(),
+                };
+                self.instrs.push(effect(EffectOps::Print, &[&var], vec![]));
+            }
+            10..=12 if nested => self.if_else(depth),
+            13..=15 if nested => self.do_while(depth),
+            16 => {
+                // A new block, and maybe a side entry into a loop later on
+                let next = self.fresh_label("next");
+                let cond = self.bool();
+                self.sites.push(Site {
+                    index: self.instrs.len(),
+                    loops: self.open_loops.clone(),
+                });
+                self.instrs.push(effect(
+                    EffectOps::Branch,
+                    &[&cond],
+                    vec![next.clone(), next.clone()],
+                ));
+                self.label(&next, false);
+            }
+            17 => self.unreachable(),
+            18 => {
+                let (ret, rest) = (self.fresh_label("ret"), self.fresh_label("rest"));
+                let cond = self.bool();
+                self.instrs.push(effect(
+                    EffectOps::Branch,
+                    &[&cond],
+                    vec![ret.clone(), rest.clone()],
+                ));
+                self.label(&ret, false);
+                let var = self.int();
+                self.instrs.push(effect(EffectOps::Print, &[&var], vec![]));
+                self.free();
+                self.instrs.push(effect(EffectOps::Return, &[], vec![]));
+                self.label(&rest, false);
+            }
+            20 | 21 => {
+                if let Some(dest) = self.memory() {
+                    self.assigned.insert(dest);
+                }
+            }
+            22 | 23 => self.speculation(),
+            _ => {
+                let dest = self.assignment();
+                self.assigned.insert(dest);
+            }
+        }
+    }
+
+    // Returns the variable it assigns
+    fn assignment(&mut self) -> String {
+        if self.rng.one_in(5) {
+            return self.float_assignment();
+        }
+        if self.rng.one_in(2) {
+            let dest = self.int();
+            if self.rng.one_in(4) {
+                let literal = self.rng.below(100) as i64;
+                self.instrs.push(constant(&dest, literal));
+                return dest;
+            }
+            let op = *self
+                .rng
+                .pick(&[ValueOps::Add, ValueOps::Sub, ValueOps::Mul, ValueOps::Id]);
+            let args = if matches!(op, ValueOps::Id) {
+                vec![self.int()]
+            } else {
+                vec![self.int(), self.int()]
+            };
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            self.instrs.push(value(op, &dest, &args, Type::Int));
+            dest
+        } else {
+            let dest = self.bool();
+            let (op, args) = match self.rng.below(4) {
+                0 => (ValueOps::Not, vec![self.bool()]),
+                1 => (
+                    *self.rng.pick(&[ValueOps::And, ValueOps::Or]),
+                    vec![self.bool(), self.bool()],
+                ),
+                _ => (
+                    *self.rng.pick(&[
+                        ValueOps::Eq,
+                        ValueOps::Lt,
+                        ValueOps::Gt,
+                        ValueOps::Le,
+                        ValueOps::Ge,
+                    ]),
+                    vec![self.int(), self.int()],
+                ),
+            };
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            self.instrs.push(value(op, &dest, &args, Type::Bool));
+            dest
+        }
+    }
+
+    fn float_assignment(&mut self) -> String {
+        if self.rng.one_in(3) {
+            let dest = self.bool();
+            let op = *self.rng.pick(&[
+                ValueOps::Feq,
+                ValueOps::Flt,
+                ValueOps::Fgt,
+                ValueOps::Fle,
+                ValueOps::Fge,
+            ]);
+            let (a, b) = (self.float(), self.float());
+            self.instrs.push(value(op, &dest, &[&a, &b], Type::Bool));
+            return dest;
+        }
+        let dest = self.float();
+        if self.rng.one_in(4) {
+            let literal = self.rng.below(40) as f64 / 4.0 - 5.0;
+            self.instrs.push(float_constant(&dest, literal));
+            return dest;
+        }
+        let op = *self.rng.pick(&[
+            ValueOps::Fadd,
+            ValueOps::Fsub,
+            ValueOps::Fmul,
+            ValueOps::Fdiv,
+        ]);
+        let (a, b) = (self.float(), self.float());
+        self.instrs.push(value(op, &dest, &[&a, &b], Type::Float));
+        dest
+    }
+
+    // A load, a store, or a pointer moved to another cell. Returns the variable a load assigns.
+    fn memory(&mut self) -> Option<String> {
+        let pointer = Type::Pointer(Box::new(Type::Int));
+        match self.rng.below(4) {
+            0 => {
+                let (dest, from) = (self.int(), self.pointer());
+                self.instrs
+                    .push(value(ValueOps::Load, &dest, &[&from], Type::Int));
+                Some(dest)
+            }
+            1 | 2 => {
+                let (to, var) = (self.pointer(), self.int());
+                self.instrs
+                    .push(effect(EffectOps::Store, &[&to, &var], vec![]));
+                None
+            }
+            _ => {
+                let dest = pointer_var(self.rng.below(POINTERS));
+                let alloc = alloc_var(1 + self.rng.below(ALLOCATIONS - 1));
+                let at = offset(self.rng.below(CELLS));
+                self.instrs
+                    .push(value(ValueOps::PtrAdd, &dest, &[&alloc, &at], pointer));
+                None
+            }
+        }
+    }
+
+    // Before every return
+    fn free(&mut self) {
+        for k in 0..ALLOCATIONS {
+            self.instrs
+                .push(effect(EffectOps::Free, &[&alloc_var(k)], vec![]));
+        }
+    }
+
+    // Its blocks are neither sites nor targets: a jump into the middle of a speculation would commit one
+    // that never started
+    fn speculation(&mut self) {
+        let join = self.fresh_label("join");
+        let abort = self.rng.one_in(2).then(|| self.fresh_label("abort"));
+        self.instrs.push(effect(EffectOps::Speculate, &[], vec![]));
+        if self.rng.one_in(3) {
+            let then = self.fresh_label("sthen");
+            let otherwise = self.fresh_label("selse");
+            let after = self.fresh_label("sjoin");
+            let cond = self.bool();
+            self.instrs.push(effect(
+                EffectOps::Branch,
+                &[&cond],
+                vec![then.clone(), otherwise.clone()],
+            ));
+            for label in [then, otherwise] {
+                self.instrs.push(Code::Label { label, pos: None });
+                self.speculated_code();
+                self.instrs
+                    .push(effect(EffectOps::Jump, &[], vec![after.clone()]));
+            }
+            self.instrs.push(Code::Label {
+                label: after,
+                pos: None,
+            });
+        } else {
+            self.speculated_code();
+        }
+
+        let cond = self.bool();
+        let to = abort.clone().unwrap_or_else(|| join.clone());
+        self.instrs
+            .push(effect(EffectOps::Guard, &[&cond], vec![to]));
+        self.instrs.push(effect(EffectOps::Commit, &[], vec![]));
+        if let Some(abort) = abort {
+            self.instrs
+                .push(effect(EffectOps::Jump, &[], vec![join.clone()]));
+            self.label(&abort, false);
+            let var = self.int();
+            self.instrs.push(effect(EffectOps::Print, &[&var], vec![]));
+            self.instrs
+                .push(effect(EffectOps::Jump, &[], vec![join.clone()]));
+        }
+        self.label(&join, false);
+    }
+
+    // Assignments, loads and stores: nothing that prints or leaves the function
+    fn speculated_code(&mut self) {
+        for _ in 0..1 + self.rng.below(3) {
+            let dest = if self.rng.one_in(3) {
+                self.memory()
+            } else {
+                Some(self.assignment())
+            };
+            self.assigned.extend(dest);
+        }
+    }
+
+    fn if_else(&mut self, depth: usize) {
+        let then = self.fresh_label("then");
+        let join = self.fresh_label("join");
+        let otherwise = (!self.rng.one_in(3)).then(|| self.fresh_label("else"));
+        let cond = self.bool();
+        let no = otherwise.clone().unwrap_or_else(|| join.clone());
+        self.instrs
+            .push(effect(EffectOps::Branch, &[&cond], vec![then.clone(), no]));
+        self.label(&then, false);
+        self.region(depth + 1);
+        self.instrs
+            .push(effect(EffectOps::Jump, &[], vec![join.clone()]));
+        if let Some(otherwise) = otherwise {
+            self.label(&otherwise, false);
+            self.region(depth + 1);
+            self.instrs
+                .push(effect(EffectOps::Jump, &[], vec![join.clone()]));
+        }
+        self.label(&join, false);
+    }
+
+    fn do_while(&mut self, depth: usize) {
+        let l = self.loops;
+        self.loops += 1;
+        let (header, exit) = (self.fresh_label("header"), self.fresh_label("exit"));
+        let (i, trips, more) = (counter(l), format!("trips{l}"), format!("more{l}"));
+
+        self.instrs.push(constant(&i, 0));
+        self.instrs
+            .push(effect(EffectOps::Jump, &[], vec![header.clone()]));
+        self.label(&header, true);
+        self.open_loops.push(l);
+        self.region(depth + 1);
+        self.open_loops.pop();
+
+        let limit = 1 + self.rng.below(MAX_TRIPS as usize) as i64;
+        self.instrs
+            .push(value(ValueOps::Add, &i, &[&i, "one"], Type::Int));
+        self.instrs.push(constant(&trips, limit));
+        self.instrs
+            .push(value(ValueOps::Lt, &more, &[&i, &trips], Type::Bool));
+        self.instrs.push(effect(
+            EffectOps::Branch,
+            &[&more],
+            vec![header, exit.clone()],
+        ));
+        self.label(&exit, false);
+    }
+
+    // A block after a jump that skips it, which goes anywhere it likes: it never runs
+    fn unreachable(&mut self) {
+        let (dead, after) = (self.fresh_label("unreachable"), self.fresh_label("after"));
+        self.instrs
+            .push(effect(EffectOps::Jump, &[], vec![after.clone()]));
+        // Not a target, a side entry into it would make it reachable
+        self.all_labels.push(dead.clone());
+        self.instrs.push(Code::Label {
+            label: dead,
+            pos: None,
+        });
+        self.assignment();
+        let to = self.rng.pick(&self.all_labels).clone();
+        self.instrs.push(effect(EffectOps::Jump, &[], vec![to]));
+        self.label(&after, false);
+    }
+
+    // Points some of the sites forward into the middle of a loop they are not in
+    fn side_entries(&mut self) {
+        for site in &self.sites {
+            let targets: Vec<&Target> = self
+                .targets
+                .iter()
+                .filter(|target| target.index > site.index)
+                .filter(|target| target.loops.iter().any(|l| !site.loops.contains(l)))
+                .collect();
+            if targets.is_empty() || self.rng.one_in(3) {
+                continue;
+            }
+            let target = self.rng.pick(&targets).label.clone();
+            if let Code::Instruction(Instruction::Effect { labels, .. }) =
+                &mut self.instrs[site.index]
+            {
+                labels[0] = target;
+            }
+        }
+    }
+}
+
+// Runs the programs for `count` seeds from `seed` on. Returns the report, and the number of failures in it.
+pub fn fuzz(seed: u64, count: u64, pipelines: &[&str]) -> Result<(String, usize), String> {
+    let managers = pipelines
+        .iter()
+        .map(|pipeline| {
+            let mut pm = PassManager::parse(pipeline)?;
+            pm.verify_each = true;
+            Ok(pm)
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let mut report = String::new();
+    let mut failures = 0;
+    for seed in seed..seed.saturating_add(count) {
+        let program = random_program(seed);
+        let Some(failure) = check_program(&program, pipelines, &managers) else {
+            continue;
+        };
+        failures += 1;
+        writeln!(report, "seed {seed}: FAIL {failure}").unwrap();
+
+        // A smaller program has to fail at the same stage in the same way, and if the program ran fine,
+        // so does the smaller one
+        let ran = difftest::run(&program, &[]).is_ok();
+        let minimized = difftest::minimize(&program, |variant| {
+            (!ran || difftest::run(variant, &[]).is_ok())
+                && check_program(variant, pipelines, &managers)
+                    .is_some_and(|other| other.same_as(&failure))
+        });
+        writeln!(report, "minimized:\n{}", program_to_text(&minimized)).unwrap();
+    }
+    panic::set_hook(hook);
+
+    writeln!(report, "{count} programs, {failures} failures").unwrap();
+    Ok((report, failures))
+}
+
+// Where a program goes wrong and how
+struct Failure {
+    // "construction", or the pipeline that went wrong
+    stage: String,
+    // What kind of failure it is, without the details: the kind of the first diagnostic, whether the
+    // output changed, ...
+    kind: String,
+    message: String,
+}
+
+impl Failure {
+    fn same_as(&self, other: &Failure) -> bool {
+        self.stage == other.stage && self.kind == other.kind
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}", self.stage, self.message)
+    }
+}
+
+// What goes wrong with the program, if anything
+fn check_program(
+    program: &Program,
+    pipelines: &[&str],
+    managers: &[PassManager],
+) -> Option<Failure> {
+    for func in &program.functions {
+        let (kind, message) = match difftest::catch_panic(|| Ok(check_construction(func))) {
+            Ok(Ok(())) => continue,
+            Ok(Err(diagnostics)) => (format!("{:?}", diagnostics[0].kind), describe(diagnostics)),
+            Err(err) => ("panic".to_string(), err),
+        };
+        return Some(Failure {
+            stage: "construction".to_string(),
+            kind,
+            message: format!("@{}: {message}", func.name),
+        });
+    }
+    // The programs from the generator terminate, the ones minimize makes of them need not
+    let original = difftest::run(program, &[]);
+    if let Err(err) = &original
+        && err.kind == ErrorKind::TooManyInstructions
+    {
+        return None;
+    }
+    pipelines.iter().zip(managers).find_map(|(pipeline, pm)| {
+        let message = difftest::check(program, &[], pm, &original)?;
+        Some(Failure {
+            stage: pipeline.to_string(),
+            kind: problem_kind(&message),
+            message,
+        })
+    })
+}
+
+// The kind of a problem difftest::check reports: what changed about the run, or the pass that failed
+// verification with the kind of its first diagnostic
+fn problem_kind(problem: &str) -> String {
+    let mut lines = problem.lines();
+    let first = lines.next().unwrap_or_default();
+    if let Some((_, pass)) = first.split_once("verification failed after ") {
+        let diagnostic = lines
+            .next()
+            .and_then(|line| line.split(": ").nth(1))
+            .unwrap_or_default();
+        return format!("{} {diagnostic}", pass.trim_end_matches(':'));
+    }
+    [
+        "prints",
+        "returns",
+        "fails: panicked",
+        "fails",
+        "no longer fails",
+        "panicked",
+    ]
+    .into_iter()
+    .find(|prefix| first.starts_with(prefix))
+    .unwrap_or(first)
+    .to_string()
+}
+
+// SSA construction step by step, with the verifiers after it, and Braun et al. on the side
+fn check_construction(func: &Function) -> Result<(), Vec<Diagnostic>> {
+    let mut cfg = CfgBuilder::new().build(func);
+    cfg.verify()?;
+    cfg.remove_unreachable();
+    let idoms = cfg.get_idoms();
+    let df = cfg.get_dominance_frontiers(&idoms);
+    cfg.insert_phi_nodes(&df);
+    cfg.rename_variables(&idoms);
+    cfg.verify()?;
+    cfg.verify_ssa(&idoms)?;
+
+    let mut braun = CfgBuilder::new().build(func);
+    braun.remove_unreachable();
+    braun.construct_ssa_braun();
+    braun.verify_ssa(&braun.get_idoms())?;
+    braun.verify_equivalent_ssa(&cfg)
+}
+
+fn describe(diagnostics: Vec<Diagnostic>) -> String {
+    diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+
+use bril_rs::{ConstOps, EffectOps, Instruction, Literal, Position, ValueOps};
+
+use crate::cfg::{BlockId, Label};
+use crate::value::{ValueId, Values};
+
+// Handle to an instruction that stays valid while instructions around it are inserted or removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InstId(usize);
+
+impl InstId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Op {
+    Const(Literal),
+    Value(ValueOps),
+    Effect(EffectOps),
+}
+
+// A Bril instruction with its variables resolved to values.
+// The type of the result is the type of `dest`.
+#[derive(Debug, Clone)]
+pub struct Inst {
+    pub op: Op,
+    pub dest: Option<ValueId>,
+    pub args: Vec<ValueId>,
+    pub funcs: Vec<String>,
+    pub labels: Vec<Label>,
+    pub pos: Option<Position>,
+}
+
+impl Inst {
+    pub fn new(op: Op, dest: Option<ValueId>, args: Vec<ValueId>) -> Self {
+        Self {
+            op,
+            dest,
+            args,
+            funcs: vec![],
+            labels: vec![],
+            pos: None,
+        }
+    }
+
+    pub fn is_phi(&self) -> bool {
+        matches!(self.op, Op::Value(ValueOps::Phi))
+    }
+
+    // A guard ends its block too: it leaves for its label when the speculation it is in gets aborted
+    pub fn is_terminator(&self) -> bool {
+        matches!(
+            self.op,
+            Op::Effect(EffectOps::Jump | EffectOps::Branch | EffectOps::Return | EffectOps::Guard)
+        )
+    }
+
+    pub fn is_guard(&self) -> bool {
+        matches!(self.op, Op::Effect(EffectOps::Guard))
+    }
+
+    pub fn from_bril(inst: &Instruction, values: &mut Values) -> Self {
+        match inst {
+            Instruction::Constant {
+                dest,
+                const_type,
+                value,
+                pos,
+                ..
+            } => Self {
+                pos: pos.clone(),
+                ..Self::new(
+                    Op::Const(value.clone()),
+                    Some(values.define(dest, const_type)),
+                    vec![],
+                )
+            },
+            Instruction::Value {
+                args,
+                dest,
+                funcs,
+                labels,
+                op,
+                pos,
+                op_type,
+            } => Self {
+                op: Op::Value(*op),
+                dest: Some(values.define(dest, op_type)),
+                args: args.iter().map(|arg| values.variable(arg)).collect(),
+                funcs: funcs.clone(),
+                labels: labels.clone(),
+                pos: pos.clone(),
+            },
+            Instruction::Effect {
+                args,
+                funcs,
+                labels,
+                op,
+                pos,
+            } => Self {
+                op: Op::Effect(*op),
+                dest: None,
+                args: args.iter().map(|arg| values.variable(arg)).collect(),
+                funcs: funcs.clone(),
+                labels: labels.clone(),
+                pos: pos.clone(),
+            },
+        }
+    }
+
+    // `names` comes from `Values::names`
+    pub fn to_bril(&self, values: &Values, names: &[String]) -> Instruction {
+        let name = |id: ValueId| names[id.index()].clone();
+        let dest_and_type = || {
+            let dest = self.dest.expect("instruction has no destination");
+            let ty = values.ty(dest).expect("definition without a type").clone();
+            (name(dest), ty)
+        };
+
+        match &self.op {
+            Op::Const(value) => {
+                let (dest, const_type) = dest_and_type();
+                Instruction::Constant {
+                    dest,
+                    op: ConstOps::Const,
+                    pos: self.pos.clone(),
+                    const_type,
+                    value: value.clone(),
+                }
+            }
+            Op::Value(op) => {
+                let (dest, op_type) = dest_and_type();
+                Instruction::Value {
+                    args: self.args.iter().copied().map(name).collect(),
+                    dest,
+                    funcs: self.funcs.clone(),
+                    labels: self.labels.clone(),
+                    op: *op,
+                    pos: self.pos.clone(),
+                    op_type,
+                }
+            }
+            Op::Effect(op) => Instruction::Effect {
+                args: self.args.iter().copied().map(name).collect(),
+                funcs: self.funcs.clone(),
+                // The block a guard falls through to is only a label in here, see CfgBuilder::build
+                labels: if self.is_guard() {
+                    self.labels[..1].to_vec()
+                } else {
+                    self.labels.clone()
+                },
+                op: *op,
+                pos: self.pos.clone(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InstData {
+    pub inst: Inst,
+    // The block whose `insts` lists this instruction
+    pub block: BlockId,
+}
+
+// Owns every instruction of a function. Blocks only keep the ids, in order.
+// Slots of removed instructions are never reused, so a stale id fails loudly instead of aliasing.
+#[derive(Debug, Clone, Default)]
+pub struct InstArena {
+    slots: Vec<Option<InstData>>,
+    // Every instruction defining a value, exactly one once the function is in SSA form
+    defs: HashMap<ValueId, Vec<InstId>>,
+}
+
+impl InstArena {
+    pub fn alloc(&mut self, inst: Inst, block: BlockId) -> InstId {
+        let id = InstId(self.slots.len());
+        if let Some(dest) = inst.dest {
+            self.defs.entry(dest).or_default().push(id);
+        }
+        self.slots.push(Some(InstData { inst, block }));
+        id
+    }
+
+    // Only frees the slot, the caller takes care of the block's list
+    pub fn free(&mut self, id: InstId) -> Inst {
+        let data = self.slots[id.0]
+            .take()
+            .expect("instruction was already removed");
+        if let Some(dest) = data.inst.dest {
+            self.unindex(dest, id);
+        }
+        data.inst
+    }
+
+    pub fn contains(&self, id: InstId) -> bool {
+        self.slots.get(id.0).is_some_and(Option::is_some)
+    }
+
+    pub fn data(&self, id: InstId) -> &InstData {
+        self.slots[id.0].as_ref().expect("instruction was removed")
+    }
+
+    pub fn block_of(&self, id: InstId) -> BlockId {
+        self.data(id).block
+    }
+
+    // Edits an instruction in place, keeping the definition index up to date if `f` changes its destination
+    pub fn update<R>(&mut self, id: InstId, f: impl FnOnce(&mut Inst) -> R) -> R {
+        let data = self.slots[id.0].as_mut().expect("instruction was removed");
+        let old_dest = data.inst.dest;
+        let result = f(&mut data.inst);
+        let new_dest = data.inst.dest;
+
+        if old_dest != new_dest {
+            if let Some(old_dest) = old_dest {
+                self.unindex(old_dest, id);
+            }
+            if let Some(new_dest) = new_dest {
+                self.defs.entry(new_dest).or_default().push(id);
+            }
+        }
+        result
+    }
+
+    pub fn replace(&mut self, id: InstId, inst: Inst) -> Inst {
+        self.update(id, |old| std::mem::replace(old, inst))
+    }
+
+    // All instructions defining `value`
+    pub fn defs(&self, value: ValueId) -> &[InstId] {
+        self.defs.get(&value).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    // The definition of `value`, if there is exactly one (always the case in SSA form)
+    pub fn def(&self, value: ValueId) -> Option<InstId> {
+        match self.defs(value) {
+            [id] => Some(*id),
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (InstId, &InstData)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, slot)| Some((InstId(i), slot.as_ref()?)))
+    }
+
+    fn unindex(&mut self, dest: ValueId, id: InstId) {
+        if let Some(ids) = self.defs.get_mut(&dest) {
+            ids.retain(|&other| other != id);
+            if ids.is_empty() {
+                self.defs.remove(&dest);
+            }
+        }
+    }
+}
+
+impl std::ops::Index<InstId> for InstArena {
+    type Output = Inst;
+
+    fn index(&self, id: InstId) -> &Inst {
+        &self.data(id).inst
+    }
+}
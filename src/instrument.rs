@@ -0,0 +1,355 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use bril_rs::{EffectOps, Literal, Type, ValueOps};
+
+use crate::cfg::{BlockId, Cfg};
+use crate::inst::{Inst, Op};
+use crate::value::ValueId;
+
+// Profiling by instrumentation, for when there is nothing else to get a profile from.
+//
+// The counters live in memory the function allocates when it's entered, and every return first prints them
+// on a line of their own and frees them:
+//
+//     <tag> <mode> <counter 0> <counter 1> ...
+//
+// The tag comes from the name of the function, so read_counts can pick its lines out of whatever else the
+// program prints. Every call prints its own line, and read_counts adds them up.
+//
+// Counting blocks puts a counter in every block. Counting edges only needs counters on the edges that are left
+// out of a spanning tree of the graph, plus one for the calls and one for each returning block: the counts
+// of the edges in the tree follow from flow conservation, which is what infer_edge_counts works out.
+// Edges that would need a block of their own to hold a counter go into the tree first.
+// "Optimal Measurement Points for Program Frequency Counts", Knuth and Stevenson, BIT 1973
+// "Optimally Profiling and Tracing Programs", Ball and Larus, TOPLAS 1994
+//
+// Which counter counts what isn't printed. read_counts works it out again from the function as it was before
+// it was instrumented, so that is the function it has to be called on.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileMode {
+    Blocks,
+    Edges,
+}
+
+impl ProfileMode {
+    fn code(self) -> i64 {
+        match self {
+            ProfileMode::Blocks => 0,
+            ProfileMode::Edges => 1,
+        }
+    }
+
+    fn from_code(code: i64) -> Option<Self> {
+        match code {
+            0 => Some(ProfileMode::Blocks),
+            1 => Some(ProfileMode::Edges),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Counter {
+    Calls,
+    Block(BlockId),
+    // All the edges from one block to the other, a branch may have the same target twice
+    Edge(BlockId, BlockId),
+}
+
+impl Cfg {
+    // The counters for the function in its current shape, in the order they are printed
+    fn counters(&self, mode: ProfileMode) -> Vec<Counter> {
+        let idoms = self.get_idoms();
+        let blocks: Vec<BlockId> = self
+            .layout
+            .iter()
+            .copied()
+            .filter(|block| idoms.contains_key(block))
+            .collect();
+        if mode == ProfileMode::Blocks {
+            return blocks.into_iter().map(Counter::Block).collect();
+        }
+
+        let mut counters = vec![Counter::Calls];
+        counters.extend(
+            blocks
+                .iter()
+                .filter(|&&block| self.successors(block).is_empty())
+                .map(|&block| Counter::Block(block)),
+        );
+
+        let position: HashMap<BlockId, usize> = blocks
+            .iter()
+            .enumerate()
+            .map(|(i, &block)| (block, i))
+            .collect();
+        let mut pairs: Vec<(BlockId, BlockId)> = Vec::new();
+        for &from in &blocks {
+            for to in self.successors(from) {
+                if !pairs.contains(&(from, to)) {
+                    pairs.push((from, to));
+                }
+            }
+        }
+        pairs.sort_by_key(|&(from, to)| {
+            (
+                Reverse(self.needs_own_block(from, to)),
+                position[&from],
+                position[&to],
+            )
+        });
+
+        // Kruskal's algorithm, whatever would close a cycle gets a counter
+        let mut parent: HashMap<BlockId, BlockId> = HashMap::new();
+        let root = |parent: &HashMap<BlockId, BlockId>, mut block| {
+            while let Some(&up) = parent.get(&block) {
+                block = up;
+            }
+            block
+        };
+        for (from, to) in pairs {
+            let (a, b) = (root(&parent, from), root(&parent, to));
+            if a == b {
+                counters.push(Counter::Edge(from, to));
+            } else {
+                parent.insert(a, b);
+            }
+        }
+        counters
+    }
+
+    // Whether a counter for the edges from `from` to `to` can only go in a block split off the edge
+    fn needs_own_block(&self, from: BlockId, to: BlockId) -> bool {
+        self.successors(from).iter().any(|&succ| succ != to)
+            && self.predecessors(to).iter().any(|&pred| pred != from)
+    }
+
+    // Marks the lines with the counts of this function
+    fn profile_tag(&self) -> i64 {
+        let hash = self
+            .name
+            .bytes()
+            .fold(0xcbf29ce484222325, |hash: u64, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+            });
+        (hash >> 1) as i64
+    }
+
+    // Returns whether there was anything to count
+    pub fn instrument(&mut self, mode: ProfileMode) -> bool {
+        let counters = self.counters(mode);
+        if counters.is_empty() {
+            return false;
+        }
+        let entry = self.block_id(&self.entry_label);
+        let base = self
+            .values
+            .fresh("counters", &Type::Pointer(Box::new(Type::Int)));
+        let one = self.values.fresh("one", &Type::Int);
+
+        // Memory for the counters, all zero
+        let mut setup = vec![self.int(one, 1)];
+        let size = self.values.fresh("counters_size", &Type::Int);
+        setup.push(self.int(size, counters.len() as i64));
+        setup.push(Inst::new(
+            Op::Value(ValueOps::Alloc),
+            Some(base),
+            vec![size],
+        ));
+        let zero = self.values.fresh("zero", &Type::Int);
+        setup.push(self.int(zero, 0));
+        for i in 0..counters.len() {
+            let counter = self.counter_address(base, i, &mut setup);
+            setup.push(Inst::new(
+                Op::Effect(EffectOps::Store),
+                None,
+                vec![counter, zero],
+            ));
+        }
+        let returns: Vec<BlockId> = self
+            .layout
+            .iter()
+            .copied()
+            .filter(|&block| {
+                self.block(block)
+                    .terminator(&self.insts)
+                    .is_some_and(|id| matches!(self.insts[id].op, Op::Effect(EffectOps::Return)))
+            })
+            .collect();
+
+        for (i, &counter) in counters.iter().enumerate() {
+            let mut increment = Vec::new();
+            let address = self.counter_address(base, i, &mut increment);
+            let old = self.values.fresh("count", &Type::Int);
+            let new = self.values.fresh("count", &Type::Int);
+            increment.push(Inst::new(
+                Op::Value(ValueOps::Load),
+                Some(old),
+                vec![address],
+            ));
+            increment.push(Inst::new(
+                Op::Value(ValueOps::Add),
+                Some(new),
+                vec![old, one],
+            ));
+            increment.push(Inst::new(
+                Op::Effect(EffectOps::Store),
+                None,
+                vec![address, new],
+            ));
+
+            match counter {
+                Counter::Calls => self.insert_at_start(entry, increment),
+                Counter::Block(block) => self.insert_at_start(block, increment),
+                Counter::Edge(from, to) => {
+                    if self.successors(from).iter().all(|&succ| succ == to) {
+                        self.insert_at_end(from, increment);
+                    } else if self.predecessors(to).iter().all(|&pred| pred == from) {
+                        self.insert_at_start(to, increment);
+                    } else {
+                        let middle = self.split_edge(from, to);
+                        self.insert_at_end(middle, increment);
+                    }
+                }
+            }
+        }
+
+        for block in returns {
+            let mut report = Vec::new();
+            let tag = self.values.fresh("profile_tag", &Type::Int);
+            report.push(self.int(tag, self.profile_tag()));
+            let mode_code = self.values.fresh("profile_mode", &Type::Int);
+            report.push(self.int(mode_code, mode.code()));
+            let mut printed = vec![tag, mode_code];
+            for i in 0..counters.len() {
+                let address = self.counter_address(base, i, &mut report);
+                let count = self.values.fresh("count", &Type::Int);
+                report.push(Inst::new(
+                    Op::Value(ValueOps::Load),
+                    Some(count),
+                    vec![address],
+                ));
+                printed.push(count);
+            }
+            report.push(Inst::new(Op::Effect(EffectOps::Print), None, printed));
+            report.push(Inst::new(Op::Effect(EffectOps::Free), None, vec![base]));
+            self.insert_at_end(block, report);
+        }
+
+        // The entry block has no φ-functions, the setup goes first of all
+        for (i, inst) in setup.into_iter().enumerate() {
+            self.insert_inst(entry, i, inst);
+        }
+        for block in self.blocks.values_mut() {
+            block.update_definitions(&self.insts);
+        }
+        true
+    }
+
+    fn int(&self, dest: ValueId, value: i64) -> Inst {
+        Inst::new(Op::Const(Literal::Int(value)), Some(dest), vec![])
+    }
+
+    // Appends to `insts` what computes the address of counter `i`, and returns the value holding it
+    fn counter_address(&mut self, base: ValueId, i: usize, insts: &mut Vec<Inst>) -> ValueId {
+        let index = self.values.fresh("counter_index", &Type::Int);
+        let address = self
+            .values
+            .fresh("counter", &Type::Pointer(Box::new(Type::Int)));
+        insts.push(self.int(index, i as i64));
+        insts.push(Inst::new(
+            Op::Value(ValueOps::PtrAdd),
+            Some(address),
+            vec![base, index],
+        ));
+        address
+    }
+
+    // After the φ-functions
+    fn insert_at_start(&mut self, block: BlockId, insts: Vec<Inst>) {
+        let at = self.block(block).phis(&self.insts).count();
+        for (i, inst) in insts.into_iter().enumerate() {
+            self.insert_inst(block, at + i, inst);
+        }
+    }
+
+    // Before the terminator
+    fn insert_at_end(&mut self, block: BlockId, insts: Vec<Inst>) {
+        let at = self.block(block).end_index(&self.insts);
+        for (i, inst) in insts.into_iter().enumerate() {
+            self.insert_inst(block, at + i, inst);
+        }
+    }
+
+    // Puts the counts an instrumented copy of the function printed in `output` on the edges of the function,
+    // which has to be in the shape it was in when the copy was instrumented.
+    // Returns whether there were any counts for it.
+    pub fn read_counts(&mut self, output: &str) -> Result<bool, String> {
+        let tag = self.profile_tag();
+        let mut totals: Option<(ProfileMode, Vec<u64>)> = None;
+        for line in output.lines() {
+            let mut values = line.split_whitespace().map(str::parse::<i64>);
+            if values.next() != Some(Ok(tag)) {
+                continue;
+            }
+            let malformed = || format!("malformed counts: {line}");
+            let values: Vec<i64> = values.collect::<Result<_, _>>().map_err(|_| malformed())?;
+            let (mode, counts) = values.split_first().ok_or_else(malformed)?;
+            let mode = ProfileMode::from_code(*mode).ok_or_else(malformed)?;
+            let (first_mode, sums) = totals.get_or_insert_with(|| (mode, vec![0; counts.len()]));
+            if *first_mode != mode || sums.len() != counts.len() {
+                return Err(format!("counts of different shapes: {line}"));
+            }
+            for (sum, &count) in sums.iter_mut().zip(counts) {
+                let count = u64::try_from(count).map_err(|_| format!("negative count: {line}"))?;
+                *sum = sum
+                    .checked_add(count)
+                    .ok_or_else(|| format!("counts add up past the largest count: {line}"))?;
+            }
+        }
+        let Some((mode, totals)) = totals else {
+            return Ok(false);
+        };
+
+        let counters = self.counters(mode);
+        if counters.len() != totals.len() {
+            return Err(format!(
+                "{} counts for {} counters, the function isn't the one that was instrumented",
+                totals.len(),
+                counters.len()
+            ));
+        }
+        // Nothing is known about the edges yet, but that unreachable blocks never ran
+        self.clear_profile();
+        let idoms = self.get_idoms();
+        for from in self.layout.clone() {
+            let count = if idoms.contains_key(&from) {
+                None
+            } else {
+                Some(0)
+            };
+            for to in self.successors(from) {
+                self.set_edge_count(from, to, count);
+            }
+        }
+
+        let entry = self.block_id(&self.entry_label);
+        let mut blocks = HashMap::new();
+        for (counter, count) in counters.into_iter().zip(totals) {
+            match counter {
+                Counter::Calls => self.entry_count = Some(count),
+                Counter::Block(block) => {
+                    blocks.insert(block, count);
+                }
+                Counter::Edge(from, to) => self.set_edge_count(from, to, Some(count)),
+            }
+        }
+        if mode == ProfileMode::Blocks {
+            self.entry_count = blocks.get(&entry).copied();
+        }
+        self.infer_edge_counts(&blocks);
+        Ok(true)
+    }
+}
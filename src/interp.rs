@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use bril_rs::{EffectOps, Literal, Type, ValueOps};
+
+use crate::cfg::{BlockId, Cfg};
+use crate::inst::{Inst, InstId, Op};
+use crate::profile::Profile;
+use crate::value::ValueId;
+
+// Runs functions straight from their Cfg, in or out of SSA form, with the float, char, memory and speculate
+// extensions. It follows the reference interpreter, brili, wherever they could differ:
+// - arithmetic on ints wraps around, and dividing by zero is an error
+// - a φ-function takes the argument for the label of the block control came from, and if that argument
+//   isn't defined, its destination becomes undefined as well
+// - speculation rolls back variables, not memory, and returning while speculating is an error
+// - memory that is still allocated when the program ends is an error
+//
+// Besides what the program prints and returns, a run counts the instructions executed, φ-functions included,
+// and how many times every edge was taken, which makes a profile.
+
+// Deeper recursion is reported as an error rather than overflowing the stack
+const MAX_CALL_DEPTH: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Bool(bool),
+    Float(f64),
+    Char(char),
+    Pointer(Pointer),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pointer {
+    alloc: usize,
+    offset: i64,
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{n}"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Float(x) if x.is_nan() => write!(f, "NaN"),
+            Value::Float(x) if x.is_infinite() => {
+                write!(f, "{}Infinity", if *x < 0.0 { "-" } else { "" })
+            }
+            Value::Float(x) => write!(f, "{x:.17}"),
+            Value::Char(c) => write!(f, "{c}"),
+            Value::Pointer(pointer) => write!(f, "<pointer {}+{}>", pointer.alloc, pointer.offset),
+        }
+    }
+}
+
+// An argument of the main function, as given on the command line
+pub fn parse_arg(text: &str, ty: &Type) -> Result<Value, String> {
+    let value = match ty {
+        Type::Int => text.parse().ok().map(Value::Int),
+        Type::Bool => text.parse().ok().map(Value::Bool),
+        Type::Float => text.parse().ok().map(Value::Float),
+        Type::Char => {
+            let mut chars = text.chars();
+            chars
+                .next()
+                .filter(|_| chars.next().is_none())
+                .map(Value::Char)
+        }
+        Type::Pointer(_) => None,
+    };
+    value.ok_or_else(|| format!("{text} is not a {ty}"))
+}
+
+// Why a run failed. Running out of instructions and reading an undefined variable have kinds of their own,
+// since difftest doesn't count them as a pass breaking the program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    TooManyInstructions,
+    UndefinedVariable,
+    Other,
+}
+
+impl Error {
+    fn in_function(self, name: &str) -> Self {
+        Self {
+            message: format!("@{name}: {}", self.message),
+            ..self
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Self {
+            kind: ErrorKind::Other,
+            message,
+        }
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Execution {
+    pub output: String,
+    pub result: Option<Value>,
+    pub instructions: u64,
+    pub profile: Profile,
+}
+
+pub struct Interpreter<'a> {
+    functions: HashMap<&'a str, &'a Cfg>,
+    // Allocations by number, None once freed. Cells are None until they are stored to.
+    heap: Vec<Option<Vec<Option<Value>>>>,
+    depth: usize,
+    // Runs that take longer than this many instructions are stopped with an error
+    pub max_instructions: Option<u64>,
+    execution: Execution,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(functions: &'a [Cfg]) -> Self {
+        Self {
+            functions: functions
+                .iter()
+                .map(|cfg| (cfg.name.as_str(), cfg))
+                .collect(),
+            heap: Vec::new(),
+            depth: 0,
+            max_instructions: None,
+            execution: Execution::default(),
+        }
+    }
+
+    // Calls `function` like a program calls its main function. Errors are prefixed with the function they
+    // happened in.
+    pub fn run(mut self, function: &str, args: Vec<Value>) -> Result<Execution, Error> {
+        self.execution.result = self.call(function, args)?;
+        let leaked = self.heap.iter().filter(|alloc| alloc.is_some()).count();
+        if leaked > 0 {
+            return Err(format!("{leaked} allocations were never freed").into());
+        }
+        Ok(self.execution)
+    }
+
+    fn step(&mut self) -> Result<(), Error> {
+        self.execution.instructions += 1;
+        match self.max_instructions {
+            Some(max) if self.execution.instructions > max => Err(Error {
+                kind: ErrorKind::TooManyInstructions,
+                message: format!("ran more than {max} instructions"),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Option<Value>, Error> {
+        let cfg: &'a Cfg = *self
+            .functions
+            .get(name)
+            .ok_or_else(|| format!("no function @{name}"))?;
+        if args.len() != cfg.args.len() {
+            return Err(format!(
+                "@{name} takes {} arguments, got {}",
+                cfg.args.len(),
+                args.len()
+            )
+            .into());
+        }
+        if self.depth == MAX_CALL_DEPTH {
+            return Err(format!("more than {MAX_CALL_DEPTH} calls deep").into());
+        }
+        self.depth += 1;
+        let result = self
+            .run_function(cfg, args)
+            .map_err(|err| err.in_function(name));
+        self.depth -= 1;
+        result
+    }
+
+    fn run_function(&mut self, cfg: &'a Cfg, args: Vec<Value>) -> Result<Option<Value>, Error> {
+        let mut env: HashMap<ValueId, Value> = cfg.args.iter().copied().zip(args).collect();
+        // What the variables were when each speculation that is still going on started
+        let mut speculations: Vec<HashMap<ValueId, Value>> = Vec::new();
+        let profile = self.execution.profile.entry(cfg.name.clone()).or_default();
+        *profile.calls.get_or_insert(0) += 1;
+
+        let mut block = cfg.block_id(&cfg.entry_label);
+        let mut pred: Option<BlockId> = None;
+        loop {
+            let phis: Vec<InstId> = cfg.block(block).phis(&cfg.insts).collect();
+            self.run_phis(cfg, &phis, pred, &mut env)?;
+
+            let mut next = None;
+            for &id in &cfg.block(block).insts[phis.len()..] {
+                self.step()?;
+                let inst = &cfg.insts[id];
+                let arg = |i: usize| lookup(cfg, &env, inst.args[i]);
+                match &inst.op {
+                    Op::Const(literal) => {
+                        let dest = inst.dest.unwrap();
+                        env.insert(dest, constant(literal, cfg.values.ty(dest)));
+                    }
+                    Op::Value(op) => {
+                        let value = self.run_value_op(cfg, op, inst, &env)?;
+                        env.insert(inst.dest.unwrap(), value);
+                    }
+                    Op::Effect(op) => match op {
+                        EffectOps::Jump => next = Some(&inst.labels[0]),
+                        EffectOps::Branch => {
+                            let taken = if as_bool(arg(0)?)? { 0 } else { 1 };
+                            next = Some(&inst.labels[taken]);
+                        }
+                        EffectOps::Return => {
+                            if !speculations.is_empty() {
+                                return Err("return while speculating".into());
+                            }
+                            return inst
+                                .args
+                                .first()
+                                .map(|&value| lookup(cfg, &env, value))
+                                .transpose();
+                        }
+                        EffectOps::Print => {
+                            let values = (0..inst.args.len())
+                                .map(|i| arg(i).map(|value| value.to_string()))
+                                .collect::<Result<Vec<_>, _>>()?;
+                            writeln!(self.execution.output, "{}", values.join(" ")).unwrap();
+                        }
+                        EffectOps::Nop => {}
+                        EffectOps::Call => {
+                            let args = (0..inst.args.len()).map(arg).collect::<Result<_, _>>()?;
+                            self.call(&inst.funcs[0], args)?;
+                        }
+                        EffectOps::Store => {
+                            let pointer = as_pointer(arg(0)?)?;
+                            *self.cell(pointer)? = Some(arg(1)?);
+                        }
+                        EffectOps::Free => {
+                            let pointer = as_pointer(arg(0)?)?;
+                            if pointer.offset != 0 {
+                                return Err(
+                                    "free of a pointer into the middle of an allocation".into()
+                                );
+                            }
+                            match self.heap.get_mut(pointer.alloc) {
+                                Some(alloc @ Some(_)) => *alloc = None,
+                                _ => return Err("double free".into()),
+                            }
+                        }
+                        EffectOps::Speculate => speculations.push(env.clone()),
+                        EffectOps::Commit => {
+                            speculations
+                                .pop()
+                                .ok_or("commit outside of a speculation")?;
+                        }
+                        EffectOps::Guard => {
+                            if as_bool(arg(0)?)? {
+                                next = Some(&inst.labels[1]);
+                            } else {
+                                env = speculations.pop().ok_or("guard outside of a speculation")?;
+                                next = Some(&inst.labels[0]);
+                            }
+                        }
+                        #[allow(unreachable_patterns)]
+                        _ => return Err(format!("unsupported operation {op:?}").into()),
+                    },
+                }
+            }
+
+            let label = next.ok_or_else(|| format!(".{} has no terminator", cfg.label(block)))?;
+            let target = cfg.block_id(label);
+            let profile = self.execution.profile.get_mut(&cfg.name).unwrap();
+            *profile
+                .edges
+                .entry((cfg.label(block).clone(), label.clone()))
+                .or_insert(0) += 1;
+            pred = Some(block);
+            block = target;
+        }
+    }
+
+    // All the φ-functions at the start of a block read their arguments before any of them is assigned
+    fn run_phis(
+        &mut self,
+        cfg: &Cfg,
+        phis: &[InstId],
+        pred: Option<BlockId>,
+        env: &mut HashMap<ValueId, Value>,
+    ) -> Result<(), Error> {
+        let from = pred.map(|pred| cfg.label(pred));
+        let mut assigned = Vec::with_capacity(phis.len());
+        for &id in phis {
+            self.step()?;
+            let inst = &cfg.insts[id];
+            let value = from
+                .and_then(|from| inst.labels.iter().position(|label| label == from))
+                .and_then(|i| env.get(&inst.args[i]).copied());
+            assigned.push((inst.dest.unwrap(), value));
+        }
+        for (dest, value) in assigned {
+            match value {
+                Some(value) => env.insert(dest, value),
+                None => env.remove(&dest),
+            };
+        }
+        Ok(())
+    }
+
+    fn run_value_op(
+        &mut self,
+        cfg: &Cfg,
+        op: &ValueOps,
+        inst: &Inst,
+        env: &HashMap<ValueId, Value>,
+    ) -> Result<Value, Error> {
+        let arg = |i: usize| lookup(cfg, env, inst.args[i]);
+        let int = |i: usize| match arg(i)? {
+            Value::Int(n) => Ok(n),
+            other => Err(Error::from(format!("{other} is not an int"))),
+        };
+        let float = |i: usize| match arg(i)? {
+            Value::Float(x) => Ok(x),
+            other => Err(Error::from(format!("{other} is not a float"))),
+        };
+        let character = |i: usize| match arg(i)? {
+            Value::Char(c) => Ok(c),
+            other => Err(Error::from(format!("{other} is not a char"))),
+        };
+        let boolean = |i: usize| arg(i).and_then(as_bool);
+
+        let value = match op {
+            ValueOps::Id => arg(0)?,
+            ValueOps::Add => Value::Int(int(0)?.wrapping_add(int(1)?)),
+            ValueOps::Sub => Value::Int(int(0)?.wrapping_sub(int(1)?)),
+            ValueOps::Mul => Value::Int(int(0)?.wrapping_mul(int(1)?)),
+            ValueOps::Div => match int(1)? {
+                0 => return Err("division by zero".into()),
+                d => Value::Int(int(0)?.wrapping_div(d)),
+            },
+            ValueOps::Eq => Value::Bool(int(0)? == int(1)?),
+            ValueOps::Lt => Value::Bool(int(0)? < int(1)?),
+            ValueOps::Gt => Value::Bool(int(0)? > int(1)?),
+            ValueOps::Le => Value::Bool(int(0)? <= int(1)?),
+            ValueOps::Ge => Value::Bool(int(0)? >= int(1)?),
+            ValueOps::Not => Value::Bool(!boolean(0)?),
+            ValueOps::And => Value::Bool(boolean(0)? && boolean(1)?),
+            ValueOps::Or => Value::Bool(boolean(0)? || boolean(1)?),
+            ValueOps::Fadd => Value::Float(float(0)? + float(1)?),
+            ValueOps::Fsub => Value::Float(float(0)? - float(1)?),
+            ValueOps::Fmul => Value::Float(float(0)? * float(1)?),
+            ValueOps::Fdiv => Value::Float(float(0)? / float(1)?),
+            ValueOps::Feq => Value::Bool(float(0)? == float(1)?),
+            ValueOps::Flt => Value::Bool(float(0)? < float(1)?),
+            ValueOps::Fgt => Value::Bool(float(0)? > float(1)?),
+            ValueOps::Fle => Value::Bool(float(0)? <= float(1)?),
+            ValueOps::Fge => Value::Bool(float(0)? >= float(1)?),
+            ValueOps::Ceq => Value::Bool(character(0)? == character(1)?),
+            ValueOps::Clt => Value::Bool(character(0)? < character(1)?),
+            ValueOps::Cgt => Value::Bool(character(0)? > character(1)?),
+            ValueOps::Cle => Value::Bool(character(0)? <= character(1)?),
+            ValueOps::Cge => Value::Bool(character(0)? >= character(1)?),
+            ValueOps::Char2int => Value::Int(i64::from(u32::from(character(0)?))),
+            ValueOps::Int2char => {
+                let n = int(0)?;
+                let c = u32::try_from(n).ok().and_then(char::from_u32);
+                Value::Char(c.ok_or_else(|| format!("{n} is not a character"))?)
+            }
+            ValueOps::Alloc => {
+                let size = int(0)?;
+                if size <= 0 {
+                    return Err(format!("allocation of {size} elements").into());
+                }
+                self.heap.push(Some(vec![None; size as usize]));
+                Value::Pointer(Pointer {
+                    alloc: self.heap.len() - 1,
+                    offset: 0,
+                })
+            }
+            ValueOps::Load => {
+                let pointer = as_pointer(arg(0)?)?;
+                self.cell(pointer)?
+                    .ok_or("load from memory that was never stored to")?
+            }
+            ValueOps::PtrAdd => {
+                let pointer = as_pointer(arg(0)?)?;
+                Value::Pointer(Pointer {
+                    offset: pointer.offset.wrapping_add(int(1)?),
+                    ..pointer
+                })
+            }
+            ValueOps::Call => {
+                let args = (0..inst.args.len()).map(arg).collect::<Result<_, _>>()?;
+                self.call(&inst.funcs[0], args)?
+                    .ok_or_else(|| format!("@{} returned nothing", inst.funcs[0]))?
+            }
+            ValueOps::Phi => return Err("φ-function after other instructions".into()),
+            #[allow(unreachable_patterns)]
+            _ => return Err(format!("unsupported operation {op:?}").into()),
+        };
+        Ok(value)
+    }
+
+    fn cell(&mut self, pointer: Pointer) -> Result<&mut Option<Value>, String> {
+        let alloc = self
+            .heap
+            .get_mut(pointer.alloc)
+            .and_then(Option::as_mut)
+            .ok_or("use of freed memory")?;
+        usize::try_from(pointer.offset)
+            .ok()
+            .and_then(|offset| alloc.get_mut(offset))
+            .ok_or_else(|| format!("offset {} is out of bounds", pointer.offset))
+    }
+}
+
+fn lookup(cfg: &Cfg, env: &HashMap<ValueId, Value>, value: ValueId) -> Result<Value, Error> {
+    env.get(&value).copied().ok_or_else(|| Error {
+        kind: ErrorKind::UndefinedVariable,
+        message: format!("undefined variable {}", cfg.values.debug_name(value)),
+    })
+}
+
+// Bril lets an integer literal stand for a float
+fn constant(literal: &Literal, ty: Option<&Type>) -> Value {
+    match (literal, ty) {
+        (Literal::Int(n), Some(Type::Float)) => Value::Float(*n as f64),
+        (Literal::Int(n), _) => Value::Int(*n),
+        (Literal::Bool(b), _) => Value::Bool(*b),
+        (Literal::Float(x), _) => Value::Float(*x),
+        (Literal::Char(c), _) => Value::Char(*c),
+    }
+}
+
+fn as_bool(value: Value) -> Result<bool, Error> {
+    match value {
+        Value::Bool(b) => Ok(b),
+        other => Err(format!("{other} is not a bool").into()),
+    }
+}
+
+fn as_pointer(value: Value) -> Result<Pointer, Error> {
+    match value {
+        Value::Pointer(pointer) => Ok(pointer),
+        other => Err(format!("{other} is not a pointer").into()),
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use bril_rs::ValueOps;
+
+use crate::analyses::loops::{Loop, Loops};
+use crate::cfg::{dominates, BlockId, Cfg, IDoms, Renaming};
+use crate::inst::{Inst, InstId, Op};
+use crate::value::ValueId;
+
+// Loop-closed SSA form: a value defined in a loop is only used outside of it by φ-functions in the blocks
+// the loop exits to. Transforming a loop then only means keeping those φ-functions right, instead of
+// hunting for uses all over the function.
+// An exit block whose predecessors are all in the loop gets φ-functions with the same value for every
+// argument, the "single-argument" φ-functions.
+impl Cfg {
+    // Returns whether any φ-function was added
+    pub fn form_lcssa(&mut self, idoms: &IDoms, loops: &Loops) -> bool {
+        if !self.in_ssa {
+            return false;
+        }
+        // Outer loops come first, so this goes from the inside out: what an outer loop sees used outside of it
+        // are then the exit φ-functions of the loops nested in it
+        let mut changed = false;
+        for l in loops.loops.iter().rev() {
+            changed |= self.close_loop(l, idoms);
+        }
+        changed
+    }
+
+    // Uses of values defined in `l` that break loop-closed SSA form, with the value used, in layout order.
+    // A φ-function uses its arguments at the end of the block they come from.
+    pub fn loop_escapes(&self, l: &Loop) -> Vec<(InstId, ValueId)> {
+        let defined: HashSet<ValueId> = l
+            .body
+            .iter()
+            .flat_map(|&block| &self.block(block).insts)
+            .filter_map(|&id| self.insts[id].dest)
+            .collect();
+
+        let mut escapes = Vec::new();
+        for block in self.blocks_in_order() {
+            for &id in &block.insts {
+                let inst = &self.insts[id];
+                if inst.is_phi() {
+                    for (&arg, label) in inst.args.iter().zip(&inst.labels) {
+                        if defined.contains(&arg) && !l.contains(self.block_id(label)) {
+                            escapes.push((id, arg));
+                        }
+                    }
+                } else if !l.contains(block.node) {
+                    for &arg in &inst.args {
+                        if defined.contains(&arg) {
+                            escapes.push((id, arg));
+                        }
+                    }
+                }
+            }
+        }
+        escapes
+    }
+
+    fn close_loop(&mut self, l: &Loop, idoms: &IDoms) -> bool {
+        let escaping: BTreeSet<ValueId> = self
+            .loop_escapes(l)
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect();
+        if escaping.is_empty() {
+            return false;
+        }
+        let exits: BTreeSet<BlockId> = l.exits.iter().map(|&(_, exit)| exit).collect();
+        let dj = self.dj_graph(idoms);
+
+        // New φ-function -> the value it stands for
+        let mut placed: HashMap<InstId, ValueId> = HashMap::new();
+        for &value in &escaping {
+            let def_block = self.insts.block_of(self.insts.def(value).unwrap());
+            // The value can't be used past an exit it doesn't dominate
+            let exit_blocks: HashSet<BlockId> = exits
+                .iter()
+                .copied()
+                .filter(|&exit| dominates(idoms, def_block, exit))
+                .collect();
+            // Where paths from different exits meet, the exit φ-functions need merging
+            let mut blocks: BTreeSet<BlockId> = dj
+                .iterated_frontier(&exit_blocks)
+                .into_iter()
+                .filter(|&join| !l.contains(join) && dominates(idoms, def_block, join))
+                .collect();
+            blocks.extend(exit_blocks);
+
+            for block in blocks {
+                let dest = self.values.new_version(value);
+                let at = self.block(block).phis(&self.insts).count();
+                let phi = Inst::new(Op::Value(ValueOps::Phi), Some(dest), vec![]);
+                placed.insert(self.insert_inst(block, at, phi), value);
+            }
+        }
+
+        // Uses outside the loop get the nearest of the new φ-functions above them in the dominator tree. Inside
+        // the loop, that is the value itself: no exit dominates a block of the loop.
+        let escaping: Vec<ValueId> = escaping.into_iter().collect();
+        let renaming = Renaming {
+            values: &escaping,
+            placed: &placed,
+            new_versions: false,
+            at_end: &HashMap::new(),
+        };
+        self.rename_along_dominator_tree(idoms, &renaming);
+
+        // Exits the value is live out of are hard to tell in advance, φ-functions nobody ended up using go again
+        let placed = self.remove_unused_phis(placed.into_keys().collect());
+
+        for block in self.blocks.values_mut() {
+            block.update_definitions(&self.insts);
+        }
+        !placed.is_empty()
+    }
+}
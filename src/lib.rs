@@ -0,0 +1,19 @@
+pub mod analyses;
+pub mod basic_block;
+pub mod braun;
+pub mod cfg;
+pub mod difftest;
+pub mod fuzz;
+pub mod inst;
+pub mod instrument;
+pub mod interp;
+pub mod lcssa;
+pub mod optimisations;
+pub mod passes;
+pub mod profile;
+pub mod programs;
+pub mod ssa_update;
+pub mod text;
+pub mod utils;
+pub mod value;
+pub mod verify;
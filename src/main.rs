@@ -1,28 +1,253 @@
-#![feature(let_chains)]
+use std::{
+    fmt::Write,
+    fs, process,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
 
-mod basic_block;
-mod cfg;
-mod optimisations;
-mod utils;
+use bril_rs::{output_program, Function};
+use ssa::cfg::{Cfg, CfgBuilder};
+use ssa::passes::{PassManager, PASS_NAMES};
+use ssa::profile::{parse_profile, FunctionProfile, Profile};
+use ssa::utils::read_program;
 
-use bril_rs::load_program;
-use cfg::CfgBuilder;
+const USAGE: &str =
+    "usage: ssa [FILE] [--passes=PASS,...] [--dump-after=PASS|all] [--dot-dir=DIR] [--verify-each]
+           [--profile=PROFILE] [--counts=OUTPUT]
+
+Reads a Bril program from FILE, or stdin if FILE is missing or `-`, runs the passes
+on every function and writes the resulting program (JSON) to stdout. Programs are JSON,
+or text if FILE ends in .bril.
+--profile reads block and edge counts (JSON, see profile.rs) for the functions before
+the passes run. They guide speculate, unroll and profile-layout, and label the edges
+in the Graphviz files. --counts reads them from OUTPUT instead, what a run of the
+program after the instrument pass printed. Functions have to be the same as when they were
+instrumented, so instrument is best run first.
+
+The other tools: ssa-run runs a program, ssa-difftest and ssa-fuzz test the passes against
+it, and ssa-inspect checks and prints SSA form.
+
+passes: remove-unreachable, to-ssa, to-ssa-braun, from-ssa, sccp, dce, pre,
+        unroll[=FACTOR], lcssa, canonicalize-loops, mem-opt,
+        mem2reg, speculate, profile-layout, instrument[=blocks|edges]";
+
+#[derive(Debug, Default)]
+struct Options {
+    input: Option<String>,
+    passes: String,
+    // Print the function to stderr after every run of this pass ("all" for every pass)
+    dump_after: Option<String>,
+    // Write one Graphviz file per function before the first pass and after every pass
+    dot_dir: Option<String>,
+    // Check the graph after every pass, and SSA form after every pass that leaves the function in it
+    verify_each: bool,
+    profile: Option<String>,
+    counts: Option<String>,
+}
 
 fn main() {
-    let program = load_program();
-    for func in program.functions {
-        let mut builder = CfgBuilder::new();
-        let mut cfg = builder.build(func);
-        cfg.remove_unreachable();
-        println!("{cfg}");
-        cfg.output_graphviz("/Users/ggd/projects/ssa/ihatemyself");
-        println!("======================================================================================");
-        let idoms = cfg.get_idoms();
-        let df = cfg.get_dominance_frontiers(idoms.clone());
-        println!("{:?}", df);
-        println!("======================================================================================");
-        cfg.insert_phi_nodes(df);
-        cfg.rename_variables(idoms);
-        println!("{cfg}");
+    let options = parse_args().unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1);
+    });
+    let mut pm = PassManager::parse(&options.passes).unwrap_or_else(|err| {
+        eprintln!("{err}\n{USAGE}");
+        process::exit(1);
+    });
+    pm.verify_each = options.verify_each;
+
+    let mut program = read_program(options.input.as_deref()).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1);
+    });
+    if let Some(dir) = &options.dot_dir
+        && let Err(err) = fs::create_dir_all(dir)
+    {
+        eprintln!("cannot create {dir}: {err}");
+        process::exit(1);
+    }
+
+    let profile = read_profile(&options);
+    let counts = options.counts.as_ref().map(|path| {
+        fs::read_to_string(path).unwrap_or_else(|err| {
+            eprintln!("cannot read counts {path}: {err}");
+            process::exit(1);
+        })
+    });
+    let results = process_functions(&program.functions, |func| {
+        let profile = profile.get(&func.name);
+        process_function(func, &pm, &options, profile, counts.as_deref())
+    });
+    let mut failed = false;
+    let cfgs: Vec<Cfg> = results
+        .into_iter()
+        .map(|(cfg, log, ok)| {
+            eprint!("{log}");
+            failed |= !ok;
+            cfg
+        })
+        .collect();
+
+    program.functions = cfgs.iter().map(Cfg::to_function).collect();
+    output_program(&program);
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Err(USAGE.to_string());
+        }
+        if arg == "--verify-each" {
+            options.verify_each = true;
+            continue;
+        }
+
+        if !arg.starts_with("--") {
+            if options.input.is_some() {
+                return Err(format!("unexpected argument {arg}\n{USAGE}"));
+            }
+            options.input = Some(arg);
+            continue;
+        }
+
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), value.to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("{arg} expects a value"))?;
+                (arg, value)
+            }
+        };
+        match flag.as_str() {
+            "--passes" => options.passes = value,
+            "--dump-after" => options.dump_after = Some(value),
+            "--dot-dir" => options.dot_dir = Some(value),
+            "--profile" => options.profile = Some(value),
+            "--counts" => options.counts = Some(value),
+            _ => return Err(format!("unknown option {flag}\n{USAGE}")),
+        }
+    }
+
+    if let Some(pass) = &options.dump_after
+        && !PASS_NAMES.contains(&pass.as_str())
+        && pass != "all"
+    {
+        return Err(format!("unknown pass {pass}\n{USAGE}"));
+    }
+
+    Ok(options)
+}
+
+fn read_profile(options: &Options) -> Profile {
+    let Some(path) = &options.profile else {
+        return Profile::new();
+    };
+    let profile = fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|text| parse_profile(&text));
+    profile.unwrap_or_else(|err| {
+        eprintln!("cannot read profile {path}: {err}");
+        process::exit(1);
+    })
+}
+
+// Every function is independent of the others, so workers pull the next unprocessed function
+// off a shared counter. Results are put back in program order before anything gets printed.
+fn process_functions<T, F>(functions: &[Function], run: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(&Function) -> T + Sync,
+{
+    let workers = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(functions.len())
+        .max(1);
+    let next = &AtomicUsize::new(0);
+    let run = &run;
+
+    let mut outputs: Vec<(usize, T)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(move || {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(func) = functions.get(i) else {
+                            break;
+                        };
+                        done.push((i, run(func)));
+                    }
+                    done
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    outputs.sort_by_key(|(i, _)| *i);
+    outputs.into_iter().map(|(_, output)| output).collect()
+}
+
+// Returns the transformed function, everything that should go to stderr, and whether verification passed
+fn process_function(
+    func: &Function,
+    pm: &PassManager,
+    options: &Options,
+    profile: Option<&FunctionProfile>,
+    counts: Option<&str>,
+) -> (Cfg, String, bool) {
+    let mut log = String::new();
+    let mut ok = true;
+
+    let mut cfg = CfgBuilder::new().build(func);
+    if let Some(profile) = profile
+        && let Err(err) = cfg.apply_profile(profile)
+    {
+        writeln!(log, "@{}: bad profile: {err}", cfg.name).unwrap();
+        ok = false;
+    }
+    if let Some(counts) = counts
+        && let Err(err) = cfg.read_counts(counts)
+    {
+        writeln!(log, "@{}: bad counts: {err}", cfg.name).unwrap();
+        ok = false;
+    }
+    write_dot(&cfg, options, 0, "input");
+
+    let result = pm.run(&mut cfg, |i, pass, cfg| {
+        if let Some(dump_after) = &options.dump_after
+            && (dump_after == pass || dump_after == "all")
+        {
+            writeln!(log, "; @{} after {pass}", cfg.name).unwrap();
+            writeln!(log, "{cfg}").unwrap();
+        }
+        write_dot(cfg, options, i + 1, pass);
+    });
+    if let Err(err) = &result {
+        write!(log, "@{}: {err}", cfg.name).unwrap();
+        ok = false;
+    }
+
+    (cfg, log, ok)
+}
+
+fn write_dot(cfg: &Cfg, options: &Options, step: usize, pass: &str) {
+    if let Some(dir) = &options.dot_dir {
+        let path = format!("{dir}/{}.{step}.{pass}.dot", cfg.name);
+        if let Err(err) = fs::write(&path, cfg.graphviz()) {
+            eprintln!("cannot write {path}: {err}");
+        }
     }
 }
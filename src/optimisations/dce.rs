@@ -0,0 +1,50 @@
+use std::collections::HashSet;
+
+use bril_rs::ValueOps;
+
+use crate::cfg::Cfg;
+use crate::inst::{InstId, Op};
+use crate::value::ValueId;
+
+impl Cfg {
+    // Deletes instructions whose results are never used, until there is nothing left to delete.
+    // Works both in and out of SSA form, since a single use anywhere keeps every definition of a name alive.
+    pub fn dce(&mut self) {
+        loop {
+            let used: HashSet<ValueId> = self
+                .insts
+                .iter()
+                .flat_map(|(_, data)| data.inst.args.iter().copied())
+                .collect();
+
+            let dead: HashSet<InstId> = self
+                .insts
+                .iter()
+                .filter(|(_, data)| match (&data.inst.op, data.inst.dest) {
+                    (Op::Const(_), Some(dest)) => !used.contains(&dest),
+                    (Op::Value(op), Some(dest)) => !used.contains(&dest) && !has_side_effects(op),
+                    _ => false,
+                })
+                .map(|(id, _)| id)
+                .collect();
+
+            if dead.is_empty() {
+                break;
+            }
+            for &id in &dead {
+                self.insts.free(id);
+            }
+            for block in self.blocks.values_mut() {
+                let before = block.insts.len();
+                block.insts.retain(|id| !dead.contains(id));
+                if block.insts.len() != before {
+                    block.update_definitions(&self.insts);
+                }
+            }
+        }
+    }
+}
+
+fn has_side_effects(op: &ValueOps) -> bool {
+    matches!(op, ValueOps::Call | ValueOps::Alloc | ValueOps::Load)
+}
@@ -0,0 +1,219 @@
+use std::collections::{BTreeSet, HashMap};
+
+use bril_rs::EffectOps;
+
+use crate::analyses::loops::Loop;
+use crate::cfg::{BlockId, Cfg, CfgEdge};
+use crate::inst::{InstId, Op};
+use crate::passes::AnalysisManager;
+use crate::value::ValueId;
+
+// Canonical loop form, as in LLVM's LoopSimplify and LoopRotate:
+// - a preheader, the only block outside the loop jumping to the header, and jumping nowhere else
+// - a single latch, the only block inside the loop jumping back to the header
+// - dedicated exits, blocks outside the loop that only loop blocks jump to
+// - the exit test at the bottom: a while loop, testing at the header, is rotated into a do-while loop guarded
+//   by a copy of the test
+// Whatever the Bril source looked like, code can then be hoisted to the preheader, sunk to the exits, and the
+// body of the loop is known to run at least once when it is entered.
+
+// Headers with more instructions than this are not copied to rotate their loop
+const MAX_ROTATED_HEADER: usize = 16;
+
+// What keeps a loop from being in canonical form: the edges into `block` coming from `preds`
+// should have a block of their own
+#[derive(Debug, Clone)]
+pub struct LoopFormIssue {
+    pub block: BlockId,
+    pub preds: Vec<BlockId>,
+    pub problem: &'static str,
+}
+
+impl Cfg {
+    // Returns whether anything changed
+    pub fn canonicalize_loops(&mut self, am: &mut AnalysisManager) -> bool {
+        // Unreachable blocks jumping into a loop would count as entries
+        let mut changed = self.remove_unreachable();
+        if changed {
+            am.invalidate(&[]);
+        }
+        changed |= self.simplify_loops(am);
+
+        // A rotated loop exits from its latch, so it doesn't qualify again
+        loop {
+            let loops = am.loops(self);
+            let Some(l) = loops.loops.iter().find(|l| self.can_rotate(l)).cloned() else {
+                break;
+            };
+            self.rotate_loop(&l, am);
+            self.simplify_loops(am);
+            changed = true;
+        }
+        changed
+    }
+
+    // Preheaders, single latches and dedicated exits, without rotating anything.
+    // Returns whether any block was added.
+    pub fn simplify_loops(&mut self, am: &mut AnalysisManager) -> bool {
+        let mut changed = false;
+        // Every block added can be part of an outer loop, so the loops are found again each time
+        loop {
+            let loops = am.loops(self);
+            let Some(issue) = loops.loops.iter().find_map(|l| self.loop_form_issue(l)) else {
+                break;
+            };
+            self.split_predecessors(issue.block, &issue.preds);
+            am.invalidate(&[]);
+            changed = true;
+        }
+        changed
+    }
+
+    pub fn loop_form_issue(&self, l: &Loop) -> Option<LoopFormIssue> {
+        let (inside, outside): (Vec<BlockId>, Vec<BlockId>) = self
+            .predecessors(l.header)
+            .into_iter()
+            .partition(|&pred| l.contains(pred));
+        if outside.len() != 1 || self.successors(outside[0]).len() != 1 {
+            return Some(LoopFormIssue {
+                block: l.header,
+                preds: outside,
+                problem: "no preheader",
+            });
+        }
+        if inside.len() != 1 {
+            return Some(LoopFormIssue {
+                block: l.header,
+                preds: inside,
+                problem: "more than one latch",
+            });
+        }
+
+        let exits: BTreeSet<BlockId> = l.exits.iter().map(|&(_, exit)| exit).collect();
+        for exit in exits {
+            let (inside, outside): (Vec<BlockId>, Vec<BlockId>) = self
+                .predecessors(exit)
+                .into_iter()
+                .partition(|&pred| l.contains(pred));
+            if !outside.is_empty() {
+                return Some(LoopFormIssue {
+                    block: exit,
+                    preds: inside,
+                    problem: "exit block also entered from outside the loop",
+                });
+            }
+        }
+        None
+    }
+
+    // A loop in simplified form that is left from the header, and only from there at the bottom.
+    // A loop made of a single block tests at the bottom already.
+    fn can_rotate(&self, l: &Loop) -> bool {
+        let [latch] = l.latches[..] else {
+            return false;
+        };
+        if latch == l.header || self.loop_form_issue(l).is_some() {
+            return false;
+        }
+        if self.successors(latch).iter().any(|&succ| !l.contains(succ)) {
+            return false;
+        }
+        let header = self.block(l.header);
+        let Some(terminator) = header.terminator(&self.insts) else {
+            return false;
+        };
+        if !matches!(self.insts[terminator].op, Op::Effect(EffectOps::Branch)) {
+            return false;
+        }
+        let succs = self.successors(l.header);
+        let stays = succs.iter().filter(|&&succ| l.contains(succ)).count();
+        let size = header.insts.len() - header.phis(&self.insts).count();
+        stays == 1 && succs.len() == 2 && size <= MAX_ROTATED_HEADER
+    }
+
+    // The preheader gets a copy of the header, test included, and stops being the preheader: the header becomes
+    // the bottom of the loop, and the block it stayed in the loop through becomes the new header
+    fn rotate_loop(&mut self, l: &Loop, am: &mut AnalysisManager) {
+        let header = l.header;
+        let preheader = self
+            .predecessors(header)
+            .into_iter()
+            .find(|&pred| !l.contains(pred))
+            .unwrap();
+        let succs = self.successors(header);
+        let new_header = succs
+            .iter()
+            .copied()
+            .find(|&succ| l.contains(succ))
+            .unwrap();
+        let exit = succs
+            .iter()
+            .copied()
+            .find(|&succ| !l.contains(succ))
+            .unwrap();
+        let preheader_label = self.label(preheader).clone();
+        let header_label = self.label(header).clone();
+
+        // Going around the preheader, the values of the header are those its φ-functions get from it.
+        // Outside of SSA form, the copy defines the same variables as the header.
+        let mut copied: HashMap<ValueId, ValueId> = HashMap::new();
+        let phis: Vec<InstId> = self.block(header).phis(&self.insts).collect();
+        for &phi in &phis {
+            let inst = &self.insts[phi];
+            let at = inst
+                .labels
+                .iter()
+                .position(|label| *label == preheader_label);
+            copied.insert(inst.dest.unwrap(), inst.args[at.unwrap()]);
+        }
+        self.remove_phi_incoming(header, &preheader_label);
+
+        let jump = self.block(preheader).terminator(&self.insts).unwrap();
+        self.remove_inst(jump);
+        let body: Vec<InstId> = self.block(header).insts[phis.len()..].to_vec();
+        for id in body {
+            let mut inst = self.insts[id].clone();
+            for arg in &mut inst.args {
+                *arg = copied.get(arg).copied().unwrap_or(*arg);
+            }
+            if self.in_ssa
+                && let Some(dest) = inst.dest
+            {
+                let version = self.values.new_version(dest);
+                copied.insert(dest, version);
+                inst.dest = Some(version);
+            }
+            self.push_inst(preheader, inst);
+        }
+        let edge = self.graph.find_edge(preheader, header).unwrap();
+        self.graph.remove_edge(edge);
+        self.graph
+            .add_edge(preheader, new_header, CfgEdge::default());
+        self.graph.add_edge(preheader, exit, CfgEdge::default());
+
+        // Both targets of the test now have the preheader as a new predecessor
+        for target in [new_header, exit] {
+            let phis: Vec<InstId> = self.block(target).phis(&self.insts).collect();
+            for phi in phis {
+                self.insts.update(phi, |inst| {
+                    let at = inst.labels.iter().position(|label| *label == header_label);
+                    let arg = inst.args[at.unwrap()];
+                    inst.args.push(copied.get(&arg).copied().unwrap_or(arg));
+                    inst.labels.push(preheader_label.clone());
+                });
+            }
+        }
+        self.update_block_definitions(preheader);
+        am.invalidate(&[]);
+
+        // Every value of the header now also has a definition in the preheader
+        if self.in_ssa {
+            let defs: HashMap<ValueId, Vec<(BlockId, ValueId)>> = copied
+                .into_iter()
+                .map(|(value, copy)| (value, vec![(preheader, copy), (header, value)]))
+                .collect();
+            let idoms = am.idoms(self).clone();
+            self.update_ssa(&defs, &idoms, am.dj_graph(self));
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use bril_rs::{EffectOps, Literal, Type, ValueOps};
+
+use crate::cfg::{copy, BlockId, Cfg};
+use crate::inst::{Inst, InstId, Op};
+use crate::passes::AnalysisManager;
+use crate::value::ValueId;
+
+// Promotion of memory to variables: an allocation of a few elements, whose address never leaves the
+// function and is only ever offset by constants, becomes one variable per element. Stores become copies
+// into the variable, loads copies out of it, and the allocation, the pointers into it and the free go.
+// In SSA form the new variables then go through the same φ-placement and renaming as the rest of the
+// function did when it was converted; outside of it, to-ssa takes care of them later.
+// A guard that aborts rolls variables back but not memory, so allocations stored to where a guard may still
+// abort stay in memory.
+
+// Allocations of more elements than this stay in memory
+const MAX_PROMOTED_ELEMENTS: i64 = 8;
+
+// An allocation that can be promoted
+struct Promotable {
+    alloc: InstId,
+    element: Type,
+    elements: i64,
+    // Every pointer into the allocation, with the element it points to
+    pointers: HashMap<ValueId, i64>,
+}
+
+impl Cfg {
+    // Returns whether any allocation was promoted
+    pub fn mem2reg(&mut self, am: &mut AnalysisManager) -> bool {
+        let speculative: HashSet<BlockId> = self
+            .guard_regions()
+            .into_iter()
+            .flat_map(|(_, region, _)| region)
+            .collect();
+        let promotable: Vec<Promotable> = self
+            .blocks_in_order()
+            .flat_map(|block| &block.insts)
+            .filter_map(|&id| self.promotable(id, &speculative))
+            .collect();
+        if promotable.is_empty() {
+            return false;
+        }
+
+        let mut vars = Vec::new();
+        for alloc in &promotable {
+            vars.extend(self.promote(alloc));
+        }
+        for block in self.blocks.values_mut() {
+            block.update_definitions(&self.insts);
+        }
+
+        if self.in_ssa {
+            self.insert_phi_nodes_for(&vars, am.dj_graph(self));
+            self.rename_variables_of(am.idoms(self), &vars);
+        }
+        true
+    }
+
+    fn promotable(&self, id: InstId, speculative: &HashSet<BlockId>) -> Option<Promotable> {
+        let inst = &self.insts[id];
+        let (Op::Value(ValueOps::Alloc), Some(dest)) = (&inst.op, inst.dest) else {
+            return None;
+        };
+        let elements = self.const_int(inst.args[0])?;
+        let Some(Type::Pointer(element)) = self.values.ty(dest) else {
+            return None;
+        };
+        // Elements that are read before they are written get a zero, which pointers don't have
+        if !(1..=MAX_PROMOTED_ELEMENTS).contains(&elements) || zero(element).is_none() {
+            return None;
+        }
+
+        // Find every pointer derived from the allocation and check how each one is used
+        let mut pointers = HashMap::from([(dest, 0)]);
+        let mut work = vec![dest];
+        while let Some(pointer) = work.pop() {
+            // Outside of SSA form, a variable could point somewhere else at the use
+            self.insts.def(pointer)?;
+            let offset = pointers[&pointer];
+            for (user_id, data) in self.insts.iter() {
+                let user = &data.inst;
+                if !user.args.contains(&pointer) {
+                    continue;
+                }
+                match (&user.op, &user.args[..], user.dest) {
+                    (Op::Value(ValueOps::Load), _, _) | (Op::Effect(EffectOps::Free), _, _) => {}
+                    (Op::Effect(EffectOps::Store), [address, value], _)
+                        if *address == pointer
+                            && *value != pointer
+                            && !speculative.contains(&self.insts.block_of(user_id)) => {}
+                    (Op::Value(ValueOps::PtrAdd), [base, by], Some(derived))
+                        if *base == pointer =>
+                    {
+                        let at = offset + self.const_int(*by)?;
+                        if !(0..elements).contains(&at) {
+                            return None;
+                        }
+                        if pointers.insert(derived, at).is_none() {
+                            work.push(derived);
+                        }
+                    }
+                    (Op::Value(ValueOps::Id), _, Some(derived)) => {
+                        if pointers.insert(derived, offset).is_none() {
+                            work.push(derived);
+                        }
+                    }
+                    // Passed to a call, stored, returned, merged with other pointers, ...
+                    _ => return None,
+                }
+            }
+        }
+
+        Some(Promotable {
+            alloc: id,
+            element: element.as_ref().clone(),
+            elements,
+            pointers,
+        })
+    }
+
+    // Rewrites the uses of the allocation and returns the variables made for its elements
+    fn promote(&mut self, alloc: &Promotable) -> Vec<ValueId> {
+        let dest = self.insts[alloc.alloc].dest.unwrap();
+        let name = self.values.debug_name(self.values.origin(dest));
+        let vars: Vec<ValueId> = (0..alloc.elements)
+            .map(|i| self.values.fresh(&format!("{name}_{i}"), &alloc.element))
+            .collect();
+
+        let users: BTreeMap<InstId, Inst> = self
+            .insts
+            .iter()
+            .filter(|(_, data)| {
+                data.inst
+                    .args
+                    .iter()
+                    .any(|arg| alloc.pointers.contains_key(arg))
+            })
+            .map(|(id, data)| (id, data.inst.clone()))
+            .collect();
+        for (id, inst) in users {
+            let element = |pointer: &ValueId| vars[alloc.pointers[pointer] as usize];
+            let replacement = match inst.op {
+                Op::Effect(EffectOps::Store) => copy(element(&inst.args[0]), inst.args[1]),
+                Op::Value(ValueOps::Load) => copy(inst.dest.unwrap(), element(&inst.args[0])),
+                _ => {
+                    // Pointer arithmetic and frees only matter to memory
+                    self.remove_inst(id);
+                    continue;
+                }
+            };
+            self.insts.replace(
+                id,
+                Inst {
+                    pos: inst.pos,
+                    ..replacement
+                },
+            );
+        }
+
+        // The allocation itself makes every element start out as zero
+        let block = self.insts.block_of(alloc.alloc);
+        let at = self
+            .block(block)
+            .insts
+            .iter()
+            .position(|&id| id == alloc.alloc)
+            .unwrap();
+        let pos = self.remove_inst(alloc.alloc).pos;
+        for (i, &var) in vars.iter().enumerate() {
+            let init = Inst {
+                pos: pos.clone(),
+                ..Inst::new(Op::Const(zero(&alloc.element).unwrap()), Some(var), vec![])
+            };
+            self.insert_inst(block, at + i, init);
+        }
+        vars
+    }
+}
+
+fn zero(ty: &Type) -> Option<Literal> {
+    match ty {
+        Type::Int => Some(Literal::Int(0)),
+        Type::Bool => Some(Literal::Bool(false)),
+        Type::Float => Some(Literal::Float(0.0)),
+        Type::Char => Some(Literal::Char('\0')),
+        _ => None,
+    }
+}
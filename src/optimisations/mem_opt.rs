@@ -0,0 +1,275 @@
+use std::collections::{BTreeSet, HashSet};
+
+use bril_rs::{EffectOps, ValueOps};
+
+use crate::analyses::alias::{AliasAnalysis, Location};
+use crate::analyses::memory_ssa::{MemoryAccess, MemoryAccessId, MemorySsa};
+use crate::cfg::{copy, Cfg};
+use crate::inst::{Inst, InstId, Op};
+use crate::passes::AnalysisManager;
+use crate::value::ValueId;
+
+// Redundant memory operations, found by walking Memory SSA and asking the alias analysis about every
+// definition on the way:
+// - a load of what a dominating store just wrote gets the stored value instead
+// - a store that is overwritten before anything can read it goes
+// - an allocation that nothing ever reads from goes, with the stores into it and the free
+// All of it needs SSA form, to forward a value to a load or tell two pointers hold the same address.
+
+impl Cfg {
+    // Returns whether anything changed
+    pub fn optimise_memory(&mut self, am: &mut AnalysisManager) -> bool {
+        if !self.in_ssa {
+            return false;
+        }
+        let mut changed = self.forward_stores(am);
+        changed |= self.eliminate_dead_stores(am);
+        changed |= self.remove_unobserved_allocs();
+        changed
+    }
+
+    // Replaces loads by the value stored to the same address by the latest write to memory above them,
+    // looking through writes that can't touch that address
+    pub fn forward_stores(&mut self, am: &mut AnalysisManager) -> bool {
+        let idoms = am.idoms(self).clone();
+        let mssa = self.memory_ssa(&idoms, am.dj_graph(self));
+        let aliases = self.alias_analysis();
+
+        let mut forwarded: Vec<(InstId, ValueId)> = Vec::new();
+        for block in self.blocks_in_order() {
+            for &id in &block.insts {
+                let inst = &self.insts[id];
+                if !matches!(inst.op, Op::Value(ValueOps::Load)) {
+                    continue;
+                }
+                let load = mssa.of_inst(id).unwrap();
+                if let Some(value) = self.stored_value(&mssa, &aliases, load, inst.args[0]) {
+                    forwarded.push((id, value));
+                }
+            }
+        }
+
+        for &(id, value) in &forwarded {
+            let load = &self.insts[id];
+            let forward = Inst {
+                pos: load.pos.clone(),
+                ..copy(load.dest.unwrap(), value)
+            };
+            self.insts.replace(id, forward);
+        }
+        !forwarded.is_empty()
+    }
+
+    fn stored_value(
+        &self,
+        mssa: &MemorySsa,
+        aliases: &AliasAnalysis,
+        load: MemoryAccessId,
+        pointer: ValueId,
+    ) -> Option<ValueId> {
+        let mut def = mssa.defining(load)?;
+        loop {
+            let MemoryAccess::Def { inst, defining } = mssa.access(def) else {
+                return None;
+            };
+            let inst = &self.insts[*inst];
+            match inst.op {
+                Op::Effect(EffectOps::Store) if aliases.must_alias(inst.args[0], pointer) => {
+                    return Some(inst.args[1]);
+                }
+                Op::Effect(EffectOps::Store | EffectOps::Free)
+                    if aliases.may_alias(inst.args[0], pointer) =>
+                {
+                    return None;
+                }
+                Op::Value(ValueOps::Alloc) if aliases.may_alias(inst.dest.unwrap(), pointer) => {
+                    return None;
+                }
+                Op::Value(ValueOps::Call) | Op::Effect(EffectOps::Call)
+                    if aliases.may_escape(pointer) =>
+                {
+                    return None;
+                }
+                _ => def = *defining,
+            }
+        }
+    }
+
+    // Deletes stores whose value is overwritten or freed on every path before a load could see it.
+    // Memory the caller can see is read when the function returns.
+    pub fn eliminate_dead_stores(&mut self, am: &mut AnalysisManager) -> bool {
+        let idoms = am.idoms(self).clone();
+        let mssa = self.memory_ssa(&idoms, am.dj_graph(self));
+        let aliases = self.alias_analysis();
+        let users = mssa.users();
+        let at_exits: HashSet<MemoryAccessId> = self
+            .layout
+            .iter()
+            .filter(|&&block| idoms.contains_key(&block) && self.successors(block).is_empty())
+            .map(|&block| mssa.live_at_end(block, &idoms))
+            .collect();
+
+        let mut dead: Vec<InstId> = Vec::new();
+        for block in self.blocks_in_order() {
+            for &id in &block.insts {
+                if !matches!(self.insts[id].op, Op::Effect(EffectOps::Store)) {
+                    continue;
+                }
+                let pointer = self.insts[id].args[0];
+                let escapes = aliases.may_escape(pointer);
+                let store = mssa.of_inst(id).unwrap();
+
+                // Follow the memory the store leaves behind until something reads the address,
+                // or it's overwritten. Past a MemoryPhi, the path may have gone around a loop and the pointer
+                // may hold another address by now: a store through it doesn't overwrite anything then.
+                let mut seen = HashSet::new();
+                let mut work = vec![(store, false)];
+                let mut read = false;
+                while let Some((access, past_phi)) = work.pop() {
+                    if !seen.insert((access, past_phi)) {
+                        continue;
+                    }
+                    if escapes && at_exits.contains(&access) {
+                        read = true;
+                        break;
+                    }
+                    for &user in users.get(&access).into_iter().flatten() {
+                        let is_phi = matches!(mssa.access(user), MemoryAccess::Phi { .. });
+                        match self.reads_or_kills(&mssa, &aliases, user, pointer) {
+                            Some(true) => read = true,
+                            Some(false) if !past_phi || !self.is_store(user, &mssa) => {}
+                            _ => work.push((user, past_phi || is_phi)),
+                        }
+                    }
+                    if read {
+                        break;
+                    }
+                }
+                if !read {
+                    dead.push(id);
+                }
+            }
+        }
+
+        for &id in &dead {
+            self.remove_inst(id);
+        }
+        !dead.is_empty()
+    }
+
+    fn is_store(&self, access: MemoryAccessId, mssa: &MemorySsa) -> bool {
+        matches!(mssa.access(access), MemoryAccess::Def { inst, .. }
+            if matches!(self.insts[*inst].op, Op::Effect(EffectOps::Store | EffectOps::Free)))
+    }
+
+    // What an access coming after a store to `pointer` means for it: Some(true) if it may read the stored
+    // value, Some(false) if it doesn't and nothing after it can (a load elsewhere, or an overwrite),
+    // None if it passes the memory on untouched
+    fn reads_or_kills(
+        &self,
+        mssa: &MemorySsa,
+        aliases: &AliasAnalysis,
+        access: MemoryAccessId,
+        pointer: ValueId,
+    ) -> Option<bool> {
+        let inst = match mssa.access(access) {
+            MemoryAccess::Use { inst, .. } => {
+                let address = self.insts[*inst].args[0];
+                return Some(aliases.may_alias(address, pointer));
+            }
+            MemoryAccess::Def { inst, .. } => &self.insts[*inst],
+            MemoryAccess::Phi { .. } | MemoryAccess::LiveOnEntry => return None,
+        };
+        match inst.op {
+            Op::Effect(EffectOps::Store | EffectOps::Free)
+                if aliases.must_alias(inst.args[0], pointer) =>
+            {
+                Some(false)
+            }
+            Op::Value(ValueOps::Call) | Op::Effect(EffectOps::Call)
+                if aliases.may_escape(pointer) =>
+            {
+                Some(true)
+            }
+            _ => None,
+        }
+    }
+
+    // Deletes allocations that are only ever written to and freed, along with those writes and frees and
+    // the pointers into them
+    pub fn remove_unobserved_allocs(&mut self) -> bool {
+        let aliases = self.alias_analysis();
+        let sites: Vec<InstId> = self
+            .blocks_in_order()
+            .flat_map(|block| &block.insts)
+            .copied()
+            .filter(|&id| matches!(self.insts[id].op, Op::Value(ValueOps::Alloc)))
+            .collect();
+
+        let mut removed = false;
+        for site in sites {
+            let Some(unobserved) = self.unobserved_alloc(&aliases, site) else {
+                continue;
+            };
+            for id in unobserved {
+                self.remove_inst(id);
+            }
+            removed = true;
+        }
+        if removed {
+            for block in self.blocks.values_mut() {
+                block.update_definitions(&self.insts);
+            }
+        }
+        removed
+    }
+
+    // The instructions to delete with the allocation, if nothing reads from it
+    fn unobserved_alloc(&self, aliases: &AliasAnalysis, site: InstId) -> Option<BTreeSet<InstId>> {
+        let only_this = BTreeSet::from([Location::Alloc(site)]);
+        let pointers: HashSet<ValueId> = self
+            .insts
+            .iter()
+            .filter_map(|(_, data)| data.inst.dest)
+            .filter(|&value| {
+                aliases
+                    .points_to(value)
+                    .is_some_and(|locations| locations.contains(&Location::Alloc(site)))
+            })
+            .collect();
+        let mut unobserved = BTreeSet::new();
+
+        for (id, data) in self.insts.iter() {
+            let inst = &data.inst;
+            let uses: Vec<usize> = (0..inst.args.len())
+                .filter(|&i| pointers.contains(&inst.args[i]))
+                .collect();
+            if let Some(dest) = inst.dest
+                && pointers.contains(&dest)
+            {
+                // Pointers that may also point elsewhere are needed for that, and a pointer loaded from
+                // memory means the allocation's address was stored somewhere
+                let derived = matches!(
+                    inst.op,
+                    Op::Value(ValueOps::Alloc | ValueOps::Id | ValueOps::Phi | ValueOps::PtrAdd)
+                );
+                if !derived || aliases.points_to(dest) != Some(&only_this) {
+                    return None;
+                }
+                unobserved.insert(id);
+                continue;
+            }
+            if uses.is_empty() {
+                continue;
+            }
+            // Written to or freed, and through nothing but a pointer to it
+            match inst.op {
+                Op::Effect(EffectOps::Store | EffectOps::Free) if uses == [0] => {
+                    unobserved.insert(id);
+                }
+                _ => return None,
+            }
+        }
+        Some(unobserved)
+    }
+}
@@ -1 +1,9 @@
-mod sccp;
\ No newline at end of file
+mod dce;
+mod layout;
+mod loop_simplify;
+mod mem2reg;
+mod mem_opt;
+mod pre;
+mod sccp;
+mod speculate;
+pub mod unroll;
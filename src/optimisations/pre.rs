@@ -0,0 +1,404 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use bril_rs::{Type, ValueOps};
+
+use crate::cfg::{copy, BlockId, Cfg};
+use crate::inst::{Inst, Op};
+use crate::value::ValueId;
+
+// Partial redundancy elimination by lazy code motion
+// "Lazy Code Motion", Knoop, Rüthing and Steffen, PLDI 1992
+// https://dl.acm.org/doi/10.1145/143095.143136
+// in the edge based formulation of Drechsler and Stadel, as presented in Engineering a Compiler, section 10.3
+//
+// Expressions are lexical: an operation and the variables it reads. That's why this runs outside of SSA form:
+// in it, the same computation on two versions of a variable wouldn't be recognized as the same expression,
+// and the temporary holding an expression would be defined in several places.
+//
+// A guard that aborts takes the variables back to where the speculation started, temporaries included. Along
+// the edge it aborts along nothing is available or anticipated, and computations go on it like on the edges
+// from the entry.
+
+// Indices into `Expressions::exprs`
+type ExprSet = HashSet<usize>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Expr {
+    op: ValueOps,
+    args: Vec<ValueId>,
+}
+
+#[derive(Debug, Default)]
+struct Expressions {
+    exprs: Vec<Expr>,
+    index: HashMap<Expr, usize>,
+    // Type of the result, taken from any of the computations
+    types: Vec<Type>,
+    // The expressions reading each variable, that a definition of it kills
+    users: HashMap<ValueId, Vec<usize>>,
+}
+
+impl Expressions {
+    fn get(&self, inst: &Inst) -> Option<usize> {
+        match &inst.op {
+            Op::Value(op) if is_candidate(op) => self
+                .index
+                .get(&Expr {
+                    op: *op,
+                    args: inst.args.clone(),
+                })
+                .copied(),
+            _ => None,
+        }
+    }
+
+    fn killed_by(&self, var: ValueId) -> &[usize] {
+        self.users.get(&var).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
+
+// What a block does to each expression, on its own
+#[derive(Debug, Default)]
+struct Local {
+    // Computed before any of its operands is defined in the block
+    upward_exposed: ExprSet,
+    // Computed after the last definition of any of its operands in the block
+    downward_exposed: ExprSet,
+    // Has an operand defined in the block
+    killed: ExprSet,
+}
+
+// Anything that reads or changes state, and copies, which there is nothing to gain from moving
+fn is_candidate(op: &ValueOps) -> bool {
+    !matches!(
+        op,
+        ValueOps::Call | ValueOps::Alloc | ValueOps::Load | ValueOps::Id | ValueOps::Phi
+    )
+}
+
+impl Cfg {
+    // Returns whether any computation was moved or removed.
+    // Critical edges get split whatever happens, the blocks made for that stay even if nothing goes in them.
+    pub fn lazy_code_motion(&mut self) -> bool {
+        if self.in_ssa {
+            return false;
+        }
+        // Unreachable blocks would have every expression available on entry
+        self.remove_unreachable();
+        // Computations are inserted on edges, which needs a block to put them in for every edge
+        self.split_critical_edges();
+
+        let exprs = self.expressions();
+        if exprs.exprs.is_empty() {
+            return false;
+        }
+        let all: ExprSet = (0..exprs.exprs.len()).collect();
+        let local: HashMap<BlockId, Local> = self
+            .layout
+            .iter()
+            .map(|&block| (block, self.local_properties(block, &exprs)))
+            .collect();
+        let entry = self.block_id(&self.entry_label);
+        let order = self.reverse_postorder();
+        let edges: Vec<(BlockId, BlockId)> = order
+            .iter()
+            .flat_map(|&from| self.successors(from).into_iter().map(move |to| (from, to)))
+            .collect();
+        let aborts: HashSet<(BlockId, BlockId)> = self
+            .guard_regions()
+            .into_iter()
+            .map(|(guard, _, target)| (guard, target))
+            .collect();
+        let none = ExprSet::new();
+
+        // Available on exit: computed on every path from the entry, and not killed since
+        let mut avail_out: HashMap<BlockId, ExprSet> =
+            order.iter().map(|&block| (block, all.clone())).collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &order {
+                let avail_in = if block == entry {
+                    ExprSet::new()
+                } else {
+                    intersection(self.predecessors(block).iter().map(|&pred| {
+                        if aborts.contains(&(pred, block)) {
+                            &none
+                        } else {
+                            &avail_out[&pred]
+                        }
+                    }))
+                };
+                let out = transfer(&local[&block].downward_exposed, avail_in, &local[&block]);
+                if out != avail_out[&block] {
+                    avail_out.insert(block, out);
+                    changed = true;
+                }
+            }
+        }
+
+        // Anticipated on entry: computed on every path to the exit, before any operand is defined
+        let mut ant_in: HashMap<BlockId, ExprSet> =
+            order.iter().map(|&block| (block, all.clone())).collect();
+        let mut ant_out: HashMap<BlockId, ExprSet> = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in order.iter().rev() {
+                let out = intersection(self.successors(block).iter().map(|&succ| {
+                    if aborts.contains(&(block, succ)) {
+                        &none
+                    } else {
+                        &ant_in[&succ]
+                    }
+                }));
+                let ant = transfer(&local[&block].upward_exposed, out.clone(), &local[&block]);
+                ant_out.insert(block, out);
+                if ant != ant_in[&block] {
+                    ant_in.insert(block, ant);
+                    changed = true;
+                }
+            }
+        }
+
+        // The edges where computing an expression is safe, and couldn't happen any earlier
+        let earliest: HashMap<(BlockId, BlockId), ExprSet> = edges
+            .iter()
+            .map(|&(from, to)| {
+                if aborts.contains(&(from, to)) {
+                    return ((from, to), ant_in[&to].clone());
+                }
+                let mut set: ExprSet = ant_in[&to].difference(&avail_out[&from]).copied().collect();
+                if from != entry {
+                    set.retain(|e| local[&from].killed.contains(e) || !ant_out[&from].contains(e));
+                }
+                ((from, to), set)
+            })
+            .collect();
+
+        // How far down the computations can be pushed from their earliest placement
+        let mut later_in: HashMap<BlockId, ExprSet> =
+            order.iter().map(|&block| (block, all.clone())).collect();
+        later_in.insert(entry, ExprSet::new());
+        let mut later: HashMap<(BlockId, BlockId), ExprSet> = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &(from, to) in &edges {
+                let mut set = earliest[&(from, to)].clone();
+                if !aborts.contains(&(from, to)) {
+                    set.extend(later_in[&from].difference(&local[&from].upward_exposed));
+                }
+                later.insert((from, to), set);
+            }
+            for &block in &order {
+                if block == entry {
+                    continue;
+                }
+                let set = intersection(
+                    self.predecessors(block)
+                        .into_iter()
+                        .map(|pred| &later[&(pred, block)]),
+                );
+                if set != later_in[&block] {
+                    later_in.insert(block, set);
+                    changed = true;
+                }
+            }
+        }
+
+        let insert: Vec<((BlockId, BlockId), Vec<usize>)> = edges
+            .iter()
+            .map(|&edge| {
+                let mut set: Vec<usize> = later[&edge]
+                    .difference(&later_in[&edge.1])
+                    .copied()
+                    .collect();
+                set.sort();
+                (edge, set)
+            })
+            .filter(|(_, set)| !set.is_empty())
+            .collect();
+        let delete: HashMap<BlockId, ExprSet> = order
+            .iter()
+            .filter(|&&block| block != entry)
+            .map(|&block| {
+                let set = local[&block]
+                    .upward_exposed
+                    .difference(&later_in[&block])
+                    .copied()
+                    .collect();
+                (block, set)
+            })
+            .collect();
+
+        // Every expression that moves gets a temporary, which every computation of it writes to from now on
+        let moved: BTreeSet<usize> = insert
+            .iter()
+            .flat_map(|(_, set)| set.iter())
+            .chain(delete.values().flatten())
+            .copied()
+            .collect();
+        if moved.is_empty() {
+            return false;
+        }
+        let temps: HashMap<usize, ValueId> = moved
+            .into_iter()
+            .map(|e| (e, self.values.fresh("pre", &exprs.types[e])))
+            .collect();
+
+        for &block in &order {
+            let delete = delete.get(&block).cloned().unwrap_or_default();
+            self.rewrite_computations(block, &exprs, &temps, &delete);
+        }
+        // After rewriting, so that the inserted computations are left as they are
+        for ((from, to), set) in insert {
+            // After splitting, an edge either is the only one leaving `from` or the only one entering `to`
+            let (block, mut at) = if self.successors(from).len() == 1 {
+                (from, self.block(from).end_index(&self.insts))
+            } else {
+                (to, 0)
+            };
+            for e in set {
+                let expr = &exprs.exprs[e];
+                let inst = Inst::new(Op::Value(expr.op), Some(temps[&e]), expr.args.clone());
+                self.insert_inst(block, at, inst);
+                at += 1;
+            }
+            self.update_block_definitions(block);
+        }
+
+        true
+    }
+
+    fn expressions(&self) -> Expressions {
+        let mut exprs = Expressions::default();
+        for block in self.blocks_in_order() {
+            for &id in &block.insts {
+                let inst = &self.insts[id];
+                let (Op::Value(op), Some(dest)) = (&inst.op, inst.dest) else {
+                    continue;
+                };
+                if !is_candidate(op) {
+                    continue;
+                }
+                let expr = Expr {
+                    op: *op,
+                    args: inst.args.clone(),
+                };
+                if exprs.index.contains_key(&expr) {
+                    continue;
+                }
+                let e = exprs.exprs.len();
+                for &arg in &expr.args {
+                    let users = exprs.users.entry(arg).or_default();
+                    if !users.contains(&e) {
+                        users.push(e);
+                    }
+                }
+                exprs.index.insert(expr.clone(), e);
+                exprs.exprs.push(expr);
+                exprs.types.push(self.values.ty(dest).unwrap().clone());
+            }
+        }
+        exprs
+    }
+
+    fn local_properties(&self, block: BlockId, exprs: &Expressions) -> Local {
+        let mut local = Local::default();
+        let insts = &self.block(block).insts;
+
+        for &id in insts {
+            let inst = &self.insts[id];
+            if let Some(e) = exprs.get(inst)
+                && !local.killed.contains(&e)
+            {
+                local.upward_exposed.insert(e);
+            }
+            if let Some(dest) = inst.dest {
+                local.killed.extend(exprs.killed_by(dest));
+            }
+        }
+
+        // The definition made by an instruction comes after the computation in it
+        let mut killed_after = ExprSet::new();
+        for &id in insts.iter().rev() {
+            let inst = &self.insts[id];
+            if let Some(dest) = inst.dest {
+                killed_after.extend(exprs.killed_by(dest));
+            }
+            if let Some(e) = exprs.get(inst)
+                && !killed_after.contains(&e)
+            {
+                local.downward_exposed.insert(e);
+            }
+        }
+
+        local
+    }
+
+    // Computations of moved expressions go through their temporary: `x = op a b` becomes `t = op a b; x = id t`,
+    // or just `x = id t` where `t` holds the value already, because the expression was computed earlier
+    // in the block or on every edge leading here.
+    fn rewrite_computations(
+        &mut self,
+        block: BlockId,
+        exprs: &Expressions,
+        temps: &HashMap<usize, ValueId>,
+        delete: &ExprSet,
+    ) {
+        let old_insts = std::mem::take(&mut self.block_mut(block).insts);
+        let mut insts = Vec::with_capacity(old_insts.len());
+        // The expressions whose temporary holds their current value
+        let mut available = delete.clone();
+
+        for id in old_insts {
+            insts.push(id);
+            let inst = &self.insts[id];
+            let dest = inst.dest;
+            if let Some(e) = exprs.get(inst)
+                && let Some(&temp) = temps.get(&e)
+            {
+                let to_dest = Inst {
+                    pos: inst.pos.clone(),
+                    ..copy(dest.unwrap(), temp)
+                };
+                if available.contains(&e) {
+                    self.insts.replace(id, to_dest);
+                } else {
+                    self.insts.update(id, |inst| inst.dest = Some(temp));
+                    insts.push(self.insts.alloc(to_dest, block));
+                    available.insert(e);
+                }
+            }
+            if let Some(dest) = dest {
+                for e in exprs.killed_by(dest) {
+                    available.remove(e);
+                }
+            }
+        }
+
+        self.block_mut(block).insts = insts;
+        self.update_block_definitions(block);
+    }
+}
+
+// Only the entry has no predecessors and only exits have no successors, and nothing is available
+// on entry to the first or anticipated on exit from the others
+fn intersection<'a>(mut sets: impl Iterator<Item = &'a ExprSet>) -> ExprSet {
+    let Some(first) = sets.next() else {
+        return ExprSet::new();
+    };
+    let mut result = first.clone();
+    for set in sets {
+        result.retain(|e| set.contains(e));
+    }
+    result
+}
+
+// `generated ∪ (input − killed)`
+fn transfer(generated: &ExprSet, mut input: ExprSet, local: &Local) -> ExprSet {
+    input.retain(|e| !local.killed.contains(e));
+    input.extend(generated);
+    input
+}
@@ -1,13 +1,18 @@
 use std::collections::{HashMap, HashSet, VecDeque};
 
-use bril_rs::{EffectOps, Instruction, Literal, ValueOps};
-use petgraph::{
-    graph::{EdgeIndex, EdgeReference},
-    visit::EdgeRef,
-    Direction,
-};
+use bril_rs::{EffectOps, Literal, ValueOps};
 
-use crate::cfg::Cfg;
+use crate::cfg::{BlockId, Cfg, SsaGraph};
+use crate::inst::{Inst, InstId, Op};
+use crate::value::ValueId;
+
+// Sparse conditional constant propagation, on functions in SSA form.
+// "Constant Propagation with Conditional Branches", Wegman and Zadeck, TOPLAS 1991
+//
+// Values start out at Top, and only ever go down. Values without a definition, arguments and variables
+// that are undefined on entry, are Bottom: nothing is known about them.
+// Instructions whose result is a constant become that constant, branches on a constant become jumps, and
+// the blocks no executable edge leads to are removed.
 
 #[derive(Debug, Clone, PartialEq)]
 enum SccpLattice {
@@ -27,178 +32,287 @@ fn meet(a: &SccpLattice, b: &SccpLattice) -> SccpLattice {
     }
 }
 
-impl Cfg {
-    pub fn sccp(&self) {
-        let (ssa_graph, names) = self.ssa_graph();
+struct Sccp<'a> {
+    cfg: &'a Cfg,
+    ssa_graph: &'a SsaGraph,
+    lattices: HashMap<ValueId, SccpLattice>,
+    executable_cfg_edges: HashSet<(BlockId, BlockId)>,
+    visited_blocks: HashSet<BlockId>,
+    // Targets of the edges that just became executable
+    cfg_work_queue: VecDeque<BlockId>,
+    ssa_work_queue: VecDeque<InstId>,
+}
 
+impl Cfg {
+    // Returns whether anything changed
+    pub fn sccp(&mut self, ssa_graph: &SsaGraph) -> bool {
+        if !self.in_ssa {
+            return false;
+        }
         let entry_node = self.blocks[&self.entry_label].node;
-
-        let mut cfg_work_queue =
-            VecDeque::from_iter(self.graph.edges_directed(entry_node, Direction::Outgoing));
-        let mut ssa_work_queue = VecDeque::<EdgeReference<()>>::new();
-
-        let mut executable_cfg_edges = HashSet::new();
-        let mut visited_blocks = HashSet::new();
+        let mut sccp = Sccp {
+            cfg: self,
+            ssa_graph,
+            lattices: HashMap::new(),
+            executable_cfg_edges: HashSet::new(),
+            visited_blocks: HashSet::new(),
+            cfg_work_queue: VecDeque::new(),
+            ssa_work_queue: VecDeque::new(),
+        };
+        sccp.visit_block(entry_node);
 
         // Remove the top element of one of the two work lists
         loop {
-            if let Some(edge) = cfg_work_queue.pop_front() {
-                // Mark the edge as executable
-                executable_cfg_edges.insert(edge.id());
-
+            if let Some(target) = sccp.cfg_work_queue.pop_front() {
                 // Visit every φ-function associated with the target node
-                let target_label = &self.graph[edge.target()];
-                let target_block = &self.blocks[target_label];
-                for inst in &target_block.insts {
-                    if matches!(
-                        inst,
-                        Instruction::Value {
-                            op: ValueOps::Phi,
-                            ..
-                        }
-                    ) {
-                        self.visit_phi();
-                    }
+                for phi in self.block(target).phis(&self.insts) {
+                    sccp.visit_inst(phi);
                 }
 
                 // If the target node was reached the first time via the CFGWorkList, visit all its operations
-                if !visited_blocks.contains(target_label) {
-                    for inst in &target_block.insts {
-                        self.visit_expr();
-                    }
-                    visited_blocks.insert(target_label);
+                if !sccp.visited_blocks.contains(&target) {
+                    sccp.visit_block(target);
                 }
+                continue;
+            }
 
-                // If the target node has a single, non-executable outgoing edge, append that edge to the CFGWorkList
-                let mut edges = self
-                    .graph
-                    .edges_directed(edge.target(), Direction::Outgoing);
-                if let Some(edge) = edges.next() && !executable_cfg_edges.contains(&edge.id()) && edges.next().is_none() {
-                    cfg_work_queue.push_back(edge);
+            if let Some(target_inst) = sccp.ssa_work_queue.pop_front() {
+                // Visit the operation if any of the incoming edges of its CFG node is executable,
+                // a φ-function only looks at the arguments of the executable ones anyway
+                if sccp
+                    .visited_blocks
+                    .contains(&self.insts.block_of(target_inst))
+                {
+                    sccp.visit_inst(target_inst);
                 }
-
                 continue;
             }
 
-            if let Some(edge) = ssa_work_queue.pop_front() {
-                let (block, index) = ssa_graph[edge.target()];
-
-                let target_block = &self.blocks[block];
-                let target_inst = &target_block.insts[index];
-
-                // When the target operation is a φ-function visit that φ-function
-                if matches!(
-                    target_inst,
-                    Instruction::Value {
-                        op: ValueOps::Phi,
-                        ..
-                    }
-                ) {
-                    self.visit_phi();
+            // Continue until both work lists become empty.
+            break;
+        }
+
+        let Sccp {
+            lattices,
+            visited_blocks,
+            ..
+        } = sccp;
+        self.apply_constants(&lattices, &visited_blocks)
+    }
+
+    fn apply_constants(
+        &mut self,
+        lattices: &HashMap<ValueId, SccpLattice>,
+        executable: &HashSet<BlockId>,
+    ) -> bool {
+        let mut changed = false;
+        let blocks: Vec<BlockId> = self
+            .layout
+            .iter()
+            .copied()
+            .filter(|block| executable.contains(block))
+            .collect();
+
+        for &block in &blocks {
+            let insts = self.block(block).insts.clone();
+            for id in insts {
+                let inst = &self.insts[id];
+                let (Some(dest), Op::Value(_)) = (inst.dest, &inst.op) else {
+                    continue;
+                };
+                let Some(SccpLattice::Constant(value)) = lattices.get(&dest) else {
+                    continue;
+                };
+                let is_phi = inst.is_phi();
+                let constant = Inst {
+                    pos: inst.pos.clone(),
+                    ..Inst::new(Op::Const(value.clone()), Some(dest), vec![])
+                };
+                // φ-functions come first in their block, the constant goes after them
+                if is_phi {
+                    self.remove_inst(id);
+                    let at = self.block(block).phis(&self.insts).count();
+                    self.insert_inst(block, at, constant);
                 } else {
-                    // For other operations,
-                    let a = self
-                        .graph
-                        .node_indices()
-                        .find(|&ni| &self.graph[ni] == block)
-                        .unwrap();
-                    // examine the executable flag of the incoming edges of the respective CFG node
-                    let b = self
-                        .graph
-                        .edges_directed(a, Direction::Incoming)
-                        .any(|edge| executable_cfg_edges.contains(&edge.id()));
-                    // Visit the operation if any of the edges is executable.
-                    if b {
-                        self.visit_expr();
-                    }
+                    self.insts.replace(id, constant);
                 }
+                changed = true;
+            }
+        }
 
+        for &block in &blocks {
+            let Some(terminator) = self.block(block).terminator(&self.insts) else {
                 continue;
+            };
+            let inst = &self.insts[terminator];
+            if let Op::Effect(EffectOps::Branch) = inst.op
+                && let Some(SccpLattice::Constant(Literal::Bool(taken))) =
+                    lattices.get(&inst.args[0])
+            {
+                let keep = self.block_id(&inst.labels[if *taken { 0 } else { 1 }]);
+                self.fold_branch(block, keep);
+                changed = true;
             }
+        }
 
-            // Continue until both work lists become empty.
-            break;
+        changed |= self.remove_unreachable();
+        for block in self.blocks.values_mut() {
+            block.update_definitions(&self.insts);
         }
+        changed
     }
+}
 
-    fn visit_phi(&self) {}
+impl Sccp<'_> {
+    fn lattice(&self, value: ValueId) -> SccpLattice {
+        if self.cfg.insts.def(value).is_none() {
+            return SccpLattice::Bottom;
+        }
+        self.lattices
+            .get(&value)
+            .cloned()
+            .unwrap_or(SccpLattice::Top)
+    }
 
-    fn visit_expr(&self) {}
+    // Lowers the lattice value of `dest`, and queues its uses if that changed it
+    fn lower(&mut self, dest: ValueId, lattice: SccpLattice) {
+        let old = self.lattice(dest);
+        let new = meet(&old, &lattice);
+        if new == old {
+            return;
+        }
+        self.lattices.insert(dest, new);
+        let Some(def) = self.cfg.insts.def(dest) else {
+            return;
+        };
+        let node = self.ssa_graph.nodes[&def];
+        let uses = self.ssa_graph.graph.neighbors(node);
+        self.ssa_work_queue
+            .extend(uses.map(|node| self.ssa_graph.graph[node]));
+    }
+
+    fn mark_edge(&mut self, source: BlockId, target: &str) {
+        let target = self.cfg.block_id(target);
+        // Mark the edge as executable
+        if self.executable_cfg_edges.insert((source, target)) {
+            self.cfg_work_queue.push_back(target);
+        }
+    }
+
+    fn visit_block(&mut self, block: BlockId) {
+        self.visited_blocks.insert(block);
+        for &id in &self.cfg.block(block).insts {
+            self.visit_inst(id);
+        }
+    }
 
-    fn visit_inst<'cfg>(
-        &'cfg self,
-        curr_block_label: &String,
-        inst_index: usize,
-        executable_cfg_edges: &mut HashSet<EdgeIndex>,
-        lattices: &mut HashMap<&'cfg String, SccpLattice>,
-    ) {
-        let inst = &self.blocks[curr_block_label].insts[inst_index];
+    fn visit_inst(&mut self, id: InstId) {
+        let cfg = self.cfg;
+        let block = cfg.insts.block_of(id);
+        let inst = &cfg.insts[id];
 
-        match inst {
+        match (&inst.op, inst.dest) {
             // φ-functions:
             // Combine the data-flow information from the node’s operands where the corresponding control-flow edge is executable.
-            Instruction::Value {
-                op: ValueOps::Phi,
-                dest,
-                args,
-                labels,
-                ..
-            } => {
-                let dest_block = self
-                    .graph
-                    .node_indices()
-                    .find(|&ni| &self.graph[ni] == curr_block_label)
-                    .unwrap();
-
-                let lattice = args
+            (Op::Value(ValueOps::Phi), Some(dest)) => {
+                let lattice = inst
+                    .args
                     .iter()
-                    .zip(labels)
-                    .filter_map(|(arg, source_label)| {
-                        let source_block = self
-                            .graph
-                            .node_indices()
-                            .find(|&ni| &self.graph[ni] == source_label)
-                            .unwrap();
-
-                        let edge = self.graph.find_edge(source_block, dest_block).unwrap();
-
-                        if executable_cfg_edges.contains(&edge) {
-                            Some(arg)
-                        } else {
-                            None
-                        }
+                    .zip(&inst.labels)
+                    .filter(|(_, source)| {
+                        self.executable_cfg_edges
+                            .contains(&(cfg.block_id(source), block))
                     })
-                    .fold(SccpLattice::Top, |acc, arg| {
-                        // SccpLattice::Top is the default value
-                        // None = SccpLattice::Top
-                        let arg = lattices.get(arg).unwrap_or(&SccpLattice::Top);
-                        meet(&acc, arg)
+                    .fold(SccpLattice::Top, |acc, (&arg, _)| {
+                        meet(&acc, &self.lattice(arg))
                     });
-
-                lattices.insert(dest, lattice);
+                self.lower(dest, lattice);
+            }
+            (Op::Const(value), Some(dest)) => {
+                self.lower(dest, SccpLattice::Constant(value.clone()));
+            }
+            // Other operations
+            // Update the operation’s data-flow information by applying its transfer function.
+            (Op::Value(op), Some(dest)) => {
+                let lattice = self.evaluate(op, &inst.args);
+                self.lower(dest, lattice);
             }
             // Conditional branches:
             // Examine the branch’s condition(s) using the data-flow information of its operands;
             // Determine all outgoing edges of the branch’s CFG node whose condition is potentially
             // satisfied; Append the CFG edges that were non-executable to the CFGWorkList.
-            Instruction::Effect {
-                op: EffectOps::Branch,
-                ..
-            } => {}
-            // Other operations
-            // Update the operation’s data-flow information by applying its transfer function.
-            Instruction::Constant { dest, value, .. } => {
-                match lattices.get(dest) {
-                    None => {
-                        lattices.insert(dest, SccpLattice::Constant(value.clone()));
-                    }
-                    Some(SccpLattice::Constant(constant)) if constant == value => {}
-                    _ => {
-                        lattices.insert(dest, SccpLattice::Bottom);
-                    }
-                };
+            (Op::Effect(EffectOps::Branch), _) => match self.lattice(inst.args[0]) {
+                SccpLattice::Top => {}
+                SccpLattice::Constant(Literal::Bool(taken)) => {
+                    self.mark_edge(block, &inst.labels[if taken { 0 } else { 1 }]);
+                }
+                _ => {
+                    self.mark_edge(block, &inst.labels[0]);
+                    self.mark_edge(block, &inst.labels[1]);
+                }
+            },
+            (Op::Effect(EffectOps::Jump | EffectOps::Guard), _) => {
+                for label in &inst.labels {
+                    self.mark_edge(block, label);
+                }
             }
             _ => {}
         }
     }
+
+    fn evaluate(&self, op: &ValueOps, args: &[ValueId]) -> SccpLattice {
+        if !is_foldable(op) {
+            return SccpLattice::Bottom;
+        }
+        let mut literals = Vec::with_capacity(args.len());
+        for &arg in args {
+            match self.lattice(arg) {
+                SccpLattice::Bottom => return SccpLattice::Bottom,
+                SccpLattice::Top => return SccpLattice::Top,
+                SccpLattice::Constant(literal) => literals.push(literal),
+            }
+        }
+        fold(op, &literals).map_or(SccpLattice::Bottom, SccpLattice::Constant)
+    }
+}
+
+fn is_foldable(op: &ValueOps) -> bool {
+    matches!(
+        op,
+        ValueOps::Id
+            | ValueOps::Add
+            | ValueOps::Sub
+            | ValueOps::Mul
+            | ValueOps::Div
+            | ValueOps::Eq
+            | ValueOps::Lt
+            | ValueOps::Gt
+            | ValueOps::Le
+            | ValueOps::Ge
+            | ValueOps::Not
+            | ValueOps::And
+            | ValueOps::Or
+    )
+}
+
+// Integer arithmetic wraps around like it does in brili. Division by zero is left for the program to fail at.
+fn fold(op: &ValueOps, args: &[Literal]) -> Option<Literal> {
+    use Literal::{Bool, Int};
+    let literal = match (op, args) {
+        (ValueOps::Id, [a]) => a.clone(),
+        (ValueOps::Add, [Int(a), Int(b)]) => Int(a.wrapping_add(*b)),
+        (ValueOps::Sub, [Int(a), Int(b)]) => Int(a.wrapping_sub(*b)),
+        (ValueOps::Mul, [Int(a), Int(b)]) => Int(a.wrapping_mul(*b)),
+        (ValueOps::Div, [Int(a), Int(b)]) if *b != 0 => Int(a.wrapping_div(*b)),
+        (ValueOps::Eq, [Int(a), Int(b)]) => Bool(a == b),
+        (ValueOps::Lt, [Int(a), Int(b)]) => Bool(a < b),
+        (ValueOps::Gt, [Int(a), Int(b)]) => Bool(a > b),
+        (ValueOps::Le, [Int(a), Int(b)]) => Bool(a <= b),
+        (ValueOps::Ge, [Int(a), Int(b)]) => Bool(a >= b),
+        (ValueOps::Not, [Bool(a)]) => Bool(!a),
+        (ValueOps::And, [Bool(a), Bool(b)]) => Bool(*a && *b),
+        (ValueOps::Or, [Bool(a), Bool(b)]) => Bool(*a || *b),
+        _ => return None,
+    };
+    Some(literal)
 }
@@ -0,0 +1,187 @@
+use bril_rs::{EffectOps, Type, ValueOps};
+
+use crate::analyses::loops::Loop;
+use crate::cfg::{BlockId, Cfg, CfgEdge};
+use crate::inst::{Inst, Op};
+use crate::passes::AnalysisManager;
+
+// Trace-based speculation, with Bril's speculation extension: the hot path through the body of an innermost
+// loop is copied into a straight line of code between `speculate` and `commit`, with every branch on it
+// turned into a guard that the path keeps being taken. A guard that fails rolls the variables back and
+// goes to the original loop header, which runs the iteration the slow way.
+// Only variables get rolled back, so nothing on the trace may touch memory or print, or fail where the
+// original code wouldn't have run it.
+//
+// This works outside of SSA form, where the copies can define the same variables as the original code.
+
+// Traces don't get longer than this many blocks
+const MAX_TRACE_BLOCKS: usize = 8;
+
+impl Cfg {
+    // Returns whether any speculative region was formed
+    pub fn speculate_traces(&mut self, am: &mut AnalysisManager) -> bool {
+        if self.in_ssa {
+            return false;
+        }
+        if self.remove_unreachable() {
+            am.invalidate(&[]);
+        }
+        let loops = am.loops(self);
+
+        // Innermost loops don't share blocks, so the traces don't get in each other's way.
+        // Loops a profile says never ran are left alone.
+        let traces: Vec<Vec<BlockId>> = (0..loops.loops.len())
+            .filter(|&i| !loops.loops.iter().any(|other| other.parent == Some(i)))
+            .filter(|&i| self.block_count(loops.loops[i].header) != Some(0))
+            .filter_map(|i| self.hot_path(&loops.loops[i]))
+            .collect();
+        for trace in &traces {
+            self.form_speculative_region(trace);
+        }
+        !traces.is_empty()
+    }
+
+    // The path through the loop most likely to be taken: at every branch the edge taken more often, when there
+    // are counts, and otherwise a guess from the shape of the graph: staying in the loop beats leaving it,
+    // and otherwise the branch is taken.
+    // At least one branch has to be on it, or there is nothing to speculate on.
+    fn hot_path(&self, l: &Loop) -> Option<Vec<BlockId>> {
+        let mut trace = Vec::new();
+        let mut block = l.header;
+        while self.can_speculate(block) && !trace.contains(&block) {
+            trace.push(block);
+            if trace.len() == MAX_TRACE_BLOCKS {
+                break;
+            }
+            let terminator = &self.insts[self.block(block).terminator(&self.insts)?];
+            let next = match terminator.op {
+                Op::Effect(EffectOps::Jump) => self.block_id(&terminator.labels[0]),
+                Op::Effect(EffectOps::Branch) => {
+                    let [taken, not_taken] = [0, 1].map(|i| self.block_id(&terminator.labels[i]));
+                    match (
+                        self.edge_count(block, taken),
+                        self.edge_count(block, not_taken),
+                    ) {
+                        (Some(a), Some(b)) if a != b => {
+                            if a > b {
+                                taken
+                            } else {
+                                not_taken
+                            }
+                        }
+                        _ if l.contains(taken) || !l.contains(not_taken) => taken,
+                        _ => not_taken,
+                    }
+                }
+                _ => break,
+            };
+            if next == l.header {
+                break;
+            }
+            block = next;
+        }
+
+        let branches = trace[..trace.len().saturating_sub(1)].iter().any(|&block| {
+            let terminator = self.block(block).terminator(&self.insts).unwrap();
+            matches!(self.insts[terminator].op, Op::Effect(EffectOps::Branch))
+        });
+        branches.then_some(trace)
+    }
+
+    // Nothing in the block changes memory, prints, calls or may fail
+    fn can_speculate(&self, block: BlockId) -> bool {
+        self.block(block)
+            .insts
+            .iter()
+            .all(|&id| match &self.insts[id].op {
+                Op::Const(_) => true,
+                Op::Value(op) => !matches!(
+                    op,
+                    ValueOps::Call
+                        | ValueOps::Alloc
+                        | ValueOps::Load
+                        | ValueOps::Div
+                        | ValueOps::Phi
+                ),
+                Op::Effect(op) => matches!(
+                    op,
+                    EffectOps::Jump | EffectOps::Branch | EffectOps::Return | EffectOps::Nop
+                ),
+            })
+    }
+
+    // Puts the speculative copy of `trace` right before its first block, and makes every edge that went to that
+    // block go to the copy instead. The original code is only entered when a guard fails.
+    fn form_speculative_region(&mut self, trace: &[BlockId]) {
+        let start = trace[0];
+        let start_label = self.label(start).clone();
+        let preds = self.predecessors(start);
+        let at = self.layout.iter().position(|&id| id == start).unwrap();
+        let first = self.add_block(self.layout[at.saturating_sub(1)]);
+        let mut current = first;
+        self.push_inst(
+            current,
+            Inst::new(Op::Effect(EffectOps::Speculate), None, vec![]),
+        );
+
+        for (i, &block) in trace.iter().enumerate() {
+            let insts = self.block(block).insts.clone();
+            let (&terminator, body) = insts.split_last().unwrap();
+            for &id in body {
+                let inst = self.insts[id].clone();
+                self.push_inst(current, inst);
+            }
+            let terminator = self.insts[terminator].clone();
+
+            let Some(&next) = trace.get(i + 1) else {
+                self.push_inst(
+                    current,
+                    Inst::new(Op::Effect(EffectOps::Commit), None, vec![]),
+                );
+                for label in &terminator.labels {
+                    let target = self.block_id(label);
+                    self.graph.add_edge(current, target, CfgEdge::default());
+                }
+                self.push_inst(current, terminator);
+                break;
+            };
+            let (Op::Effect(EffectOps::Branch), [cond]) = (&terminator.op, &terminator.args[..])
+            else {
+                // A jump to the next block of the trace
+                continue;
+            };
+            if terminator.labels[0] == terminator.labels[1] {
+                continue;
+            }
+
+            // The guard holds when the trace goes on
+            let mut cond = *cond;
+            if self.block_id(&terminator.labels[0]) != next {
+                let negated = self.values.fresh("not_taken", &Type::Bool);
+                let not = Inst::new(Op::Value(ValueOps::Not), Some(negated), vec![cond]);
+                self.push_inst(current, not);
+                cond = negated;
+            }
+            let rest = self.add_block(current);
+            let guard = Inst {
+                labels: vec![start_label.clone(), self.label(rest).clone()],
+                ..Inst::new(Op::Effect(EffectOps::Guard), None, vec![cond])
+            };
+            self.push_inst(current, guard);
+            self.graph.add_edge(current, start, CfgEdge::default());
+            self.graph.add_edge(current, rest, CfgEdge::default());
+            current = rest;
+        }
+
+        for pred in preds {
+            self.redirect_edge(pred, start, first);
+        }
+        // Going around the loop again starts another speculation
+        if self.successors(current).contains(&start) {
+            self.redirect_edge(current, start, first);
+        }
+        for block in self.blocks.values_mut() {
+            block.update_definitions(&self.insts);
+        }
+    }
+}
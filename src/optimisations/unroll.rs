@@ -0,0 +1,740 @@
+use std::collections::{HashMap, HashSet};
+
+use bril_rs::{EffectOps, Literal, Type, ValueOps};
+
+use crate::analyses::loops::Loop;
+use crate::cfg::{dominates, BlockId, Cfg, CfgEdge, IDoms};
+use crate::inst::{Inst, Op};
+use crate::passes::AnalysisManager;
+use crate::value::ValueId;
+
+#[derive(Debug, Clone, Copy)]
+pub struct UnrollOptions {
+    // Copies of the body in the unrolled loop
+    pub factor: usize,
+    // Loops known to iterate at most this many times are replaced by straight-line code
+    pub max_full_unroll: u64,
+}
+
+impl Default for UnrollOptions {
+    fn default() -> Self {
+        Self {
+            factor: 4,
+            max_full_unroll: 8,
+        }
+    }
+}
+
+// Trip counts are found by running the loop's test, longer loops are treated as if the count was unknown
+const MAX_SIMULATED_TRIPS: u64 = 1 << 16;
+
+// A loop whose exit is decided by comparing an induction variable with a loop invariant bound:
+//
+//     header:   i = phi init preheader next latch
+//               ...
+//               next = add i step          (anywhere in the loop, step a constant)
+//               ...
+//     exiting:  c = lt v bound             (v either i or next, the comparison any of lt, le, gt, ge, eq)
+//               br c stay exit
+//
+// The exiting block runs on every iteration, and its branch is the only way out of the loop.
+// Iterations start at the header, so a while loop whose body runs n times iterates n + 1 times,
+// the last time only as far as the test in the header.
+struct CountedLoop {
+    header: BlockId,
+    preheader: BlockId,
+    latch: BlockId,
+    exiting: BlockId,
+    exit: BlockId,
+    // The target of the exiting branch inside the loop
+    stay: BlockId,
+    // Header φ-function of the induction variable
+    iv: ValueId,
+    init: ValueId,
+    step: i64,
+    // `v - i`, usually 0, or `step` when the test reads the next value
+    offset: i64,
+    cmp: ValueOps,
+    iv_on_left: bool,
+    bound: ValueId,
+    // The value of the condition that keeps the loop going
+    stay_when: bool,
+    // Number of iterations, when it is known at compile time
+    trip_count: Option<u64>,
+}
+
+impl CountedLoop {
+    // Whether checking that the test still passes `factor - 1` iterations ahead is enough to know it passes
+    // on every iteration in between: the loop has to keep going while `v` stays on one side of the bound,
+    // and `v` has to move towards the other side without wrapping around on the way. A constant bound has to
+    // leave room for `factor - 1` more steps past it, any other one is checked for that at run time.
+    fn can_check_ahead(&self, factor: usize, bound: Option<i64>) -> bool {
+        let Some(ahead) = self.ahead(factor) else {
+            return false;
+        };
+        if bound.is_some_and(|bound| bound.checked_add(ahead).is_none()) {
+            return false;
+        }
+
+        let mut below = match self.cmp {
+            ValueOps::Lt | ValueOps::Le => true,
+            ValueOps::Gt | ValueOps::Ge => false,
+            _ => return false,
+        };
+        if !self.iv_on_left {
+            below = !below;
+        }
+        if !self.stay_when {
+            below = !below;
+        }
+        if below {
+            self.step > 0
+        } else {
+            self.step < 0
+        }
+    }
+
+    // How far `v` moves in `factor - 1` iterations
+    fn ahead(&self, factor: usize) -> Option<i64> {
+        i64::try_from(factor - 1).ok()?.checked_mul(self.step)
+    }
+}
+
+struct HeaderPhi {
+    dest: ValueId,
+    // From the preheader
+    init: ValueId,
+    // From the latch
+    back: ValueId,
+}
+
+// The blocks and values a copy of a loop made of the original ones
+struct LoopCopy {
+    blocks: HashMap<BlockId, BlockId>,
+    values: HashMap<ValueId, ValueId>,
+    // Last of the new blocks in the layout
+    last: BlockId,
+}
+
+impl LoopCopy {
+    fn block(&self, block: BlockId) -> BlockId {
+        self.blocks[&block]
+    }
+
+    // Values defined outside the loop are the same in every copy
+    fn value(&self, value: ValueId) -> ValueId {
+        self.values.get(&value).copied().unwrap_or(value)
+    }
+
+    // Where the copy goes when the exit test passes. Staying in the loop from the latch means going back to the
+    // header, and back edges are left for the caller to point at the next copy.
+    fn stay(&self, counted: &CountedLoop) -> BlockId {
+        if counted.stay == counted.header {
+            counted.header
+        } else {
+            self.block(counted.stay)
+        }
+    }
+}
+
+impl Cfg {
+    // Unrolls innermost counted loops, in SSA form. Returns whether any loop was unrolled.
+    //
+    // A loop with a trip count known at compile time is replaced by straight-line code if the count is small,
+    // and otherwise by a loop running `factor` iterations at a time if the count is a multiple of `factor`.
+    // Any other loop gets an unrolled copy that runs while there are at least `factor` iterations left,
+    // followed by the original loop for the remaining ones.
+    pub fn unroll_loops(&mut self, options: &UnrollOptions, am: &mut AnalysisManager) -> bool {
+        if !self.in_ssa {
+            return false;
+        }
+        if self.remove_unreachable() {
+            am.invalidate(&[]);
+        }
+
+        // Headers of the loops that were looked at already, or that unrolling made
+        let mut done: HashSet<BlockId> = HashSet::new();
+        let mut changed = false;
+        'search: loop {
+            let idoms = am.idoms(self).clone();
+            let loops = &am.loops(self).loops;
+            for (i, l) in loops.iter().enumerate() {
+                // Outer loops may become innermost ones once the loops in them are fully unrolled
+                if loops.iter().any(|other| other.parent == Some(i)) || !done.insert(l.header) {
+                    continue;
+                }
+                // Unrolling a loop a profile says never ran only makes the code bigger
+                if self.block_count(l.header) == Some(0) {
+                    continue;
+                }
+                let Some((counted, phis)) = self.counted_loop(l, &idoms) else {
+                    continue;
+                };
+
+                let factor = options.factor as u64;
+                let new_header = match counted.trip_count {
+                    Some(trips) if trips <= options.max_full_unroll => {
+                        self.unroll_fully(l, &counted, &phis, trips);
+                        None
+                    }
+                    Some(trips) if factor > 1 && trips % factor == 0 => {
+                        Some(self.unroll_exactly(l, &counted, &phis, options.factor))
+                    }
+                    // Neither is an unrolled copy that hardly ever has `factor` iterations left to run
+                    _ if factor > 1
+                        && counted.can_check_ahead(options.factor, self.const_int(counted.bound))
+                        && self.average_trips(l).is_none_or(|trips| trips > factor) =>
+                    {
+                        Some(self.unroll_with_remainder(l, &counted, &phis, options.factor))
+                    }
+                    _ => continue,
+                };
+                done.extend(new_header);
+                changed = true;
+
+                for block in self.blocks.values_mut() {
+                    block.update_definitions(&self.insts);
+                }
+                // Blocks and loops changed, everything has to be computed again
+                am.invalidate(&[]);
+                continue 'search;
+            }
+            break;
+        }
+        changed
+    }
+
+    fn counted_loop(&self, l: &Loop, idoms: &IDoms) -> Option<(CountedLoop, Vec<HeaderPhi>)> {
+        let [latch] = l.latches[..] else {
+            return None;
+        };
+        let preds = self.predecessors(l.header);
+        let outside: Vec<BlockId> = preds.iter().copied().filter(|&p| !l.contains(p)).collect();
+        let [preheader] = outside[..] else {
+            return None;
+        };
+        if preds.len() != 2 {
+            return None;
+        }
+        let [(exiting, exit)] = l.exits[..] else {
+            return None;
+        };
+        if !dominates(idoms, exiting, latch) {
+            return None;
+        }
+
+        let preheader_label = self.label(preheader);
+        let latch_label = self.label(latch);
+        let phis: Vec<HeaderPhi> = self
+            .block(l.header)
+            .phis(&self.insts)
+            .map(|id| {
+                let inst = &self.insts[id];
+                let arg_from = |pred| {
+                    let i = inst.labels.iter().position(|label| label == pred)?;
+                    Some(inst.args[i])
+                };
+                Some(HeaderPhi {
+                    dest: inst.dest?,
+                    init: arg_from(preheader_label)?,
+                    back: arg_from(latch_label)?,
+                })
+            })
+            .collect::<Option<_>>()?;
+
+        let branch = &self.insts[self.block(exiting).terminator(&self.insts)?];
+        let (Op::Effect(EffectOps::Branch), [cond]) = (&branch.op, &branch.args[..]) else {
+            return None;
+        };
+        let exit_label = self.label(exit);
+        let (stay, stay_when) = match &branch.labels[..] {
+            [t, f] if f == exit_label && t != exit_label => (self.block_id(t), true),
+            [t, f] if t == exit_label && f != exit_label => (self.block_id(f), false),
+            _ => return None,
+        };
+
+        let test = &self.insts[self.insts.def(*cond)?];
+        let (Op::Value(cmp), [a, b]) = (&test.op, &test.args[..]) else {
+            return None;
+        };
+        if !matches!(
+            cmp,
+            ValueOps::Lt | ValueOps::Le | ValueOps::Gt | ValueOps::Ge | ValueOps::Eq
+        ) {
+            return None;
+        }
+
+        // `v` as a header φ-function plus a constant
+        let linear = |v: ValueId| -> Option<(ValueId, i64)> {
+            if phis.iter().any(|phi| phi.dest == v) {
+                return Some((v, 0));
+            }
+            let def = &self.insts[self.insts.def(v)?];
+            let is_phi = |x: &ValueId| phis.iter().any(|phi| phi.dest == *x);
+            match (&def.op, &def.args[..]) {
+                (Op::Value(ValueOps::Add), [x, c]) | (Op::Value(ValueOps::Add), [c, x])
+                    if is_phi(x) =>
+                {
+                    Some((*x, self.const_int(*c)?))
+                }
+                (Op::Value(ValueOps::Sub), [x, c]) if is_phi(x) => {
+                    Some((*x, self.const_int(*c)?.checked_neg()?))
+                }
+                _ => None,
+            }
+        };
+        let invariant = |v: ValueId| {
+            self.insts
+                .def(v)
+                .is_none_or(|def| !l.contains(self.insts.block_of(def)))
+        };
+        let (iv_on_left, (iv, offset), bound) = if let Some(linear) = linear(*a)
+            && invariant(*b)
+        {
+            (true, linear, *b)
+        } else if let Some(linear) = linear(*b)
+            && invariant(*a)
+        {
+            (false, linear, *a)
+        } else {
+            return None;
+        };
+
+        let phi = phis.iter().find(|phi| phi.dest == iv)?;
+        let (from, step) = linear(phi.back)?;
+        if from != iv || step == 0 {
+            return None;
+        }
+
+        let mut counted = CountedLoop {
+            header: l.header,
+            preheader,
+            latch,
+            exiting,
+            exit,
+            stay,
+            iv,
+            init: phi.init,
+            step,
+            offset,
+            cmp: *cmp,
+            iv_on_left,
+            bound,
+            stay_when,
+            trip_count: None,
+        };
+        counted.trip_count = self.trip_count(&counted);
+        Some((counted, phis))
+    }
+
+    // The integer a value is defined as by a constant instruction
+    pub fn const_int(&self, value: ValueId) -> Option<i64> {
+        match self.insts[self.insts.def(value)?].op {
+            Op::Const(Literal::Int(n)) => Some(n),
+            _ => None,
+        }
+    }
+
+    // Runs the test with the values the induction variable takes, until it fails
+    fn trip_count(&self, counted: &CountedLoop) -> Option<u64> {
+        let mut i = self.const_int(counted.init)?;
+        let bound = self.const_int(counted.bound)?;
+        for trips in 1..=MAX_SIMULATED_TRIPS {
+            let v = i.wrapping_add(counted.offset);
+            let (a, b) = if counted.iv_on_left {
+                (v, bound)
+            } else {
+                (bound, v)
+            };
+            let result = match counted.cmp {
+                ValueOps::Lt => a < b,
+                ValueOps::Le => a <= b,
+                ValueOps::Gt => a > b,
+                ValueOps::Ge => a >= b,
+                ValueOps::Eq => a == b,
+                _ => return None,
+            };
+            if result != counted.stay_when {
+                return Some(trips);
+            }
+            i = i.wrapping_add(counted.step);
+        }
+        None
+    }
+
+    // One copy per iteration, each going straight into the next. The last one takes the exit.
+    fn unroll_fully(&mut self, l: &Loop, counted: &CountedLoop, phis: &[HeaderPhi], trips: u64) {
+        let mut copies: Vec<LoopCopy> = Vec::new();
+        for i in 0..trips {
+            let incoming = phis
+                .iter()
+                .map(|phi| {
+                    let value = copies.last().map_or(phi.init, |prev| prev.value(phi.back));
+                    (phi.dest, value)
+                })
+                .collect();
+            let after = copies.last().map_or(counted.preheader, |prev| prev.last);
+            let copy = self.copy_loop(l, &incoming, after);
+            let keep = if i + 1 == trips {
+                counted.exit
+            } else {
+                copy.stay(counted)
+            };
+            self.fold_branch(copy.block(counted.exiting), keep);
+            copies.push(copy);
+        }
+
+        for pair in copies.windows(2) {
+            self.redirect_edge(
+                pair[0].block(counted.latch),
+                counted.header,
+                pair[1].block(counted.header),
+            );
+        }
+        self.redirect_edge(
+            counted.preheader,
+            counted.header,
+            copies[0].block(counted.header),
+        );
+        self.replace_loop(l, copies.last().unwrap());
+    }
+
+    // `factor` copies in a loop, the last one with the exit test. The trip count is a multiple of `factor`,
+    // so the others never take the exit.
+    fn unroll_exactly(
+        &mut self,
+        l: &Loop,
+        counted: &CountedLoop,
+        phis: &[HeaderPhi],
+        factor: usize,
+    ) -> BlockId {
+        let heads: Vec<ValueId> = phis
+            .iter()
+            .map(|phi| self.values.new_version(phi.dest))
+            .collect();
+        let copies = self.copy_iterations(
+            l,
+            counted,
+            phis,
+            &heads,
+            counted.preheader,
+            factor,
+            factor - 1,
+        );
+        let last = &copies[factor - 1];
+
+        let header = copies[0].block(counted.header);
+        self.redirect_edge(last.block(counted.latch), counted.header, header);
+        self.redirect_edge(counted.preheader, counted.header, header);
+        let preheader_label = self.label(counted.preheader).clone();
+        let latch_label = self.label(last.block(counted.latch)).clone();
+        for (i, (phi, &head)) in phis.iter().zip(&heads).enumerate() {
+            let inst = Inst {
+                labels: vec![preheader_label.clone(), latch_label.clone()],
+                ..Inst::new(
+                    Op::Value(ValueOps::Phi),
+                    Some(head),
+                    vec![phi.init, last.value(phi.back)],
+                )
+            };
+            self.insert_inst(header, i, inst);
+        }
+
+        self.replace_loop(l, last);
+        header
+    }
+
+    // A loop running `factor` iterations at a time, entered through a guard that checks that the next `factor`
+    // iterations all stay in the loop. When that's no longer the case, the original loop runs the rest.
+    fn unroll_with_remainder(
+        &mut self,
+        l: &Loop,
+        counted: &CountedLoop,
+        phis: &[HeaderPhi],
+        factor: usize,
+    ) -> BlockId {
+        let guard = self.add_block(counted.preheader);
+        let heads: Vec<ValueId> = phis
+            .iter()
+            .map(|phi| self.values.new_version(phi.dest))
+            .collect();
+        let copies = self.copy_iterations(l, counted, phis, &heads, guard, factor, factor);
+        let last = &copies[factor - 1];
+        self.redirect_edge(last.block(counted.latch), counted.header, guard);
+
+        let preheader_label = self.label(counted.preheader).clone();
+        let latch_label = self.label(last.block(counted.latch)).clone();
+        for (phi, &head) in phis.iter().zip(&heads) {
+            let inst = Inst {
+                labels: vec![preheader_label.clone(), latch_label.clone()],
+                ..Inst::new(
+                    Op::Value(ValueOps::Phi),
+                    Some(head),
+                    vec![phi.init, last.value(phi.back)],
+                )
+            };
+            self.push_inst(guard, inst);
+        }
+
+        // v, `factor - 1` iterations from now
+        let iv = heads[phis.iter().position(|phi| phi.dest == counted.iv).unwrap()];
+        let ahead = counted.ahead(factor).unwrap();
+        let distance = self.values.fresh("ahead", &Type::Int);
+        let v = self.values.fresh("v", &Type::Int);
+        let mut cond = self.values.fresh("enough", &Type::Bool);
+        let args = if counted.iv_on_left {
+            vec![v, counted.bound]
+        } else {
+            vec![counted.bound, v]
+        };
+        self.push_inst(
+            guard,
+            Inst::new(
+                Op::Const(Literal::Int(counted.offset.wrapping_add(ahead))),
+                Some(distance),
+                vec![],
+            ),
+        );
+        self.push_inst(
+            guard,
+            Inst::new(Op::Value(ValueOps::Add), Some(v), vec![iv, distance]),
+        );
+        self.push_inst(guard, Inst::new(Op::Value(counted.cmp), Some(cond), args));
+
+        // A bound too close to the end of the range would let `v` wrap around past it, and look like it
+        // stays in the loop
+        if self.const_int(counted.bound).is_none() {
+            let (limit, cmp) = if ahead > 0 {
+                (i64::MAX - ahead, ValueOps::Le)
+            } else {
+                (i64::MIN - ahead, ValueOps::Ge)
+            };
+            // The unrolled loop is entered when the test has the value that keeps the loop going
+            let (cmp, join) = match (counted.stay_when, cmp) {
+                (true, cmp) => (cmp, ValueOps::And),
+                (false, ValueOps::Le) => (ValueOps::Gt, ValueOps::Or),
+                (false, _) => (ValueOps::Lt, ValueOps::Or),
+            };
+            let limit_value = self.values.fresh("limit", &Type::Int);
+            let room = self.values.fresh("room", &Type::Bool);
+            let both = self.values.fresh("enough", &Type::Bool);
+            self.push_inst(
+                guard,
+                Inst::new(Op::Const(Literal::Int(limit)), Some(limit_value), vec![]),
+            );
+            self.push_inst(
+                guard,
+                Inst::new(Op::Value(cmp), Some(room), vec![counted.bound, limit_value]),
+            );
+            self.push_inst(
+                guard,
+                Inst::new(Op::Value(join), Some(both), vec![cond, room]),
+            );
+            cond = both;
+        }
+
+        let unrolled = copies[0].block(counted.header);
+        let mut targets = vec![unrolled, counted.header];
+        if !counted.stay_when {
+            targets.reverse();
+        }
+        let branch = Inst {
+            labels: targets.iter().map(|&t| self.label(t).clone()).collect(),
+            ..Inst::new(Op::Effect(EffectOps::Branch), None, vec![cond])
+        };
+        self.push_inst(guard, branch);
+        for target in targets {
+            self.graph.add_edge(guard, target, CfgEdge::default());
+        }
+
+        // The original loop is now entered from the guard, with the values the unrolled loop left off at
+        self.redirect_edge(counted.preheader, counted.header, guard);
+        let guard_label = self.label(guard).clone();
+        let header_phis: Vec<_> = self.block(counted.header).phis(&self.insts).collect();
+        for (id, &head) in header_phis.into_iter().zip(&heads) {
+            self.insts.update(id, |inst| {
+                let i = inst
+                    .labels
+                    .iter()
+                    .position(|label| *label == preheader_label)
+                    .unwrap();
+                inst.args[i] = head;
+                inst.labels[i] = guard_label.clone();
+            });
+        }
+
+        guard
+    }
+
+    // `factor` copies of the loop, each going into the next one through its back edge, the last one still
+    // going back to the original header. The first copy starts with `heads` for the header φ-functions.
+    // The first `folded` copies lose their exit test, the caller knows it passes in them.
+    #[allow(clippy::too_many_arguments)]
+    fn copy_iterations(
+        &mut self,
+        l: &Loop,
+        counted: &CountedLoop,
+        phis: &[HeaderPhi],
+        heads: &[ValueId],
+        after: BlockId,
+        factor: usize,
+        folded: usize,
+    ) -> Vec<LoopCopy> {
+        let mut copies: Vec<LoopCopy> = Vec::with_capacity(factor);
+        for _ in 0..factor {
+            let incoming = phis
+                .iter()
+                .zip(heads)
+                .map(|(phi, &head)| {
+                    let value = copies.last().map_or(head, |prev| prev.value(phi.back));
+                    (phi.dest, value)
+                })
+                .collect();
+            let after = copies.last().map_or(after, |prev| prev.last);
+            copies.push(self.copy_loop(l, &incoming, after));
+        }
+        // Before chaining, when the latch is the exiting block its back edge is the branch being folded
+        for copy in &copies[..folded] {
+            self.fold_branch(copy.block(counted.exiting), copy.stay(counted));
+        }
+        for pair in copies.windows(2) {
+            self.redirect_edge(
+                pair[0].block(counted.latch),
+                counted.header,
+                pair[1].block(counted.header),
+            );
+        }
+        copies
+    }
+
+    // Copies every block of the loop, with fresh labels and fresh versions of the values defined in it.
+    // The header's φ-functions aren't copied: `incoming` says which values they stand for in the copy.
+    // Branches inside the loop go to the copies of their targets, except back edges, which still go to the
+    // original header. Exit edges still go where they went, with an argument for the copy in the φ-functions there.
+    fn copy_loop(
+        &mut self,
+        l: &Loop,
+        incoming: &HashMap<ValueId, ValueId>,
+        after: BlockId,
+    ) -> LoopCopy {
+        let body: Vec<BlockId> = self
+            .layout
+            .iter()
+            .copied()
+            .filter(|&block| l.contains(block))
+            .collect();
+        let mut copy = LoopCopy {
+            blocks: HashMap::new(),
+            values: incoming.clone(),
+            last: after,
+        };
+        for &block in &body {
+            copy.last = self.add_block(copy.last);
+            copy.blocks.insert(block, copy.last);
+        }
+
+        // Destinations first, a value can be used before its definition in layout order
+        let mut insts = Vec::new();
+        for &block in &body {
+            for id in self.block(block).insts.clone() {
+                if block == l.header && self.insts[id].is_phi() {
+                    continue;
+                }
+                let mut inst = self.insts[id].clone();
+                if let Some(dest) = inst.dest {
+                    let version = self.values.new_version(dest);
+                    copy.values.insert(dest, version);
+                    inst.dest = Some(version);
+                }
+                insts.push((block, inst));
+            }
+        }
+
+        // φ-functions name the block they come from, terminators the block they go to, and only going to
+        // the header is special
+        let labels: HashMap<_, _> = body
+            .iter()
+            .map(|&block| {
+                (
+                    self.label(block).clone(),
+                    self.label(copy.block(block)).clone(),
+                )
+            })
+            .collect();
+        let header_label = self.label(l.header).clone();
+        for (block, mut inst) in insts {
+            for arg in &mut inst.args {
+                *arg = copy.value(*arg);
+            }
+            let is_phi = inst.is_phi();
+            for label in &mut inst.labels {
+                if let Some(new) = labels.get(label)
+                    && (is_phi || *label != header_label)
+                {
+                    *label = new.clone();
+                }
+            }
+            self.push_inst(copy.block(block), inst);
+        }
+
+        for &block in &body {
+            let from = copy.block(block);
+            let mut exits = HashSet::new();
+            for succ in self.successors(block) {
+                if succ == l.header {
+                    self.graph.add_edge(from, succ, CfgEdge::default());
+                } else if l.contains(succ) {
+                    self.graph
+                        .add_edge(from, copy.block(succ), CfgEdge::default());
+                } else {
+                    self.graph.add_edge(from, succ, CfgEdge::default());
+                    if exits.insert(succ) {
+                        self.add_exit_phi_args(succ, block, &copy);
+                    }
+                }
+            }
+        }
+        copy
+    }
+
+    // The φ-functions in `exit` take from the copy of `exiting` what they took from `exiting`
+    fn add_exit_phi_args(&mut self, exit: BlockId, exiting: BlockId, copy: &LoopCopy) {
+        let exiting_label = self.label(exiting).clone();
+        let copy_label = self.label(copy.block(exiting)).clone();
+        let phis: Vec<_> = self.block(exit).phis(&self.insts).collect();
+        for phi in phis {
+            self.insts.update(phi, |inst| {
+                let from_exiting: Vec<ValueId> = inst
+                    .args
+                    .iter()
+                    .zip(&inst.labels)
+                    .filter(|(_, label)| **label == exiting_label)
+                    .map(|(&arg, _)| copy.value(arg))
+                    .collect();
+                for arg in from_exiting {
+                    inst.args.push(arg);
+                    inst.labels.push(copy_label.clone());
+                }
+            });
+        }
+    }
+
+    // Once nothing enters the original loop anymore: uses after it take their values from `copy`,
+    // and the loop goes away
+    fn replace_loop(&mut self, l: &Loop, copy: &LoopCopy) {
+        let outside: Vec<_> = self
+            .insts
+            .iter()
+            .filter(|(_, data)| !l.contains(data.block))
+            .map(|(id, _)| id)
+            .collect();
+        for id in outside {
+            self.insts.update(id, |inst| {
+                for arg in &mut inst.args {
+                    *arg = copy.value(*arg);
+                }
+            });
+        }
+        self.remove_unreachable();
+    }
+}
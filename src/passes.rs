@@ -0,0 +1,524 @@
+use crate::analyses::{dj_graph::DjGraph, liveness::Liveness, loops::Loops};
+use crate::cfg::{Cfg, DominanceFrontiers, IDoms, SsaGraph};
+use crate::instrument::ProfileMode;
+use crate::optimisations::unroll::UnrollOptions;
+use crate::verify::Diagnostic;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Analysis {
+    Dominators,
+    DominanceFrontiers,
+    DjGraph,
+    Liveness,
+    Loops,
+    SsaGraph,
+}
+
+impl Analysis {
+    // Analyses that are computed from this one and go stale with it
+    fn depends_on(self) -> &'static [Analysis] {
+        match self {
+            Analysis::DominanceFrontiers | Analysis::DjGraph | Analysis::Loops => {
+                &[Analysis::Dominators]
+            }
+            _ => &[],
+        }
+    }
+}
+
+// Lazily computed analyses of a single Cfg.
+// Everything handed out stays valid until the next call to `invalidate`.
+#[derive(Debug, Default)]
+pub struct AnalysisManager {
+    idoms: Option<IDoms>,
+    frontiers: Option<DominanceFrontiers>,
+    dj_graph: Option<DjGraph>,
+    liveness: Option<Liveness>,
+    loops: Option<Loops>,
+    ssa_graph: Option<SsaGraph>,
+}
+
+impl AnalysisManager {
+    pub fn idoms(&mut self, cfg: &Cfg) -> &IDoms {
+        self.idoms.get_or_insert_with(|| cfg.get_idoms())
+    }
+
+    pub fn frontiers(&mut self, cfg: &Cfg) -> &DominanceFrontiers {
+        if self.frontiers.is_none() {
+            let frontiers = cfg.get_dominance_frontiers(self.idoms(cfg));
+            self.frontiers = Some(frontiers);
+        }
+        self.frontiers.as_ref().unwrap()
+    }
+
+    pub fn dj_graph(&mut self, cfg: &Cfg) -> &DjGraph {
+        if self.dj_graph.is_none() {
+            let dj_graph = cfg.dj_graph(self.idoms(cfg));
+            self.dj_graph = Some(dj_graph);
+        }
+        self.dj_graph.as_ref().unwrap()
+    }
+
+    pub fn liveness(&mut self, cfg: &Cfg) -> &Liveness {
+        self.liveness.get_or_insert_with(|| cfg.liveness())
+    }
+
+    pub fn loops(&mut self, cfg: &Cfg) -> &Loops {
+        if self.loops.is_none() {
+            let loops = cfg.find_loops(self.idoms(cfg));
+            self.loops = Some(loops);
+        }
+        self.loops.as_ref().unwrap()
+    }
+
+    pub fn ssa_graph(&mut self, cfg: &Cfg) -> &SsaGraph {
+        self.ssa_graph.get_or_insert_with(|| cfg.ssa_graph())
+    }
+
+    // Drops every cached result that is not in `preserved`, or that was computed from one that isn't
+    pub fn invalidate(&mut self, preserved: &[Analysis]) {
+        let keep = |analysis: Analysis| {
+            preserved.contains(&analysis)
+                && analysis
+                    .depends_on()
+                    .iter()
+                    .all(|dep| preserved.contains(dep))
+        };
+
+        if !keep(Analysis::Dominators) {
+            self.idoms = None;
+        }
+        if !keep(Analysis::DominanceFrontiers) {
+            self.frontiers = None;
+        }
+        if !keep(Analysis::DjGraph) {
+            self.dj_graph = None;
+        }
+        if !keep(Analysis::Liveness) {
+            self.liveness = None;
+        }
+        if !keep(Analysis::Loops) {
+            self.loops = None;
+        }
+        if !keep(Analysis::SsaGraph) {
+            self.ssa_graph = None;
+        }
+    }
+}
+
+pub trait Pass: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn run(&self, cfg: &mut Cfg, am: &mut AnalysisManager);
+
+    // Analyses that are still valid after the pass ran, everything else gets invalidated
+    fn preserves(&self) -> &'static [Analysis] {
+        &[]
+    }
+
+    // Checks the form the pass promises to leave the function in, on top of what every pass is checked for
+    fn verify(&self, _cfg: &Cfg, _am: &mut AnalysisManager) -> Result<(), Vec<Diagnostic>> {
+        Ok(())
+    }
+}
+
+// The analyses that only depend on the shape of the graph
+const CONTROL_FLOW: &[Analysis] = &[
+    Analysis::Dominators,
+    Analysis::DominanceFrontiers,
+    Analysis::DjGraph,
+    Analysis::Loops,
+];
+
+const ALL: &[Analysis] = &[
+    Analysis::Dominators,
+    Analysis::DominanceFrontiers,
+    Analysis::DjGraph,
+    Analysis::Liveness,
+    Analysis::Loops,
+    Analysis::SsaGraph,
+];
+
+struct RemoveUnreachable;
+
+impl Pass for RemoveUnreachable {
+    fn name(&self) -> &'static str {
+        "remove-unreachable"
+    }
+
+    fn run(&self, cfg: &mut Cfg, _am: &mut AnalysisManager) {
+        cfg.remove_unreachable();
+    }
+}
+
+struct ToSsa;
+
+impl Pass for ToSsa {
+    fn name(&self) -> &'static str {
+        "to-ssa"
+    }
+
+    fn run(&self, cfg: &mut Cfg, am: &mut AnalysisManager) {
+        // Unreachable blocks have no place in the dominator tree
+        if cfg.remove_unreachable() {
+            am.invalidate(&[]);
+        }
+        cfg.insert_phi_nodes_dj(am.dj_graph(cfg));
+        cfg.rename_variables(am.idoms(cfg));
+    }
+
+    fn preserves(&self) -> &'static [Analysis] {
+        CONTROL_FLOW
+    }
+}
+
+// Same result as to-ssa, minus the φ-functions that only ever see one value
+struct ToSsaBraun;
+
+impl Pass for ToSsaBraun {
+    fn name(&self) -> &'static str {
+        "to-ssa-braun"
+    }
+
+    fn run(&self, cfg: &mut Cfg, am: &mut AnalysisManager) {
+        // Same as to-ssa: the construction would leave unreachable blocks reading values it never
+        // defines, and the analyses it keeps have to hold for the graph without them
+        if cfg.remove_unreachable() {
+            am.invalidate(&[]);
+        }
+        cfg.construct_ssa_braun();
+    }
+
+    fn preserves(&self) -> &'static [Analysis] {
+        CONTROL_FLOW
+    }
+}
+
+struct FromSsa;
+
+impl Pass for FromSsa {
+    fn name(&self) -> &'static str {
+        "from-ssa"
+    }
+
+    fn run(&self, cfg: &mut Cfg, am: &mut AnalysisManager) {
+        // The edges out of guards get blocks of their own
+        if cfg.remove_phi_nodes() {
+            am.invalidate(&[]);
+        }
+    }
+
+    fn preserves(&self) -> &'static [Analysis] {
+        CONTROL_FLOW
+    }
+}
+
+struct Sccp;
+
+impl Pass for Sccp {
+    fn name(&self) -> &'static str {
+        "sccp"
+    }
+
+    fn run(&self, cfg: &mut Cfg, am: &mut AnalysisManager) {
+        cfg.sccp(am.ssa_graph(cfg));
+    }
+}
+
+struct Dce;
+
+impl Pass for Dce {
+    fn name(&self) -> &'static str {
+        "dce"
+    }
+
+    fn run(&self, cfg: &mut Cfg, _am: &mut AnalysisManager) {
+        cfg.dce();
+    }
+
+    fn preserves(&self) -> &'static [Analysis] {
+        CONTROL_FLOW
+    }
+}
+
+// Works on functions that are not in SSA form, and leaves those that are alone
+struct Pre;
+
+impl Pass for Pre {
+    fn name(&self) -> &'static str {
+        "pre"
+    }
+
+    fn run(&self, cfg: &mut Cfg, _am: &mut AnalysisManager) {
+        cfg.lazy_code_motion();
+    }
+}
+
+struct Unroll {
+    options: UnrollOptions,
+}
+
+impl Pass for Unroll {
+    fn name(&self) -> &'static str {
+        "unroll"
+    }
+
+    fn run(&self, cfg: &mut Cfg, am: &mut AnalysisManager) {
+        cfg.unroll_loops(&self.options, am);
+    }
+}
+
+struct Lcssa;
+
+impl Pass for Lcssa {
+    fn name(&self) -> &'static str {
+        "lcssa"
+    }
+
+    fn run(&self, cfg: &mut Cfg, am: &mut AnalysisManager) {
+        let idoms = am.idoms(cfg).clone();
+        cfg.form_lcssa(&idoms, am.loops(cfg));
+    }
+
+    fn preserves(&self) -> &'static [Analysis] {
+        CONTROL_FLOW
+    }
+
+    fn verify(&self, cfg: &Cfg, am: &mut AnalysisManager) -> Result<(), Vec<Diagnostic>> {
+        if cfg.in_ssa {
+            cfg.verify_lcssa(am.loops(cfg))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Promoted allocations get their φ-functions right away in SSA form, and from to-ssa otherwise
+struct Mem2Reg;
+
+impl Pass for Mem2Reg {
+    fn name(&self) -> &'static str {
+        "mem2reg"
+    }
+
+    fn run(&self, cfg: &mut Cfg, am: &mut AnalysisManager) {
+        cfg.mem2reg(am);
+    }
+
+    fn preserves(&self) -> &'static [Analysis] {
+        CONTROL_FLOW
+    }
+}
+
+// Counts blocks or edges, see instrument.rs for how to get the counts back
+struct Instrument {
+    mode: ProfileMode,
+}
+
+impl Pass for Instrument {
+    fn name(&self) -> &'static str {
+        "instrument"
+    }
+
+    fn run(&self, cfg: &mut Cfg, _am: &mut AnalysisManager) {
+        cfg.instrument(self.mode);
+    }
+}
+
+// Reorders the blocks of functions that have a profile, nothing else changes
+struct ProfileLayout;
+
+impl Pass for ProfileLayout {
+    fn name(&self) -> &'static str {
+        "profile-layout"
+    }
+
+    fn run(&self, cfg: &mut Cfg, _am: &mut AnalysisManager) {
+        cfg.layout_by_profile();
+    }
+
+    fn preserves(&self) -> &'static [Analysis] {
+        ALL
+    }
+}
+
+// Forms speculative regions on the hot paths of innermost loops, outside of SSA form only
+struct Speculate;
+
+impl Pass for Speculate {
+    fn name(&self) -> &'static str {
+        "speculate"
+    }
+
+    fn run(&self, cfg: &mut Cfg, am: &mut AnalysisManager) {
+        cfg.speculate_traces(am);
+    }
+}
+
+// Works on functions in SSA form, and leaves the others alone
+struct MemOpt;
+
+impl Pass for MemOpt {
+    fn name(&self) -> &'static str {
+        "mem-opt"
+    }
+
+    fn run(&self, cfg: &mut Cfg, am: &mut AnalysisManager) {
+        cfg.optimise_memory(am);
+    }
+
+    fn preserves(&self) -> &'static [Analysis] {
+        CONTROL_FLOW
+    }
+}
+
+// Preheaders, single latches, dedicated exits, and while loops rotated into guarded do-while loops
+struct CanonicalizeLoops;
+
+impl Pass for CanonicalizeLoops {
+    fn name(&self) -> &'static str {
+        "canonicalize-loops"
+    }
+
+    fn run(&self, cfg: &mut Cfg, am: &mut AnalysisManager) {
+        cfg.canonicalize_loops(am);
+    }
+
+    fn verify(&self, cfg: &Cfg, am: &mut AnalysisManager) -> Result<(), Vec<Diagnostic>> {
+        cfg.verify_loop_form(am.loops(cfg))
+    }
+}
+
+pub const PASS_NAMES: &[&str] = &[
+    "remove-unreachable",
+    "to-ssa",
+    "to-ssa-braun",
+    "from-ssa",
+    "sccp",
+    "dce",
+    "pre",
+    "unroll",
+    "lcssa",
+    "canonicalize-loops",
+    "mem-opt",
+    "mem2reg",
+    "speculate",
+    "profile-layout",
+    "instrument",
+];
+
+// Passes that take a parameter are written `name=value`, e.g. "unroll=8"
+pub fn pass_by_name(name: &str) -> Option<Box<dyn Pass>> {
+    if let Some(factor) = name.strip_prefix("unroll=") {
+        let options = UnrollOptions {
+            factor: factor.parse().ok()?,
+            ..Default::default()
+        };
+        return Some(Box::new(Unroll { options }));
+    }
+    if let Some(mode) = name.strip_prefix("instrument=") {
+        let mode = match mode {
+            "blocks" => ProfileMode::Blocks,
+            "edges" => ProfileMode::Edges,
+            _ => return None,
+        };
+        return Some(Box::new(Instrument { mode }));
+    }
+
+    let pass: Box<dyn Pass> = match name {
+        "remove-unreachable" => Box::new(RemoveUnreachable),
+        "to-ssa" => Box::new(ToSsa),
+        "to-ssa-braun" => Box::new(ToSsaBraun),
+        "from-ssa" => Box::new(FromSsa),
+        "sccp" => Box::new(Sccp),
+        "dce" => Box::new(Dce),
+        "pre" => Box::new(Pre),
+        "unroll" => Box::new(Unroll {
+            options: UnrollOptions::default(),
+        }),
+        "lcssa" => Box::new(Lcssa),
+        "canonicalize-loops" => Box::new(CanonicalizeLoops),
+        "mem-opt" => Box::new(MemOpt),
+        "mem2reg" => Box::new(Mem2Reg),
+        "speculate" => Box::new(Speculate),
+        "profile-layout" => Box::new(ProfileLayout),
+        "instrument" => Box::new(Instrument {
+            mode: ProfileMode::Edges,
+        }),
+        _ => return None,
+    };
+    Some(pass)
+}
+
+#[derive(Default)]
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+    // Verify the graph after every pass, SSA form after every pass that leaves the function in it,
+    // and whatever form the pass itself promises
+    pub verify_each: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifyError {
+    pub pass: &'static str,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl std::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "verification failed after {}:", self.pass)?;
+        for diagnostic in &self.diagnostics {
+            writeln!(f, "\t{diagnostic}")?;
+        }
+        Ok(())
+    }
+}
+
+impl PassManager {
+    // `pipeline` is a comma separated list of pass names, e.g. "to-ssa,sccp,dce,from-ssa"
+    pub fn parse(pipeline: &str) -> Result<Self, String> {
+        let mut pm = PassManager::default();
+        for name in pipeline.split(',').filter(|name| !name.is_empty()) {
+            let pass = pass_by_name(name).ok_or_else(|| format!("unknown pass {name}"))?;
+            pm.add(pass);
+        }
+        Ok(pm)
+    }
+
+    pub fn add(&mut self, pass: Box<dyn Pass>) {
+        self.passes.push(pass);
+    }
+
+    // Runs every pass in order, calling `after_pass` with the position and name of each pass once it's done.
+    // Stops at the first pass that fails verification.
+    pub fn run(
+        &self,
+        cfg: &mut Cfg,
+        mut after_pass: impl FnMut(usize, &str, &Cfg),
+    ) -> Result<(), VerifyError> {
+        let mut am = AnalysisManager::default();
+        for (i, pass) in self.passes.iter().enumerate() {
+            pass.run(cfg, &mut am);
+            am.invalidate(pass.preserves());
+            after_pass(i, pass.name(), cfg);
+
+            if self.verify_each {
+                let verified = cfg
+                    .verify()
+                    .and_then(|()| {
+                        if cfg.in_ssa {
+                            cfg.verify_ssa(am.idoms(cfg))
+                        } else {
+                            Ok(())
+                        }
+                    })
+                    .and_then(|()| pass.verify(cfg, &mut am));
+                verified.map_err(|diagnostics| VerifyError {
+                    pass: pass.name(),
+                    diagnostics,
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+
+use petgraph::{
+    stable_graph::EdgeIndex,
+    visit::EdgeRef,
+    Direction::{Incoming, Outgoing},
+};
+use serde_json::{json, Value};
+
+use crate::analyses::loops::Loop;
+use crate::cfg::{BlockId, Cfg, Label};
+
+// Execution counts, from an instrumented run or written by hand, as a JSON object with one entry per function:
+//
+//     { "main": { "calls": 1,
+//                 "blocks": { "loop": 11 },
+//                 "edges": [ { "from": "loop", "to": "body", "count": 10 } ] } }
+//
+// Every part is optional. The counts end up as the weights of the edges of `Cfg::graph`, and whatever counts
+// the profile leaves out are inferred from the others where they can be.
+
+#[derive(Debug, Clone, Default)]
+pub struct FunctionProfile {
+    pub calls: Option<u64>,
+    pub blocks: HashMap<Label, u64>,
+    pub edges: HashMap<(Label, Label), u64>,
+}
+
+pub type Profile = HashMap<String, FunctionProfile>;
+
+pub fn parse_profile(text: &str) -> Result<Profile, String> {
+    let json: Value = serde_json::from_str(text).map_err(|err| err.to_string())?;
+    let functions = json
+        .as_object()
+        .ok_or("a profile is an object with an entry per function")?;
+    functions
+        .iter()
+        .map(|(name, func)| {
+            let profile = parse_function_profile(func).map_err(|err| format!("@{name}: {err}"))?;
+            Ok((name.clone(), profile))
+        })
+        .collect()
+}
+
+// The inverse of parse_profile, with functions, blocks and edges sorted
+pub fn profile_to_json(profile: &Profile) -> String {
+    let mut names: Vec<&String> = profile.keys().collect();
+    names.sort();
+    let functions: serde_json::Map<String, Value> = names
+        .into_iter()
+        .map(|name| {
+            let func = &profile[name];
+            let mut edges: Vec<(&(Label, Label), &u64)> = func.edges.iter().collect();
+            edges.sort();
+            let edges: Vec<Value> = edges
+                .into_iter()
+                .map(|((from, to), count)| json!({ "from": from, "to": to, "count": count }))
+                .collect();
+            let mut entry = json!({ "edges": edges });
+            if let Some(calls) = func.calls {
+                entry["calls"] = json!(calls);
+            }
+            if !func.blocks.is_empty() {
+                entry["blocks"] = json!(func.blocks);
+            }
+            (name.clone(), entry)
+        })
+        .collect();
+    serde_json::to_string_pretty(&functions).unwrap()
+}
+
+fn parse_function_profile(json: &Value) -> Result<FunctionProfile, String> {
+    let count = |value: &Value| {
+        value
+            .as_u64()
+            .ok_or_else(|| format!("{value} is not a count"))
+    };
+    let mut profile = FunctionProfile::default();
+
+    if let Some(calls) = json.get("calls") {
+        profile.calls = Some(count(calls)?);
+    }
+    if let Some(blocks) = json.get("blocks") {
+        let blocks = blocks
+            .as_object()
+            .ok_or("\"blocks\" maps labels to counts")?;
+        for (label, n) in blocks {
+            profile.blocks.insert(label.clone(), count(n)?);
+        }
+    }
+    if let Some(edges) = json.get("edges") {
+        let edges = edges
+            .as_array()
+            .ok_or("\"edges\" is a list of {\"from\", \"to\", \"count\"}")?;
+        for edge in edges {
+            let field = |key: &str| {
+                edge.get(key)
+                    .ok_or_else(|| format!("edge {edge} has no \"{key}\""))
+            };
+            let label = |key: &str| {
+                field(key)?
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| format!("\"{key}\" of edge {edge} is not a label"))
+            };
+            profile
+                .edges
+                .insert((label("from")?, label("to")?), count(field("count")?)?);
+        }
+    }
+    Ok(profile)
+}
+
+impl Cfg {
+    // Replaces whatever counts the function had with those of the profile
+    pub fn apply_profile(&mut self, profile: &FunctionProfile) -> Result<(), String> {
+        self.clear_profile();
+        self.entry_count = profile.calls;
+
+        for ((from, to), &count) in &profile.edges {
+            let (Some(from), Some(to)) = (self.blocks.get(from), self.blocks.get(to)) else {
+                return Err(format!("no edge from .{from} to .{to}"));
+            };
+            if self.edges_between(from.node, to.node).is_empty() {
+                return Err(format!("no edge from .{} to .{}", from.label, to.label));
+            }
+            self.set_edge_count(from.node, to.node, Some(count));
+        }
+
+        let mut blocks = HashMap::new();
+        for (label, &count) in &profile.blocks {
+            let block = self
+                .blocks
+                .get(label)
+                .ok_or_else(|| format!("no block .{label}"))?;
+            blocks.insert(block.node, count);
+        }
+        self.infer_edge_counts(&blocks);
+        Ok(())
+    }
+
+    // A branch with the same label twice takes the first of the edges, as far as counts go, the others get 0
+    pub fn set_edge_count(&mut self, from: BlockId, to: BlockId, count: Option<u64>) {
+        for (i, edge) in self.edges_between(from, to).into_iter().enumerate() {
+            self.graph[edge].count = if i == 0 { count } else { Some(0) };
+        }
+    }
+
+    pub fn clear_profile(&mut self) {
+        self.entry_count = None;
+        for edge in self.graph.edge_weights_mut() {
+            edge.count = None;
+        }
+    }
+
+    pub fn has_profile(&self) -> bool {
+        self.entry_count.is_some() || self.graph.edge_weights().any(|edge| edge.count.is_some())
+    }
+
+    // Fills in the edge counts that follow from the known ones, and from the known block counts:
+    // a block runs as many times as its incoming edges are taken, and unless it returns,
+    // as many times as its outgoing edges are. The entry block runs once per call.
+    pub fn infer_edge_counts(&mut self, block_counts: &HashMap<BlockId, u64>) {
+        let mut counts = block_counts.clone();
+        if let Some(calls) = self.entry_count {
+            counts
+                .entry(self.blocks[&self.entry_label].node)
+                .or_insert(calls);
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &self.layout {
+                for direction in [Incoming, Outgoing] {
+                    let edges: Vec<(EdgeIndex, Option<u64>)> = self
+                        .graph
+                        .edges_directed(block, direction)
+                        .map(|edge| (edge.id(), edge.weight().count))
+                        .collect();
+                    if edges.is_empty() {
+                        continue;
+                    }
+                    let known: u64 = edges.iter().filter_map(|&(_, count)| count).sum();
+                    let unknown: Vec<EdgeIndex> = edges
+                        .iter()
+                        .filter(|(_, count)| count.is_none())
+                        .map(|&(edge, _)| edge)
+                        .collect();
+                    match (counts.get(&block), &unknown[..]) {
+                        (None, []) => {
+                            counts.insert(block, known);
+                            changed = true;
+                        }
+                        // An inconsistent profile doesn't get negative counts
+                        (Some(&total), [edge]) => {
+                            self.graph[*edge].count = Some(total.saturating_sub(known));
+                            changed = true;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    // Times the edges from `from` to `to` were taken, together
+    pub fn edge_count(&self, from: BlockId, to: BlockId) -> Option<u64> {
+        self.edges_between(from, to)
+            .into_iter()
+            .map(|edge| self.graph[edge].count)
+            .sum()
+    }
+
+    // Times the block ran, if the counts say
+    pub fn block_count(&self, block: BlockId) -> Option<u64> {
+        let sum = |direction| {
+            let counts: Vec<Option<u64>> = self
+                .graph
+                .edges_directed(block, direction)
+                .map(|edge| edge.weight().count)
+                .collect();
+            if counts.is_empty() {
+                None
+            } else {
+                counts.into_iter().sum()
+            }
+        };
+        if block == self.blocks[&self.entry_label].node {
+            self.entry_count.or_else(|| sum(Outgoing))
+        } else {
+            sum(Incoming)
+        }
+    }
+
+    // How many times the header of the loop runs per entry into the loop, rounded down.
+    // None without counts, or if the loop was never entered.
+    pub fn average_trips(&self, l: &Loop) -> Option<u64> {
+        let entries: u64 = self
+            .graph
+            .edges_directed(l.header, Incoming)
+            .filter(|edge| !l.contains(edge.source()))
+            .map(|edge| edge.weight().count)
+            .sum::<Option<u64>>()?;
+        if entries == 0 {
+            return None;
+        }
+        Some(self.block_count(l.header)? / entries)
+    }
+
+    fn edges_between(&self, from: BlockId, to: BlockId) -> Vec<EdgeIndex> {
+        self.graph
+            .edges_directed(from, Outgoing)
+            .filter(|edge| edge.target() == to)
+            .map(|edge| edge.id())
+            .collect()
+    }
+}
@@ -0,0 +1,110 @@
+use bril_rs::{
+    Argument, Code, ConstOps, EffectOps, Function, Instruction, Literal, Program, Type, ValueOps,
+};
+
+// Bril programs made in code, for the benchmarks and the fuzzer
+
+// A program made of `depth` do-while loops nested in each other. The innermost block dominates the
+// latch of every loop around it, so each loop header is in the dominance frontier of every block
+// nested in it, and the frontiers add up to quadratic size in `depth`.
+pub fn nested_loops(depth: usize) -> Program {
+    let depth = depth.max(1);
+    let counter = |k: usize| format!("i{k}");
+    let header = |k: usize| format!("h{k}");
+    let latch = |k: usize| format!("l{k}");
+
+    let mut instrs = vec![
+        constant("one", 1),
+        constant("x", 0),
+        constant(&counter(0), 0),
+    ];
+    for k in 0..depth {
+        instrs.push(Code::Label {
+            label: header(k),
+            pos: None,
+        });
+        if k + 1 < depth {
+            instrs.push(constant(&counter(k + 1), 0));
+        }
+    }
+    instrs.push(value(ValueOps::Add, "x", &["x", "one"], Type::Int));
+    for k in (0..depth).rev() {
+        if k + 1 < depth {
+            instrs.push(Code::Label {
+                label: latch(k),
+                pos: None,
+            });
+        }
+        let i = counter(k);
+        instrs.push(value(ValueOps::Add, &i, &[&i, "one"], Type::Int));
+        instrs.push(value(ValueOps::Lt, "c", &[&i, "n"], Type::Bool));
+        let exit = if k == 0 {
+            "done".to_string()
+        } else {
+            latch(k - 1)
+        };
+        instrs.push(effect(EffectOps::Branch, &["c"], vec![header(k), exit]));
+    }
+    instrs.push(Code::Label {
+        label: "done".to_string(),
+        pos: None,
+    });
+    instrs.push(effect(EffectOps::Print, &["x"], vec![]));
+
+    let main = Function {
+        args: vec![Argument {
+            name: "n".to_string(),
+            arg_type: Type::Int,
+        }],
+        instrs,
+        name: "main".to_string(),
+        pos: None,
+        return_type: None,
+    };
+    Program {
+        functions: vec![main],
+        imports: vec![],
+    }
+}
+
+pub fn constant(dest: &str, value: i64) -> Code {
+    Code::Instruction(Instruction::Constant {
+        dest: dest.to_string(),
+        op: ConstOps::Const,
+        pos: None,
+        const_type: Type::Int,
+        value: Literal::Int(value),
+    })
+}
+
+pub fn float_constant(dest: &str, value: f64) -> Code {
+    Code::Instruction(Instruction::Constant {
+        dest: dest.to_string(),
+        op: ConstOps::Const,
+        pos: None,
+        const_type: Type::Float,
+        value: Literal::Float(value),
+    })
+}
+
+pub fn value(op: ValueOps, dest: &str, args: &[&str], op_type: Type) -> Code {
+    Code::Instruction(Instruction::Value {
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+        dest: dest.to_string(),
+        funcs: vec![],
+        labels: vec![],
+        op,
+        pos: None,
+        op_type,
+    })
+}
+
+pub fn effect(op: EffectOps, args: &[&str], labels: Vec<String>) -> Code {
+    Code::Instruction(Instruction::Effect {
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+        funcs: vec![],
+        labels,
+        op,
+        pos: None,
+    })
+}
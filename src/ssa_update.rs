@@ -0,0 +1,66 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use bril_rs::ValueOps;
+
+use crate::analyses::dj_graph::DjGraph;
+use crate::cfg::{BlockId, Cfg, IDoms, Renaming};
+use crate::inst::{Inst, InstId, Op};
+use crate::value::ValueId;
+
+// Repairing SSA form after code got duplicated: a value that used to have a single definition now has one in
+// every copy, and each use has to get the copy that reaches it. Placement is the usual iterated dominance
+// frontier of the defining blocks, renaming the usual walk over the dominator tree, restricted to the values
+// concerned.
+impl Cfg {
+    // `defs` maps each value to the blocks that now define it, and the name it has at the end of each of them.
+    // The instructions of a block are left alone for the values it defines, they are expected to use the right
+    // names already.
+    pub fn update_ssa(
+        &mut self,
+        defs: &HashMap<ValueId, Vec<(BlockId, ValueId)>>,
+        idoms: &IDoms,
+        dj: &DjGraph,
+    ) {
+        if defs.is_empty() {
+            return;
+        }
+
+        let values: BTreeSet<ValueId> = defs.keys().copied().collect();
+        let mut at_end: HashMap<BlockId, Vec<(ValueId, ValueId)>> = HashMap::new();
+        // New φ-function -> the value it stands for
+        let mut placed: HashMap<InstId, ValueId> = HashMap::new();
+        for &value in &values {
+            let blocks: HashSet<BlockId> = defs[&value].iter().map(|&(block, _)| block).collect();
+            for &(block, name) in &defs[&value] {
+                at_end.entry(block).or_default().push((value, name));
+            }
+            let joins: BTreeSet<BlockId> = dj
+                .iterated_frontier(&blocks)
+                .into_iter()
+                .filter(|join| !blocks.contains(join))
+                .collect();
+            for block in joins {
+                let dest = self.values.new_version(value);
+                let at = self.block(block).phis(&self.insts).count();
+                let phi = Inst::new(Op::Value(ValueOps::Phi), Some(dest), vec![]);
+                placed.insert(self.insert_inst(block, at, phi), value);
+            }
+        }
+
+        let values: Vec<ValueId> = values.into_iter().collect();
+        let renaming = Renaming {
+            values: &values,
+            placed: &placed,
+            new_versions: false,
+            at_end: &at_end,
+        };
+        self.rename_along_dominator_tree(idoms, &renaming);
+
+        // Joins the values aren't live at get their φ-function removed again, like in lcssa
+        self.remove_unused_phis(placed.into_keys().collect());
+
+        for block in self.blocks.values_mut() {
+            block.update_definitions(&self.insts);
+        }
+    }
+}
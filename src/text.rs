@@ -0,0 +1,436 @@
+use std::fmt::Write;
+
+use bril_rs::{
+    Argument, Code, ConstOps, EffectOps, Function, Instruction, Literal, Program, Type, ValueOps,
+};
+
+// Bril in its text form, the one bril2json reads:
+//
+//     @main(n: int) {
+//       one: int = const 1;
+//     .loop:
+//       n: int = sub n one;
+//       more: bool = gt n one;
+//       br more .loop .done;
+//     .done:
+//       print n;
+//     }
+//
+// `#` starts a comment that runs to the end of the line. Imports are not supported, everything else
+// the interpreter runs is: types are int, bool, float, char and ptr<T>, chars are quoted like 'a'.
+
+pub fn parse_program(text: &str) -> Result<Program, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text),
+        next: 0,
+    };
+    let mut functions = Vec::new();
+    while parser.peek().is_some() {
+        functions.push(parser.function()?);
+    }
+    Ok(Program {
+        functions,
+        imports: vec![],
+    })
+}
+
+// Words, quoted chars and single punctuation characters, with the line they are on
+fn tokenize(text: &str) -> Vec<(String, usize)> {
+    let mut tokens = Vec::new();
+    for (line, text) in text.lines().enumerate() {
+        let text = text.split('#').next().unwrap();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            let mut token = c.to_string();
+            if c.is_whitespace() {
+                continue;
+            } else if c == '\'' {
+                for c in chars.by_ref() {
+                    token.push(c);
+                    if c == '\'' {
+                        break;
+                    }
+                }
+            } else if !is_punctuation(c) {
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || is_punctuation(c) {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+            }
+            tokens.push((token, line + 1));
+        }
+    }
+    tokens
+}
+
+fn is_punctuation(c: char) -> bool {
+    "{}():;,=<>".contains(c)
+}
+
+#[derive(Default)]
+struct Operands {
+    args: Vec<String>,
+    funcs: Vec<String>,
+    labels: Vec<String>,
+}
+
+struct Parser {
+    tokens: Vec<(String, usize)>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.next).map(|(token, _)| token.as_str())
+    }
+
+    fn token(&mut self) -> Result<String, String> {
+        let (token, _) = self
+            .tokens
+            .get(self.next)
+            .ok_or("unexpected end of program")?;
+        self.next += 1;
+        Ok(token.clone())
+    }
+
+    // Consumes the next token if it is `token`
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.peek() == Some(token);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.eat(token) {
+            return Ok(());
+        }
+        Err(self.error(&format!("expected `{token}`")))
+    }
+
+    // The line of the token before the next one
+    fn line(&self) -> usize {
+        self.tokens[self.next - 1].1
+    }
+
+    fn error(&self, message: &str) -> String {
+        match self.tokens.get(self.next) {
+            Some((token, line)) => format!("line {line}: {message}, found `{token}`"),
+            None => format!("{message} at the end of the program"),
+        }
+    }
+
+    fn function(&mut self) -> Result<Function, String> {
+        let name = self.token()?;
+        let Some(name) = name.strip_prefix('@') else {
+            return Err(format!(
+                "line {}: expected a function, found `{name}`",
+                self.line()
+            ));
+        };
+        let mut args = Vec::new();
+        if self.eat("(") && !self.eat(")") {
+            loop {
+                let name = self.token()?;
+                self.expect(":")?;
+                let arg_type = self.ty()?;
+                args.push(Argument { name, arg_type });
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        let return_type = if self.eat(":") {
+            Some(self.ty()?)
+        } else {
+            None
+        };
+
+        self.expect("{")?;
+        let mut instrs = Vec::new();
+        while !self.eat("}") {
+            instrs.push(self.code()?);
+        }
+        Ok(Function {
+            args,
+            instrs,
+            name: name.to_string(),
+            pos: None,
+            return_type,
+        })
+    }
+
+    fn ty(&mut self) -> Result<Type, String> {
+        let ty = match self.token()?.as_str() {
+            "int" => Type::Int,
+            "bool" => Type::Bool,
+            "float" => Type::Float,
+            "char" => Type::Char,
+            "ptr" => {
+                self.expect("<")?;
+                let pointee = self.ty()?;
+                self.expect(">")?;
+                Type::Pointer(Box::new(pointee))
+            }
+            ty => return Err(format!("line {}: unknown type {ty}", self.line())),
+        };
+        Ok(ty)
+    }
+
+    fn code(&mut self) -> Result<Code, String> {
+        let first = self.token()?;
+        if let Some(label) = first.strip_prefix('.') {
+            self.expect(":")?;
+            return Ok(Code::Label {
+                label: label.to_string(),
+                pos: None,
+            });
+        }
+
+        if !self.eat(":") {
+            let op = effect_op(&first)
+                .ok_or_else(|| format!("line {}: unknown operation {first}", self.line()))?;
+            let Operands {
+                args,
+                funcs,
+                labels,
+            } = self.operands()?;
+            return Ok(Code::Instruction(Instruction::Effect {
+                args,
+                funcs,
+                labels,
+                op,
+                pos: None,
+            }));
+        }
+
+        let ty = self.ty()?;
+        self.expect("=")?;
+        let op = self.token()?;
+        if op == "const" {
+            let value = self.token()?;
+            let value = literal(&value, &ty)
+                .ok_or_else(|| format!("line {}: bad {ty:?} constant {value}", self.line()))?;
+            self.expect(";")?;
+            return Ok(Code::Instruction(Instruction::Constant {
+                dest: first,
+                op: ConstOps::Const,
+                pos: None,
+                const_type: ty,
+                value,
+            }));
+        }
+        let op =
+            value_op(&op).ok_or_else(|| format!("line {}: unknown operation {op}", self.line()))?;
+        let Operands {
+            args,
+            funcs,
+            labels,
+        } = self.operands()?;
+        Ok(Code::Instruction(Instruction::Value {
+            args,
+            dest: first,
+            funcs,
+            labels,
+            op,
+            pos: None,
+            op_type: ty,
+        }))
+    }
+
+    // Variables, @functions and .labels up to the end of the instruction
+    fn operands(&mut self) -> Result<Operands, String> {
+        let mut operands = Operands::default();
+        while !self.eat(";") {
+            let operand = self.token()?;
+            if let Some(func) = operand.strip_prefix('@') {
+                operands.funcs.push(func.to_string());
+            } else if let Some(label) = operand.strip_prefix('.') {
+                operands.labels.push(label.to_string());
+            } else {
+                operands.args.push(operand);
+            }
+        }
+        Ok(operands)
+    }
+}
+
+fn literal(text: &str, ty: &Type) -> Option<Literal> {
+    let literal = match ty {
+        Type::Int => Literal::Int(text.parse().ok()?),
+        Type::Bool => Literal::Bool(text.parse().ok()?),
+        Type::Float => Literal::Float(text.parse().ok()?),
+        Type::Char => {
+            let mut chars = text.strip_prefix('\'')?.strip_suffix('\'')?.chars();
+            let c = chars.next()?;
+            chars.next().is_none().then_some(Literal::Char(c))?
+        }
+        Type::Pointer(_) => return None,
+    };
+    Some(literal)
+}
+
+const VALUE_OPS: &[(&str, ValueOps)] = &[
+    ("add", ValueOps::Add),
+    ("sub", ValueOps::Sub),
+    ("mul", ValueOps::Mul),
+    ("div", ValueOps::Div),
+    ("eq", ValueOps::Eq),
+    ("lt", ValueOps::Lt),
+    ("gt", ValueOps::Gt),
+    ("le", ValueOps::Le),
+    ("ge", ValueOps::Ge),
+    ("not", ValueOps::Not),
+    ("and", ValueOps::And),
+    ("or", ValueOps::Or),
+    ("call", ValueOps::Call),
+    ("id", ValueOps::Id),
+    ("fadd", ValueOps::Fadd),
+    ("fsub", ValueOps::Fsub),
+    ("fmul", ValueOps::Fmul),
+    ("fdiv", ValueOps::Fdiv),
+    ("feq", ValueOps::Feq),
+    ("flt", ValueOps::Flt),
+    ("fgt", ValueOps::Fgt),
+    ("fle", ValueOps::Fle),
+    ("fge", ValueOps::Fge),
+    ("ceq", ValueOps::Ceq),
+    ("clt", ValueOps::Clt),
+    ("cgt", ValueOps::Cgt),
+    ("cle", ValueOps::Cle),
+    ("cge", ValueOps::Cge),
+    ("char2int", ValueOps::Char2int),
+    ("int2char", ValueOps::Int2char),
+    ("alloc", ValueOps::Alloc),
+    ("load", ValueOps::Load),
+    ("ptradd", ValueOps::PtrAdd),
+    ("phi", ValueOps::Phi),
+];
+
+const EFFECT_OPS: &[(&str, EffectOps)] = &[
+    ("jmp", EffectOps::Jump),
+    ("br", EffectOps::Branch),
+    ("call", EffectOps::Call),
+    ("ret", EffectOps::Return),
+    ("print", EffectOps::Print),
+    ("nop", EffectOps::Nop),
+    ("store", EffectOps::Store),
+    ("free", EffectOps::Free),
+    ("speculate", EffectOps::Speculate),
+    ("commit", EffectOps::Commit),
+    ("guard", EffectOps::Guard),
+];
+
+fn value_op(op: &str) -> Option<ValueOps> {
+    VALUE_OPS
+        .iter()
+        .find(|(name, _)| *name == op)
+        .map(|&(_, op)| op)
+}
+
+fn effect_op(op: &str) -> Option<EffectOps> {
+    EFFECT_OPS
+        .iter()
+        .find(|(name, _)| *name == op)
+        .map(|&(_, op)| op)
+}
+
+fn op_name<T: PartialEq>(ops: &[(&'static str, T)], op: &T) -> &'static str {
+    ops.iter().find(|(_, other)| other == op).unwrap().0
+}
+
+// The text form of a program, which parse_program reads back
+pub fn program_to_text(program: &Program) -> String {
+    let mut text = String::new();
+    for func in &program.functions {
+        write!(text, "@{}", func.name).unwrap();
+        if !func.args.is_empty() {
+            let args: Vec<String> = func
+                .args
+                .iter()
+                .map(|arg| format!("{}: {}", arg.name, type_to_text(&arg.arg_type)))
+                .collect();
+            write!(text, "({})", args.join(", ")).unwrap();
+        }
+        if let Some(ty) = &func.return_type {
+            write!(text, ": {}", type_to_text(ty)).unwrap();
+        }
+        writeln!(text, " {{").unwrap();
+        for code in &func.instrs {
+            match code {
+                Code::Label { label, .. } => writeln!(text, ".{label}:").unwrap(),
+                Code::Instruction(inst) => {
+                    writeln!(text, "  {};", instruction_to_text(inst)).unwrap()
+                }
+            }
+        }
+        writeln!(text, "}}").unwrap();
+    }
+    text
+}
+
+fn instruction_to_text(inst: &Instruction) -> String {
+    match inst {
+        Instruction::Constant {
+            dest,
+            const_type,
+            value,
+            ..
+        } => {
+            let value = match value {
+                Literal::Int(n) => n.to_string(),
+                Literal::Bool(b) => b.to_string(),
+                Literal::Float(f) => f.to_string(),
+                Literal::Char(c) => format!("'{c}'"),
+            };
+            format!("{dest}: {} = const {value}", type_to_text(const_type))
+        }
+        Instruction::Value {
+            args,
+            dest,
+            funcs,
+            labels,
+            op,
+            op_type,
+            ..
+        } => format!(
+            "{dest}: {} = {}",
+            type_to_text(op_type),
+            operation_to_text(op_name(VALUE_OPS, op), args, funcs, labels)
+        ),
+        Instruction::Effect {
+            args,
+            funcs,
+            labels,
+            op,
+            ..
+        } => operation_to_text(op_name(EFFECT_OPS, op), args, funcs, labels),
+    }
+}
+
+fn operation_to_text(op: &str, args: &[String], funcs: &[String], labels: &[String]) -> String {
+    let operands = funcs
+        .iter()
+        .map(|func| format!("@{func}"))
+        .chain(args.iter().cloned())
+        .chain(labels.iter().map(|label| format!(".{label}")));
+    std::iter::once(op.to_string())
+        .chain(operands)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn type_to_text(ty: &Type) -> String {
+    match ty {
+        Type::Int => "int".to_string(),
+        Type::Bool => "bool".to_string(),
+        Type::Float => "float".to_string(),
+        Type::Char => "char".to_string(),
+        Type::Pointer(pointee) => format!("ptr<{}>", type_to_text(pointee)),
+    }
+}
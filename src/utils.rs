@@ -1,71 +1,47 @@
-use petgraph::dot::{Config, Dot};
-use petgraph::{Directed, Graph, Undirected};
-use std::{
-    io::Write,
-    process::{Command, Stdio},
-};
-
-static mut UNIQUE_VALUE: usize = 0;
+use std::fs::{self, File};
 
-fn get_unique_value() -> usize {
-    unsafe {
-        let cur_val = UNIQUE_VALUE;
-        UNIQUE_VALUE += 1;
-        cur_val
-    }
-}
-
-pub fn get_new_block() -> String {
-    format!("_block{}", get_unique_value())
-}
+use bril_rs::{load_program, load_program_from_read, Program};
+use petgraph::dot::{Config, Dot};
+use petgraph::visit::{EdgeRef, GraphProp, IntoEdgeReferences, IntoNodeReferences, NodeIndexable};
 
-pub fn graph_to_svg<N, E, Ty, Ix>(filename: &str, graph: &Graph<N, E, Ty, Ix>)
-where
-    Ix: std::fmt::Debug + petgraph::adj::IndexType,
-    E: std::fmt::Debug,
-    N: std::fmt::Debug,
-    Ty: petgraph::EdgeType,
-{
-    let contents = format!("{:?}", Dot::with_config(&graph, &[Config::EdgeNoLabel]));
-    let mut child = Command::new("dot")
-        .arg("-T")
-        .arg("svg")
-        .arg("-o")
-        .arg(format!("{filename}.svg"))
-        .stdin(Stdio::piped())
-        .spawn()
-        .unwrap();
+use crate::text::parse_program;
 
-    let child_stdin = child.stdin.as_mut().unwrap();
-    child_stdin.write_all(contents.as_bytes()).unwrap();
+pub fn get_new_block(id: usize) -> String {
+    format!("_block{id}")
 }
 
-pub fn assoc_list_to_directed_graph<N>(list: Vec<(N, N)>) -> Graph<N, (), Directed, u32>
+// Works for both Graph and StableGraph. Edges get a label only where `edge_label` gives one for their weight.
+pub fn graph_to_dot<G>(graph: G, edge_label: impl Fn(&G::EdgeWeight) -> Option<String>) -> String
 where
-    N: std::fmt::Debug,
+    G: IntoEdgeReferences + IntoNodeReferences + NodeIndexable + GraphProp,
+    G::EdgeWeight: std::fmt::Debug,
+    G::NodeWeight: std::fmt::Debug,
 {
-    let mut graph = Graph::default();
-    for (src, dst) in list {
-        let src = graph.add_node(src);
-        let dst = graph.add_node(dst);
-        graph.add_edge(src, dst, ());
-    }
-    graph
+    let edge_attributes = |_: G, edge: G::EdgeRef| {
+        edge_label(edge.weight()).map_or_else(String::new, |label| format!("label = {label:?}"))
+    };
+    let node_attributes = |_: G, _: G::NodeRef| String::new();
+    format!(
+        "{:?}",
+        Dot::with_attr_getters(
+            graph,
+            &[Config::EdgeNoLabel],
+            &edge_attributes,
+            &node_attributes
+        )
+    )
 }
 
-pub fn assoc_list_to_undirected_graph<N>(list: Vec<(N, N)>) -> Graph<N, (), Undirected, u32>
-where
-    N: std::fmt::Debug,
-{
-    let mut graph: Graph<N, (), Undirected> = Graph::default();
-    for (src, dst) in list {
-        let src = graph.add_node(src);
-        let dst = graph.add_node(dst);
-        graph.add_edge(src, dst, ());
+// The program in the file at `path`, or on stdin if there is no path or it is `-`.
+// Files ending in .bril hold the text form, anything else JSON.
+pub fn read_program(path: Option<&str>) -> Result<Program, String> {
+    match path {
+        None | Some("-") => Ok(load_program()),
+        Some(path) if path.ends_with(".bril") => fs::read_to_string(path)
+            .map_err(|err| format!("cannot read {path}: {err}"))
+            .and_then(|text| parse_program(&text).map_err(|err| format!("{path}: {err}"))),
+        Some(path) => File::open(path)
+            .map(load_program_from_read)
+            .map_err(|err| format!("cannot open {path}: {err}")),
     }
-    graph
-}
-
-pub fn extract_first_part(name: &str) -> &str {
-    name.split('.').next().unwrap()
 }
@@ -0,0 +1,153 @@
+use std::collections::{HashMap, HashSet};
+
+use bril_rs::Type;
+
+// A variable of the source function, or one of the SSA values renaming splits it into.
+// Names only exist for printing: two values never get confused because their names look alike.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ValueId(u32);
+
+impl ValueId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ValueData {
+    // Name of the source variable this value comes from
+    pub name: String,
+    // 0 for the source variable itself, n for the n-th version made of it
+    pub version: usize,
+    // The source variable (itself for version 0)
+    pub origin: ValueId,
+    // None for a variable that is used but never defined
+    pub ty: Option<Type>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Values {
+    values: Vec<ValueData>,
+    // Source variables by name
+    variables: HashMap<String, ValueId>,
+    // Number of versions made of each source variable so far
+    versions: HashMap<ValueId, usize>,
+}
+
+impl Values {
+    // The source variable called `name`, created the first time it shows up
+    pub fn variable(&mut self, name: &str) -> ValueId {
+        if let Some(&id) = self.variables.get(name) {
+            return id;
+        }
+        let id = ValueId(self.values.len() as u32);
+        self.values.push(ValueData {
+            name: name.to_string(),
+            version: 0,
+            origin: id,
+            ty: None,
+        });
+        self.variables.insert(name.to_string(), id);
+        id
+    }
+
+    // Like `variable`, for a definition of it. Bril variables keep the type of their first definition.
+    pub fn define(&mut self, name: &str, ty: &Type) -> ValueId {
+        let id = self.variable(name);
+        self.values[id.index()].ty.get_or_insert_with(|| ty.clone());
+        id
+    }
+
+    // A new source variable, for a temporary that transformations introduce.
+    // Named after `hint`, with a number if that is taken already.
+    pub fn fresh(&mut self, hint: &str, ty: &Type) -> ValueId {
+        let mut name = hint.to_string();
+        let mut n = 0;
+        while self.variables.contains_key(&name) {
+            n += 1;
+            name = format!("{hint}{n}");
+        }
+        self.define(&name, ty)
+    }
+
+    // A new value standing for `of`'s source variable
+    pub fn new_version(&mut self, of: ValueId) -> ValueId {
+        let origin = self.values[of.index()].origin;
+        let version = self.versions.entry(origin).or_insert(0);
+        *version += 1;
+
+        let id = ValueId(self.values.len() as u32);
+        let data = ValueData {
+            name: self.values[origin.index()].name.clone(),
+            version: *version,
+            origin,
+            ty: self.values[of.index()].ty.clone(),
+        };
+        self.values.push(data);
+        id
+    }
+
+    // Out of SSA form, a version is a variable like any other: the next construction makes versions of it,
+    // not of the variable it was a version of. It keeps the name it would have been printed with.
+    pub fn make_variable(&mut self, id: ValueId) {
+        let name = self.debug_name(id);
+        self.variables.entry(name.clone()).or_insert(id);
+        self.values[id.index()] = ValueData {
+            name,
+            version: 0,
+            origin: id,
+            ty: self.values[id.index()].ty.take(),
+        };
+    }
+
+    pub fn origin(&self, id: ValueId) -> ValueId {
+        self.values[id.index()].origin
+    }
+
+    pub fn ty(&self, id: ValueId) -> Option<&Type> {
+        self.values[id.index()].ty.as_ref()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ValueId, &ValueData)> {
+        self.values
+            .iter()
+            .enumerate()
+            .map(|(i, data)| (ValueId(i as u32), data))
+    }
+
+    // Readable, but not necessarily unique: `x.1` may be a version of `x` as well as a source variable
+    pub fn debug_name(&self, id: ValueId) -> String {
+        let data = &self.values[id.index()];
+        if data.version == 0 {
+            data.name.clone()
+        } else {
+            format!("{}.{}", data.name, data.version)
+        }
+    }
+
+    // Bril names for every value, indexed by `ValueId::index`.
+    // Source variables come first and keep their names, versions that would clash get a suffix.
+    pub fn names(&self) -> Vec<String> {
+        let mut taken = HashSet::with_capacity(self.values.len());
+        let mut names = vec![String::new(); self.values.len()];
+        let (variables, versions): (Vec<_>, Vec<_>) =
+            self.iter().partition(|(_, data)| data.version == 0);
+
+        for (id, _) in variables.into_iter().chain(versions) {
+            let mut name = self.debug_name(id);
+            while !taken.insert(name.clone()) {
+                name.push('_');
+            }
+            names[id.index()] = name;
+        }
+        names
+    }
+}
+
+impl std::ops::Index<ValueId> for Values {
+    type Output = ValueData;
+
+    fn index(&self, id: ValueId) -> &ValueData {
+        &self.values[id.index()]
+    }
+}
@@ -0,0 +1,692 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use bril_rs::{EffectOps, ValueOps};
+use petgraph::{graph::NodeIndex, visit::EdgeRef, Direction};
+
+use crate::analyses::loops::Loops;
+use crate::cfg::{dominates, Cfg, IDoms, Label};
+use crate::inst::{Inst, InstId, Op};
+use crate::value::ValueId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiagnosticKind {
+    MissingEntry,
+    BlockGraphMismatch,
+    InstParent,
+    Layout,
+    MissingEdge,
+    ExtraEdge,
+    MultipleDefinitions,
+    UndefinedUse,
+    UseNotDominated,
+    PhiArguments,
+    PhiNotAtStart,
+    Terminator,
+    NotEquivalent,
+    NotLoopClosed,
+    NotCanonicalLoop,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub block: Label,
+    // Index of the offending instruction within the block, if there is one
+    pub inst: Option<usize>,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.inst {
+            Some(inst) => write!(
+                f,
+                "{}[{}]: {:?}: {}",
+                self.block, inst, self.kind, self.message
+            ),
+            None => write!(f, "{}: {:?}: {}", self.block, self.kind, self.message),
+        }
+    }
+}
+
+// Where a name gets its value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Def {
+    Argument,
+    Inst(NodeIndex, usize),
+}
+
+// A definition that can reach a use once φ-functions are looked through.
+// Instructions are numbered by their position among the block's non-φ instructions,
+// which SSA construction leaves alone.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Source {
+    // The value a variable has on entry: an argument, or undefined
+    Entry(String),
+    Inst(Label, usize),
+}
+
+impl Cfg {
+    // Checks that `blocks` and `graph` describe the same function:
+    // - the entry block exists
+    // - every block sits at a graph node carrying its label, and every graph node has a block
+    // - the layout lists every block exactly once, starting with the entry block
+    // - the edges out of a block are exactly the labels its branches and jumps name
+    // - every instruction in the arena is listed by exactly one block, the one it points back to
+    pub fn verify(&self) -> Result<(), Vec<Diagnostic>> {
+        let mut diagnostics = Vec::new();
+        let mut report = |kind, block: &Label, message| {
+            diagnostics.push(Diagnostic {
+                kind,
+                block: block.clone(),
+                inst: None,
+                message,
+            })
+        };
+
+        if !self.blocks.contains_key(&self.entry_label) {
+            report(
+                DiagnosticKind::MissingEntry,
+                &self.entry_label,
+                "entry block does not exist".to_string(),
+            );
+        }
+
+        let mut labels: Vec<&Label> = self.blocks.keys().collect();
+        labels.sort();
+        for label in labels {
+            let block = &self.blocks[label];
+            if &block.label != label {
+                report(
+                    DiagnosticKind::BlockGraphMismatch,
+                    label,
+                    format!(
+                        "block is stored under {label} but is labelled {}",
+                        block.label
+                    ),
+                );
+            }
+            match self.graph.node_weight(block.node) {
+                Some(weight) if weight == label => {}
+                Some(weight) => report(
+                    DiagnosticKind::BlockGraphMismatch,
+                    label,
+                    format!("node {} belongs to {weight}", block.node.index()),
+                ),
+                None => report(
+                    DiagnosticKind::BlockGraphMismatch,
+                    label,
+                    format!("node {} is not in the graph", block.node.index()),
+                ),
+            }
+        }
+
+        let mut listed = HashSet::new();
+        let mut placed = HashSet::new();
+        for &ni in &self.layout {
+            if !self.graph.contains_node(ni) || !placed.insert(ni) {
+                report(
+                    DiagnosticKind::Layout,
+                    &self.entry_label,
+                    format!("node {} is not in the graph or placed twice", ni.index()),
+                );
+            }
+        }
+        if self
+            .layout
+            .first()
+            .and_then(|&ni| self.graph.node_weight(ni))
+            != Some(&self.entry_label)
+        {
+            report(
+                DiagnosticKind::Layout,
+                &self.entry_label,
+                "entry block does not come first".to_string(),
+            );
+        }
+
+        for ni in self.graph.node_indices() {
+            let label = &self.graph[ni];
+            if !placed.contains(&ni) {
+                report(
+                    DiagnosticKind::Layout,
+                    label,
+                    "block is missing from the layout".to_string(),
+                );
+            }
+            let Some(block) = self.blocks.get(label).filter(|block| block.node == ni) else {
+                report(
+                    DiagnosticKind::BlockGraphMismatch,
+                    label,
+                    format!("node {} has no block", ni.index()),
+                );
+                continue;
+            };
+
+            for &id in &block.insts {
+                if !self.insts.contains(id) {
+                    report(
+                        DiagnosticKind::InstParent,
+                        label,
+                        format!("instruction {} was removed", id.index()),
+                    );
+                } else if self.insts.block_of(id) != ni {
+                    report(
+                        DiagnosticKind::InstParent,
+                        label,
+                        format!(
+                            "instruction {} belongs to node {}",
+                            id.index(),
+                            self.insts.block_of(id).index()
+                        ),
+                    );
+                } else if !listed.insert(id) {
+                    report(
+                        DiagnosticKind::InstParent,
+                        label,
+                        format!("instruction {} is listed twice", id.index()),
+                    );
+                }
+            }
+
+            let mut targets: Vec<&Label> = block
+                .insts
+                .iter()
+                .filter(|&&id| self.insts.contains(id))
+                .flat_map(|&id| match &self.insts[id] {
+                    Inst {
+                        op: Op::Effect(_),
+                        labels,
+                        ..
+                    } => labels.iter().collect::<Vec<_>>(),
+                    _ => vec![],
+                })
+                .collect();
+            let mut successors: Vec<&Label> = self
+                .graph
+                .edges_directed(ni, Direction::Outgoing)
+                .map(|edge| &self.graph[edge.target()])
+                .collect();
+            targets.sort();
+            successors.sort();
+
+            for target in &targets {
+                if !self.blocks.contains_key(*target) {
+                    report(
+                        DiagnosticKind::MissingEdge,
+                        label,
+                        format!("jump to {target}, which does not exist"),
+                    );
+                }
+            }
+            if targets != successors {
+                let kind = if targets.len() > successors.len() {
+                    DiagnosticKind::MissingEdge
+                } else {
+                    DiagnosticKind::ExtraEdge
+                };
+                report(
+                    kind,
+                    label,
+                    format!("jumps to {targets:?} but the edges go to {successors:?}"),
+                );
+            }
+        }
+
+        for (id, data) in self.insts.iter() {
+            if !listed.contains(&id) && self.graph.contains_node(data.block) {
+                report(
+                    DiagnosticKind::InstParent,
+                    &self.graph[data.block],
+                    format!("instruction {} is not listed by its block", id.index()),
+                );
+            } else if !listed.contains(&id) {
+                report(
+                    DiagnosticKind::InstParent,
+                    &self.entry_label,
+                    format!("instruction {} belongs to a removed block", id.index()),
+                );
+            }
+        }
+
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(diagnostics)
+        }
+    }
+
+    // Checks that the function is in (strict) SSA form:
+    // - every name is defined exactly once
+    // - every use is dominated by its definition, φ-function arguments at the end of the block they come from
+    // - every φ-function has exactly one argument per predecessor, labelled with that predecessor
+    // - φ-functions only appear at the start of a block
+    // - every block ends in a terminator, and has no terminator anywhere else
+    // Blocks the entry does not reach are not checked. A source variable itself (version 0) that has no
+    // definition stands for its value on entry to the function, like renaming leaves it: reading it is
+    // fine where the variable is undefined on every path to the read, i.e. no definition of any of its
+    // versions reaches it. A variable with no type was never defined at all.
+    pub fn verify_ssa(&self, idoms: &IDoms) -> Result<(), Vec<Diagnostic>> {
+        let mut diagnostics = Vec::new();
+        let mut report = |kind, block: &Label, inst, message| {
+            diagnostics.push(Diagnostic {
+                kind,
+                block: block.clone(),
+                inst,
+                message,
+            })
+        };
+
+        let name = |value: ValueId| self.values.debug_name(value);
+
+        let mut defs: HashMap<ValueId, Def> = HashMap::new();
+        for &arg in &self.args {
+            defs.insert(arg, Def::Argument);
+        }
+        for ni in self.graph.node_indices() {
+            let block = &self.blocks[&self.graph[ni]];
+            for (i, inst) in block.insts.iter().map(|&id| &self.insts[id]).enumerate() {
+                if let Some(dest) = inst.dest
+                    && defs.insert(dest, Def::Inst(ni, i)).is_some()
+                {
+                    report(
+                        DiagnosticKind::MultipleDefinitions,
+                        &block.label,
+                        Some(i),
+                        format!("{} is defined more than once", name(dest)),
+                    );
+                }
+            }
+        }
+
+        // Along the edge a guard aborts along, the variables have the values they had where the speculations
+        // that reach the guard started
+        let speculation_starts: HashMap<(NodeIndex, NodeIndex), Vec<(NodeIndex, usize)>> = self
+            .guard_regions()
+            .into_iter()
+            .map(|(guard, region, target)| {
+                let starts = region
+                    .into_iter()
+                    .filter_map(|block| {
+                        let index = self.block(block).insts.iter().rposition(|&id| {
+                            matches!(self.insts[id].op, Op::Effect(EffectOps::Speculate))
+                        })?;
+                        Some((block, index))
+                    })
+                    .collect();
+                ((guard, target), starts)
+            })
+            .collect();
+
+        // Where the versions of the variables with an undefined entry value are defined, and the blocks those
+        // definitions reach through at least one edge. What reaches a guard in a speculation that defines the
+        // variable doesn't reach the block it aborts to, what reached the start of the speculation does.
+        let mut def_sites: HashMap<ValueId, Vec<(NodeIndex, usize)>> = HashMap::new();
+        for (&value, &def) in &defs {
+            let origin = self.values.origin(value);
+            if let Def::Inst(block, index) = def
+                && !defs.contains_key(&origin)
+            {
+                def_sites.entry(origin).or_default().push((block, index));
+            }
+        }
+        let reached: HashMap<ValueId, HashSet<NodeIndex>> = def_sites
+            .iter()
+            .map(|(&var, sites)| {
+                let defined_before = |at: NodeIndex, index: Option<usize>| {
+                    sites.iter().any(|&(block, def_index)| {
+                        block == at && index.is_none_or(|index| def_index < index)
+                    })
+                };
+                // Whether a definition reaches the end of the edge from `pred` to `block`
+                let carries = |pred: NodeIndex, block: NodeIndex, reached: &HashSet<NodeIndex>| {
+                    match speculation_starts.get(&(pred, block)) {
+                        Some(starts) if !starts.is_empty() => {
+                            starts.iter().any(|&(start, index)| {
+                                defined_before(start, Some(index)) || reached.contains(&start)
+                            })
+                        }
+                        _ => defined_before(pred, None) || reached.contains(&pred),
+                    }
+                };
+                let mut reached = HashSet::new();
+                let mut changed = true;
+                while changed {
+                    changed = false;
+                    for block in self.graph.node_indices() {
+                        if !reached.contains(&block)
+                            && self
+                                .predecessors(block)
+                                .into_iter()
+                                .any(|pred| carries(pred, block, &reached))
+                        {
+                            reached.insert(block);
+                            changed = true;
+                        }
+                    }
+                }
+                (var, reached)
+            })
+            .collect();
+        let undefined_on_every_path = |var: ValueId, at: NodeIndex, index: Option<usize>| {
+            let defined_before = def_sites.get(&var).is_some_and(|sites| {
+                sites.iter().any(|&(block, def_index)| {
+                    block == at && index.is_none_or(|index| def_index < index)
+                })
+            });
+            !defined_before && !reached.get(&var).is_some_and(|blocks| blocks.contains(&at))
+        };
+
+        // Whether the definition of `arg` is available at the end of block `at` (`index` None),
+        // or right before instruction `index` of it
+        let available = |arg: ValueId, at: NodeIndex, index: Option<usize>| match defs.get(&arg) {
+            None if self.values[arg].version == 0 && self.values[arg].ty.is_some() => {
+                Some(undefined_on_every_path(arg, at, index))
+            }
+            None => None,
+            Some(Def::Argument) => Some(true),
+            Some(&Def::Inst(def_block, def_index)) if def_block == at => {
+                Some(index.is_none_or(|index| def_index < index))
+            }
+            Some(&Def::Inst(def_block, _)) => Some(dominates(idoms, def_block, at)),
+        };
+
+        let reachable = self.reachable();
+        for ni in self.graph.node_indices() {
+            // Nothing dominates unreachable code
+            if !reachable.contains(&ni) {
+                continue;
+            }
+            let block = &self.blocks[&self.graph[ni]];
+            let mut seen_non_phi = false;
+
+            for (i, inst) in block.insts.iter().map(|&id| &self.insts[id]).enumerate() {
+                match inst {
+                    Inst {
+                        op: Op::Value(ValueOps::Phi),
+                        dest,
+                        args,
+                        labels,
+                        ..
+                    } => {
+                        let dest = name(dest.unwrap());
+                        if seen_non_phi {
+                            report(
+                                DiagnosticKind::PhiNotAtStart,
+                                &block.label,
+                                Some(i),
+                                format!("φ-function for {dest} follows other instructions"),
+                            );
+                        }
+
+                        let mut preds: Vec<&Label> = self
+                            .graph
+                            .edges_directed(ni, Direction::Incoming)
+                            .map(|edge| &self.graph[edge.source()])
+                            .collect();
+                        let mut phi_labels: Vec<&Label> = labels.iter().collect();
+                        preds.sort();
+                        phi_labels.sort();
+                        if args.len() != labels.len() || preds != phi_labels {
+                            report(
+                                DiagnosticKind::PhiArguments,
+                                &block.label,
+                                Some(i),
+                                format!(
+                                    "φ-function for {dest} has arguments from {phi_labels:?} but the predecessors are {preds:?}"
+                                ),
+                            );
+                            continue;
+                        }
+
+                        for (&arg, label) in args.iter().zip(labels) {
+                            let pred = self.blocks[label].node;
+                            // An argument without a definition stands for a variable that is undefined along this edge
+                            let message = match speculation_starts.get(&(pred, ni)) {
+                                Some(starts) if !starts.is_empty() => {
+                                    let missing = starts.iter().any(|&(start, index)| {
+                                        available(arg, start, Some(index)) == Some(false)
+                                    });
+                                    missing.then(|| {
+                                        format!(
+                                            "{} (from {label}) is not available where the speculation {label} aborts started",
+                                            name(arg)
+                                        )
+                                    })
+                                }
+                                _ => (available(arg, pred, None) == Some(false)).then(|| {
+                                    format!(
+                                        "{} (from {label}) is not available at the end of {label}",
+                                        name(arg)
+                                    )
+                                }),
+                            };
+                            if let Some(message) = message {
+                                report(
+                                    DiagnosticKind::UseNotDominated,
+                                    &block.label,
+                                    Some(i),
+                                    message,
+                                );
+                            }
+                        }
+                    }
+                    Inst { args, .. } => {
+                        seen_non_phi = true;
+                        for &arg in args {
+                            match available(arg, ni, Some(i)) {
+                                None => report(
+                                    DiagnosticKind::UndefinedUse,
+                                    &block.label,
+                                    Some(i),
+                                    format!("{} is never defined", name(arg)),
+                                ),
+                                Some(false) if !defs.contains_key(&arg) => report(
+                                    DiagnosticKind::UseNotDominated,
+                                    &block.label,
+                                    Some(i),
+                                    format!(
+                                        "{} is used undefined where a definition of it reaches",
+                                        name(arg)
+                                    ),
+                                ),
+                                Some(false) => report(
+                                    DiagnosticKind::UseNotDominated,
+                                    &block.label,
+                                    Some(i),
+                                    format!(
+                                        "{} is used where its definition does not dominate",
+                                        name(arg)
+                                    ),
+                                ),
+                                Some(true) => {}
+                            }
+                        }
+                    }
+                }
+
+                if inst.is_terminator() && i + 1 != block.insts.len() {
+                    report(
+                        DiagnosticKind::Terminator,
+                        &block.label,
+                        Some(i),
+                        "terminator in the middle of the block".to_string(),
+                    );
+                }
+            }
+
+            if block.terminator(&self.insts).is_none() {
+                report(
+                    DiagnosticKind::Terminator,
+                    &block.label,
+                    None,
+                    "block does not end in a terminator".to_string(),
+                );
+            }
+        }
+
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(diagnostics)
+        }
+    }
+
+    // Checks that the function is in loop-closed SSA form: a value defined in a loop is only used outside of it
+    // by φ-functions, with the argument coming from a block of the loop
+    pub fn verify_lcssa(&self, loops: &Loops) -> Result<(), Vec<Diagnostic>> {
+        let mut diagnostics = Vec::new();
+        for l in &loops.loops {
+            for (id, value) in self.loop_escapes(l) {
+                let block = self.block(self.insts.block_of(id));
+                diagnostics.push(Diagnostic {
+                    kind: DiagnosticKind::NotLoopClosed,
+                    block: block.label.clone(),
+                    inst: block.insts.iter().position(|&other| other == id),
+                    message: format!(
+                        "{} is defined in the loop at {} and used outside of it",
+                        self.values.debug_name(value),
+                        self.label(l.header)
+                    ),
+                });
+            }
+        }
+
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(diagnostics)
+        }
+    }
+
+    // Checks that every loop has a preheader, a single latch and dedicated exits
+    pub fn verify_loop_form(&self, loops: &Loops) -> Result<(), Vec<Diagnostic>> {
+        let diagnostics: Vec<Diagnostic> = loops
+            .loops
+            .iter()
+            .filter_map(|l| {
+                let issue = self.loop_form_issue(l)?;
+                Some(Diagnostic {
+                    kind: DiagnosticKind::NotCanonicalLoop,
+                    block: self.label(issue.block).clone(),
+                    inst: None,
+                    message: format!("loop at {}: {}", self.label(l.header), issue.problem),
+                })
+            })
+            .collect();
+
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(diagnostics)
+        }
+    }
+
+    // Checks that two SSA forms of the same function (e.g. from different construction algorithms)
+    // agree on which definitions reach every use. φ-functions may differ, as long as looking through
+    // them leads to the same definitions.
+    pub fn verify_equivalent_ssa(&self, other: &Cfg) -> Result<(), Vec<Diagnostic>> {
+        let mut diagnostics = Vec::new();
+        let positions = self.non_phi_positions();
+        let other_positions = other.non_phi_positions();
+
+        for block in self.blocks_in_order() {
+            let Some(other_block) = other.blocks.get(&block.label) else {
+                diagnostics.push(Diagnostic {
+                    kind: DiagnosticKind::NotEquivalent,
+                    block: block.label.clone(),
+                    inst: None,
+                    message: "block is missing from the other function".to_string(),
+                });
+                continue;
+            };
+
+            let insts: Vec<_> = block
+                .insts
+                .iter()
+                .filter(|&&id| !self.insts[id].is_phi())
+                .collect();
+            let other_insts: Vec<_> = other_block
+                .insts
+                .iter()
+                .filter(|&&id| !other.insts[id].is_phi())
+                .collect();
+            if insts.len() != other_insts.len() {
+                diagnostics.push(Diagnostic {
+                    kind: DiagnosticKind::NotEquivalent,
+                    block: block.label.clone(),
+                    inst: None,
+                    message: format!(
+                        "{} instructions here, {} in the other function",
+                        insts.len(),
+                        other_insts.len()
+                    ),
+                });
+                continue;
+            }
+
+            for (i, (&id, &other_id)) in insts.into_iter().zip(other_insts).enumerate() {
+                let args = &self.insts[id].args;
+                let other_args = &other.insts[other_id].args;
+                for (j, (&arg, &other_arg)) in args.iter().zip(other_args).enumerate() {
+                    let sources = self.sources(arg, &positions);
+                    let other_sources = other.sources(other_arg, &other_positions);
+                    if sources != other_sources {
+                        diagnostics.push(Diagnostic {
+                            kind: DiagnosticKind::NotEquivalent,
+                            block: block.label.clone(),
+                            inst: Some(i),
+                            message: format!(
+                                "argument {j} comes from {sources:?} here, but from {other_sources:?} in the other function"
+                            ),
+                        });
+                    }
+                }
+            }
+        }
+
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(diagnostics)
+        }
+    }
+
+    fn non_phi_positions(&self) -> HashMap<InstId, (Label, usize)> {
+        let mut positions = HashMap::new();
+        for block in self.blocks_in_order() {
+            let insts = block.insts.iter().filter(|&&id| !self.insts[id].is_phi());
+            for (i, &id) in insts.enumerate() {
+                positions.insert(id, (block.label.clone(), i));
+            }
+        }
+        positions
+    }
+
+    fn sources(
+        &self,
+        value: ValueId,
+        positions: &HashMap<InstId, (Label, usize)>,
+    ) -> BTreeSet<Source> {
+        let mut sources = BTreeSet::new();
+        let mut seen = HashSet::new();
+        let mut stack = vec![value];
+        while let Some(value) = stack.pop() {
+            if !seen.insert(value) {
+                continue;
+            }
+            match self.insts.def(value) {
+                None => {
+                    let origin = self.values.origin(value);
+                    sources.insert(Source::Entry(self.values[origin].name.clone()));
+                }
+                Some(def) if self.insts[def].is_phi() => stack.extend(&self.insts[def].args),
+                Some(def) => {
+                    let (label, index) = positions[&def].clone();
+                    sources.insert(Source::Inst(label, index));
+                }
+            }
+        }
+        sources
+    }
+}
@@ -0,0 +1,87 @@
+use bril_rs::ValueOps;
+use ssa::cfg::CfgBuilder;
+use ssa::difftest::{check, run};
+use ssa::inst::Op;
+use ssa::passes::PassManager;
+use ssa::text::parse_program;
+
+// @mk hands the memory it allocates and stores to back to main, which reads it
+const RETURNED: &str = "
+@mk: ptr<int> {
+  one: int = const 1;
+  p: ptr<int> = alloc one;
+  v: int = const 42;
+  store p v;
+  ret p;
+}
+@main {
+  p: ptr<int> = call @mk;
+  v: int = load p;
+  print v;
+  free p;
+}
+";
+
+#[test]
+fn returned_pointers_escape() {
+    let program = parse_program(RETURNED).unwrap();
+    let mut cfg = CfgBuilder::new().build(&program.functions[0]);
+    PassManager::parse("to-ssa")
+        .unwrap()
+        .run(&mut cfg, |_, _, _| {})
+        .unwrap();
+    let p = cfg
+        .insts
+        .iter()
+        .find(|(_, data)| matches!(data.inst.op, Op::Value(ValueOps::Alloc)))
+        .and_then(|(_, data)| data.inst.dest)
+        .unwrap();
+    assert!(cfg.alias_analysis().may_escape(p));
+}
+
+#[test]
+fn stores_to_returned_memory_stay() {
+    let program = parse_program(RETURNED).unwrap();
+    let original = run(&program, &[]);
+    assert_eq!(original, Ok(("42\n".to_string(), None)));
+    let mut pm = PassManager::parse("to-ssa,mem-opt,from-ssa").unwrap();
+    pm.verify_each = true;
+    assert_eq!(check(&program, &[], &pm, &original), None);
+}
+
+// `r` is `p` moved by 2 * i64::MAX, which wraps around to the same address as `s`
+#[test]
+fn overflowing_offsets_are_unknown() {
+    let program = parse_program(
+        "
+@main {
+  two: int = const 2;
+  p: ptr<int> = alloc two;
+  max: int = const 9223372036854775807;
+  q: ptr<int> = ptradd p max;
+  r: ptr<int> = ptradd q max;
+  minus_two: int = const -2;
+  s: ptr<int> = ptradd p minus_two;
+  free p;
+}
+",
+    )
+    .unwrap();
+    let mut cfg = CfgBuilder::new().build(&program.functions[0]);
+    PassManager::parse("to-ssa")
+        .unwrap()
+        .run(&mut cfg, |_, _, _| {})
+        .unwrap();
+    let ptradds: Vec<_> = cfg
+        .blocks_in_order()
+        .flat_map(|block| &block.insts)
+        .filter(|&&id| matches!(cfg.insts[id].op, Op::Value(ValueOps::PtrAdd)))
+        .map(|&id| cfg.insts[id].dest.unwrap())
+        .collect();
+    let [q, r, s] = ptradds[..] else {
+        panic!("{ptradds:?}");
+    };
+    let aliases = cfg.alias_analysis();
+    assert!(aliases.may_alias(r, s));
+    assert!(!aliases.must_alias(q, r));
+}
@@ -0,0 +1,107 @@
+use ssa::cfg::{Cfg, CfgBuilder};
+use ssa::interp::Interpreter;
+use ssa::passes::PassManager;
+use ssa::text::parse_program;
+
+const CALLS_IN_A_LOOP: &str = "
+@main {
+  i: int = const 0;
+  one: int = const 1;
+  ten: int = const 10;
+.loop:
+  odd: bool = call @odd i;
+  br odd .skip .show;
+.show:
+  print i;
+.skip:
+  i: int = add i one;
+  more: bool = lt i ten;
+  br more .loop .exit;
+.exit:
+  print i;
+}
+
+@odd(n: int): bool {
+  two: int = const 2;
+  half: int = div n two;
+  back: int = mul half two;
+  even: bool = eq n back;
+  odd: bool = not even;
+  br odd .yes .no;
+.yes:
+  ret odd;
+.no:
+  ret odd;
+}
+";
+
+fn build(text: &str) -> Vec<Cfg> {
+    let program = parse_program(text).unwrap();
+    program
+        .functions
+        .iter()
+        .map(|func| CfgBuilder::new().build(func))
+        .collect()
+}
+
+fn instrumented_output(cfgs: &[Cfg], pipeline: &str) -> String {
+    let pm = PassManager::parse(pipeline).unwrap();
+    let mut instrumented = cfgs.to_vec();
+    for cfg in &mut instrumented {
+        pm.run(cfg, |_, _, _| {}).unwrap();
+    }
+    Interpreter::new(&instrumented)
+        .run("main", vec![])
+        .unwrap()
+        .output
+}
+
+// The counts an instrumented run prints, read back into the functions, are the ones the interpreter sees
+// running the functions themselves
+#[test]
+fn counts_read_back_from_an_instrumented_run() {
+    let expected = Interpreter::new(&build(CALLS_IN_A_LOOP))
+        .run("main", vec![])
+        .unwrap()
+        .profile;
+
+    for pipeline in ["instrument=blocks", "instrument=edges"] {
+        let mut cfgs = build(CALLS_IN_A_LOOP);
+        let output = instrumented_output(&cfgs, pipeline);
+        for cfg in &mut cfgs {
+            assert_eq!(cfg.read_counts(&output), Ok(true), "{pipeline}");
+            let profile = &expected[&cfg.name];
+            for from in cfg.layout.clone() {
+                for to in cfg.successors(from) {
+                    let edge = (cfg.label(from).clone(), cfg.label(to).clone());
+                    let count = profile.edges.get(&edge).copied().unwrap_or(0);
+                    assert_eq!(cfg.edge_count(from, to), Some(count), "{pipeline}: {edge:?}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn negative_counts_are_rejected() {
+    let mut cfgs = build(CALLS_IN_A_LOOP);
+    let output = instrumented_output(&cfgs, "instrument=blocks");
+    let line = output.lines().last().unwrap();
+    let mut values: Vec<&str> = line.split_whitespace().collect();
+    *values.last_mut().unwrap() = "-1";
+    let err = cfgs[0].read_counts(&values.join(" ")).unwrap_err();
+    assert!(err.starts_with("negative count"), "{err}");
+}
+
+#[test]
+fn counts_adding_up_past_the_largest_are_rejected() {
+    let mut cfgs = build(CALLS_IN_A_LOOP);
+    let output = instrumented_output(&cfgs, "instrument=blocks");
+    let line = output.lines().last().unwrap();
+    let mut values: Vec<&str> = line.split_whitespace().collect();
+    let max = i64::MAX.to_string();
+    *values.last_mut().unwrap() = &max;
+    let line = values.join(" ");
+    let err = cfgs[0].read_counts(&format!("{line}\n{line}\n{line}")).unwrap_err();
+    assert!(err.starts_with("counts add up"), "{err}");
+}
@@ -0,0 +1,45 @@
+use ssa::cfg::CfgBuilder;
+use ssa::passes::{Analysis, AnalysisManager};
+use ssa::text::parse_program;
+
+const COUNTDOWN: &str = "
+@main(n: int) {
+  one: int = const 1;
+  zero: int = const 0;
+.loop:
+  done: bool = le n zero;
+  br done .exit .body;
+.body:
+  n: int = sub n one;
+  jmp .loop;
+.exit:
+  print zero;
+}
+";
+
+#[test]
+fn loop_variables_live_around_the_loop() {
+    let program = parse_program(COUNTDOWN).unwrap();
+    let cfg = CfgBuilder::new().build(&program.functions[0]);
+    let var = |name: &str| {
+        cfg.values
+            .iter()
+            .find(|&(value, _)| cfg.values.debug_name(value) == name)
+            .unwrap()
+            .0
+    };
+    let (n, one, zero) = (var("n"), var("one"), var("zero"));
+    let body = cfg.block_id("body");
+    let exit = cfg.block_id("exit");
+
+    let mut am = AnalysisManager::default();
+    let liveness = am.liveness(&cfg);
+    assert!(liveness.is_live_out(body, n));
+    assert!(liveness.is_live_out(body, one));
+    assert!(liveness.is_live_in(exit, zero));
+    assert!(!liveness.is_live_in(exit, n));
+
+    // Passes that keep every analysis keep liveness as well
+    am.invalidate(&[Analysis::Liveness]);
+    assert!(am.liveness(&cfg).is_live_out(body, n));
+}
@@ -0,0 +1,112 @@
+use bril_rs::Program;
+use ssa::cfg::CfgBuilder;
+use ssa::difftest::{check, run};
+use ssa::passes::PassManager;
+use ssa::text::parse_program;
+
+// Runs the passes over every function, verifying after each of them, and checks that the program still
+// prints the same
+fn check_passes(program: &Program, args: &[&str], passes: &str) {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    let mut pm = PassManager::parse(passes).unwrap();
+    pm.verify_each = true;
+    for func in &program.functions {
+        let mut cfg = CfgBuilder::new().build(func);
+        if let Err(err) = pm.run(&mut cfg, |_, _, _| {}) {
+            panic!("@{}: {err}", func.name);
+        }
+    }
+    let original = run(program, &args);
+    assert!(original.is_ok(), "{original:?}");
+    assert_eq!(check(program, &args, &pm, &original), None);
+}
+
+// A loop header with φ-functions and two predecessors outside of the loop, which get a preheader of
+// their own with the φ-functions that merge what they bring in
+#[test]
+fn preheader_for_several_entries() {
+    let program = parse_program(
+        "
+@main(c: bool) {
+  one: int = const 1;
+  ten: int = const 10;
+  i: int = const 0;
+  x: int = const 5;
+  br c .a .b;
+.a:
+  i: int = const 1;
+  jmp .header;
+.b:
+  x: int = const 7;
+  jmp .header;
+.header:
+  x: int = add x one;
+  i: int = add i one;
+  more: bool = lt i ten;
+  br more .header .exit;
+.exit:
+  print i;
+  print x;
+}
+",
+    )
+    .unwrap();
+    for c in ["true", "false"] {
+        check_passes(&program, &[c], "to-ssa,canonicalize-loops");
+        check_passes(
+            &program,
+            &[c],
+            "to-ssa,canonicalize-loops,unroll,lcssa,from-ssa",
+        );
+    }
+}
+
+// Counting up to a bound close to the largest integer, where looking `factor - 1` iterations ahead
+// overflows past it, with the bound known at compile time or not
+#[test]
+fn unrolled_bound_near_the_largest_integer() {
+    let start = (i64::MAX - 10).to_string();
+    let max = i64::MAX.to_string();
+    let argument = parse_program(
+        "
+@main(start: int, n: int) {
+  one: int = const 1;
+  i: int = id start;
+.loop:
+  i: int = add i one;
+  more: bool = lt i n;
+  br more .loop .exit;
+.exit:
+  print i;
+}
+",
+    )
+    .unwrap();
+    check_passes(
+        &argument,
+        &[&start, &max],
+        "to-ssa,canonicalize-loops,unroll,lcssa,from-ssa",
+    );
+
+    let constant = parse_program(
+        "
+@main(start: int) {
+  one: int = const 1;
+  n: int = const 9223372036854775807;
+  i: int = id start;
+.loop:
+  i: int = add i one;
+  done: bool = ge i n;
+  br done .exit .loop;
+.exit:
+  print i;
+}
+",
+    )
+    .unwrap();
+    check_passes(
+        &constant,
+        &[&start],
+        "to-ssa,canonicalize-loops,unroll,lcssa,from-ssa",
+    );
+}
@@ -0,0 +1,54 @@
+use std::fs;
+use std::path::Path;
+
+use ssa::difftest::{test_directory, PIPELINES};
+use ssa::text::{parse_program, program_to_text};
+
+// The programs in tests/programs are in text form, each with what it prints next to it in NAME.out
+const PROGRAMS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs");
+
+#[test]
+fn every_program_has_its_output() {
+    for entry in fs::read_dir(PROGRAMS).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "bril") {
+            assert!(
+                Path::new(&path.with_extension("out")).exists(),
+                "{} has no .out file",
+                path.display()
+            );
+        }
+    }
+}
+
+#[test]
+fn pipelines_keep_what_programs_do() {
+    let (report, failures) = test_directory(PROGRAMS, PIPELINES).unwrap();
+    assert_eq!(failures, 0, "\n{report}");
+}
+
+// Out of SSA form and back in: the variables are versions of the ones of the first construction by then
+#[test]
+fn ssa_round_trips_twice() {
+    let (report, failures) = test_directory(
+        PROGRAMS,
+        &[
+            "to-ssa,from-ssa,to-ssa,from-ssa",
+            "to-ssa-braun,from-ssa,to-ssa-braun,from-ssa",
+        ],
+    )
+    .unwrap();
+    assert_eq!(failures, 0, "\n{report}");
+}
+
+#[test]
+fn every_program_reads_back_from_its_text() {
+    for entry in fs::read_dir(PROGRAMS).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "bril") {
+            let program = parse_program(&fs::read_to_string(&path).unwrap()).unwrap();
+            let text = program_to_text(&program);
+            assert_eq!(parse_program(&text), Ok(program), "{}", path.display());
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::fs;
+
+use bril_rs::Program;
+use ssa::braun::compare_ssa_construction;
+use ssa::cfg::CfgBuilder;
+use ssa::difftest::run;
+use ssa::passes::PassManager;
+use ssa::utils::read_program;
+
+const PROGRAMS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs");
+
+// Both constructions over every program: the same definitions reach every use, and the programs
+// they build print and return the same
+#[test]
+fn constructions_agree() {
+    for entry in fs::read_dir(PROGRAMS).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "bril") {
+            continue;
+        }
+        let name = path.display();
+        let program = read_program(path.to_str()).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let args: Vec<String> = text
+            .lines()
+            .find_map(|line| line.split_once("ARGS:"))
+            .map(|(_, args)| args.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default();
+
+        for func in &program.functions {
+            if let Err(diagnostics) = compare_ssa_construction(func) {
+                panic!("{name} @{}: {diagnostics:?}", func.name);
+            }
+        }
+        let cytron = run(&in_ssa(&program, "to-ssa"), &args);
+        let braun = run(&in_ssa(&program, "to-ssa-braun"), &args);
+        assert!(cytron.is_ok(), "{name}: {cytron:?}");
+        assert_eq!(cytron, braun, "{name}");
+    }
+}
+
+fn in_ssa(program: &Program, pipeline: &str) -> Program {
+    let mut pm = PassManager::parse(pipeline).unwrap();
+    pm.verify_each = true;
+    let functions = program
+        .functions
+        .iter()
+        .map(|func| {
+            let mut cfg = CfgBuilder::new().build(func);
+            if let Err(err) = pm.run(&mut cfg, |_, _, _| {}) {
+                panic!("@{} after {pipeline}: {err}", cfg.name);
+            }
+            cfg.to_function()
+        })
+        .collect();
+    Program {
+        functions,
+        ..program.clone()
+    }
+}
@@ -0,0 +1,205 @@
+use ssa::cfg::{Cfg, CfgBuilder, CfgEdge};
+use ssa::inst::InstId;
+use ssa::passes::PassManager;
+use ssa::text::parse_program;
+use ssa::verify::{Diagnostic, DiagnosticKind};
+
+// Each test breaks a function that verifies in one particular way, and checks that the verifier says so
+
+const BRANCH: &str = "
+@main(c: bool) {
+  x: int = const 1;
+  br c .then .join;
+.then:
+  x: int = const 2;
+  jmp .join;
+.join:
+  print x;
+}
+";
+
+// `x` is only defined on the way through .then
+const MAYBE_UNDEFINED: &str = "
+@main(c: bool) {
+  br c .then .join;
+.then:
+  x: int = const 2;
+  jmp .join;
+.join:
+  print x;
+}
+";
+
+// `x` is only defined in a speculation, which the guard may abort
+const ABORTED: &str = "
+@main(c: bool) {
+  speculate;
+  x: int = const 1;
+  guard c .join;
+  commit;
+.join:
+  print x;
+}
+";
+
+const LOOP: &str = "
+@main(n: int) {
+  i: int = const 0;
+  one: int = const 1;
+.loop:
+  i: int = add i one;
+  done: bool = ge i n;
+  br done .exit .loop;
+.exit:
+  print i;
+}
+";
+
+// The loop is entered from both .a and .b, so it has no preheader
+const TWO_ENTRIES: &str = "
+@main(c: bool) {
+  i: int = const 1;
+  br c .a .b;
+.a:
+  jmp .loop;
+.b:
+  jmp .loop;
+.loop:
+  i: int = add i i;
+  br c .loop .exit;
+.exit:
+  print i;
+}
+";
+
+fn build(text: &str, pipeline: &str) -> Cfg {
+    let program = parse_program(text).unwrap();
+    let mut cfg = CfgBuilder::new().build(&program.functions[0]);
+    PassManager::parse(pipeline)
+        .unwrap()
+        .run(&mut cfg, |_, _, _| {})
+        .unwrap();
+    cfg
+}
+
+fn inst(cfg: &Cfg, label: &str, index: usize) -> InstId {
+    cfg.block(cfg.block_id(label)).insts[index]
+}
+
+fn kinds(result: Result<(), Vec<Diagnostic>>) -> Vec<DiagnosticKind> {
+    result
+        .unwrap_err()
+        .into_iter()
+        .map(|diagnostic| diagnostic.kind)
+        .collect()
+}
+
+fn verify_ssa(cfg: &Cfg) -> Result<(), Vec<Diagnostic>> {
+    cfg.verify_ssa(&cfg.get_idoms())
+}
+
+#[test]
+fn edge_without_a_jump() {
+    let mut cfg = build(BRANCH, "");
+    let (join, then) = (cfg.block_id("join"), cfg.block_id("then"));
+    cfg.graph.add_edge(join, then, CfgEdge::default());
+    assert_eq!(kinds(cfg.verify()), [DiagnosticKind::ExtraEdge]);
+}
+
+#[test]
+fn jump_without_an_edge() {
+    let mut cfg = build(BRANCH, "");
+    let (then, join) = (cfg.block_id("then"), cfg.block_id("join"));
+    let edge = cfg.graph.find_edge(then, join).unwrap();
+    cfg.graph.remove_edge(edge);
+    assert!(kinds(cfg.verify()).contains(&DiagnosticKind::MissingEdge));
+}
+
+#[test]
+fn defined_twice() {
+    let mut cfg = build(BRANCH, "to-ssa");
+    assert_eq!(verify_ssa(&cfg), Ok(()));
+    let first = cfg.insts[inst(&cfg, &cfg.entry_label, 0)].dest;
+    cfg.insts.update(inst(&cfg, "then", 0), |inst| inst.dest = first);
+    assert!(kinds(verify_ssa(&cfg)).contains(&DiagnosticKind::MultipleDefinitions));
+}
+
+#[test]
+fn never_defined() {
+    let mut cfg = build(BRANCH, "to-ssa");
+    let nowhere = cfg.values.variable("nowhere");
+    cfg.insts.update(inst(&cfg, "join", 1), |inst| inst.args = vec![nowhere]);
+    assert_eq!(kinds(verify_ssa(&cfg)), [DiagnosticKind::UndefinedUse]);
+}
+
+// Renaming leaves `x` itself only where no definition of it can reach, anywhere else the value on entry is
+// the wrong one
+#[test]
+fn undefined_where_a_definition_reaches() {
+    let mut cfg = build(BRANCH, "to-ssa");
+    let print = inst(&cfg, "join", 1);
+    let x = cfg.values.origin(cfg.insts[print].args[0]);
+    cfg.insts.update(print, |inst| inst.args = vec![x]);
+    assert_eq!(kinds(verify_ssa(&cfg)), [DiagnosticKind::UseNotDominated]);
+}
+
+#[test]
+fn undefined_on_some_paths() {
+    let mut cfg = build(MAYBE_UNDEFINED, "to-ssa");
+    assert_eq!(verify_ssa(&cfg), Ok(()));
+
+    // The φ-function may get `x` on entry from the entry block, but not from .then, where it was just
+    // defined
+    let phi = inst(&cfg, "join", 0);
+    let x = cfg.values.origin(cfg.insts[phi].args[0]);
+    cfg.insts.update(phi, |inst| inst.args = vec![x; 2]);
+    assert_eq!(kinds(verify_ssa(&cfg)), [DiagnosticKind::UseNotDominated]);
+}
+
+// Along the edge the guard aborts along, `x` is what it was before the speculation: undefined
+#[test]
+fn undefined_where_a_guard_aborts() {
+    for pipeline in ["to-ssa", "to-ssa-braun"] {
+        let mut cfg = build(ABORTED, pipeline);
+        assert_eq!(verify_ssa(&cfg), Ok(()), "{pipeline}");
+
+        // The value the speculation defines is the wrong one
+        let phi = inst(&cfg, "join", 0);
+        let defined = cfg.insts[inst(&cfg, &cfg.entry_label, 1)].dest.unwrap();
+        cfg.insts.update(phi, |inst| {
+            let i = inst.labels.iter().position(|label| *label == cfg.entry_label).unwrap();
+            inst.args[i] = defined;
+        });
+        assert_eq!(kinds(verify_ssa(&cfg)), [DiagnosticKind::UseNotDominated], "{pipeline}");
+    }
+}
+
+#[test]
+fn phi_missing_a_predecessor() {
+    let mut cfg = build(BRANCH, "to-ssa");
+    cfg.insts.update(inst(&cfg, "join", 0), |inst| {
+        inst.args.pop();
+        inst.labels.pop();
+    });
+    assert_eq!(kinds(verify_ssa(&cfg)), [DiagnosticKind::PhiArguments]);
+}
+
+#[test]
+fn used_outside_the_loop() {
+    let cfg = build(LOOP, "to-ssa");
+    let loops = cfg.find_loops(&cfg.get_idoms());
+    assert_eq!(kinds(cfg.verify_lcssa(&loops)), [DiagnosticKind::NotLoopClosed]);
+
+    let cfg = build(LOOP, "to-ssa,lcssa");
+    assert_eq!(cfg.verify_lcssa(&cfg.find_loops(&cfg.get_idoms())), Ok(()));
+}
+
+#[test]
+fn loop_without_a_preheader() {
+    let cfg = build(TWO_ENTRIES, "");
+    let loops = cfg.find_loops(&cfg.get_idoms());
+    assert_eq!(kinds(cfg.verify_loop_form(&loops)), [DiagnosticKind::NotCanonicalLoop]);
+
+    let cfg = build(TWO_ENTRIES, "canonicalize-loops");
+    assert_eq!(cfg.verify_loop_form(&cfg.find_loops(&cfg.get_idoms())), Ok(()));
+}